@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE refresh_tokens (
+  id          SERIAL PRIMARY KEY,
+  key         VARCHAR(255) NOT NULL UNIQUE,
+  session_id  INTEGER      NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+  family      VARCHAR(255) NOT NULL,
+  used        BOOLEAN      NOT NULL DEFAULT false,
+  valid       BOOLEAN      NOT NULL DEFAULT true,
+  created_at  TIMESTAMP    NOT NULL DEFAULT NOW(),
+  expires_at  TIMESTAMP    NOT NULL
+);
+
+CREATE INDEX ix_refresh_tokens_key ON refresh_tokens (key);
+CREATE INDEX ix_refresh_tokens_family ON refresh_tokens (family);
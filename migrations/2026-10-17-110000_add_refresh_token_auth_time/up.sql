@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE refresh_tokens ADD COLUMN auth_time TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE clients DROP COLUMN userinfo_signed;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN userinfo_signed BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN jwt_access_tokens BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE consents (
+  id          SERIAL PRIMARY KEY,
+  user_id     INTEGER      NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  client_id   INTEGER      NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+  scope       TEXT         NOT NULL DEFAULT '',
+  created_at  TIMESTAMP    NOT NULL DEFAULT NOW(),
+  UNIQUE (user_id, client_id)
+);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE scopes (
+  id          SERIAL PRIMARY KEY,
+  name        VARCHAR(255) NOT NULL UNIQUE,
+  description VARCHAR(255) NOT NULL
+);
+
+INSERT INTO scopes (name, description) VALUES
+  ('openid', 'Know who you are'),
+  ('profile', 'See your username and full name'),
+  ('email', 'See your email address'),
+  ('roles', 'See which roles you have'),
+  ('offline_access', 'Stay connected when you are not using it');
+
+ALTER TABLE clients ADD COLUMN allowed_scopes TEXT NOT NULL
+  DEFAULT 'openid profile email roles offline_access';
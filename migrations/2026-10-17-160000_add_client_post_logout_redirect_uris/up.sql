@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN post_logout_redirect_uri_list TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sessions DROP COLUMN sid;
+ALTER TABLE clients DROP COLUMN backchannel_logout_uri;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN backchannel_logout_uri TEXT NOT NULL DEFAULT '';
+ALTER TABLE sessions ADD COLUMN sid VARCHAR(255);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN token_exchange_audiences TEXT NOT NULL DEFAULT '';
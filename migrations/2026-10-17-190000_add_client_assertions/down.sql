@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE used_assertions;
+
+ALTER TABLE clients DROP COLUMN jwks_uri;
+ALTER TABLE clients DROP COLUMN jwks;
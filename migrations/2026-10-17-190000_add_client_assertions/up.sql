@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN jwks TEXT NOT NULL DEFAULT '';
+ALTER TABLE clients ADD COLUMN jwks_uri TEXT NOT NULL DEFAULT '';
+
+CREATE TABLE used_assertions (
+  id          SERIAL PRIMARY KEY,
+  client_id   INTEGER NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+  jti         TEXT NOT NULL,
+  expires_at  TIMESTAMP NOT NULL,
+  UNIQUE (client_id, jti)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE clients DROP COLUMN registration_access_token;
+
+DROP TABLE initial_access_tokens;
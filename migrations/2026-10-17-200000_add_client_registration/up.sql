@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE initial_access_tokens (
+  id          SERIAL PRIMARY KEY,
+  token       VARCHAR(255) NOT NULL UNIQUE,
+  description VARCHAR(255) NOT NULL,
+  created_at  TIMESTAMP    NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE clients ADD COLUMN registration_access_token VARCHAR(255) UNIQUE;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+-- The secrets themselves can't be recovered from their hashes, so every
+-- client gets a new random one.
+ALTER TABLE clients ADD COLUMN secret VARCHAR(255) NOT NULL
+  DEFAULT md5(random()::text);
+ALTER TABLE clients ALTER COLUMN secret DROP DEFAULT;
+
+DROP TABLE client_secrets;
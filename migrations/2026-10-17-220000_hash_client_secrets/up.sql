@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE client_secrets (
+  id             SERIAL PRIMARY KEY,
+  client_id      INTEGER      NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+  label          VARCHAR(255) NOT NULL DEFAULT '',
+  hashed_secret  VARCHAR(255) NOT NULL UNIQUE,
+  created_at     TIMESTAMP    NOT NULL DEFAULT NOW(),
+  expires_at     TIMESTAMP
+);
+
+INSERT INTO client_secrets (client_id, label, hashed_secret, created_at)
+  SELECT id, 'Original secret',
+         encode(sha256(convert_to(secret, 'UTF8')), 'hex'), created_at
+  FROM clients
+  WHERE NOT public;
+
+ALTER TABLE clients DROP COLUMN secret;
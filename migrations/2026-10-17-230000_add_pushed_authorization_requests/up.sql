@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN par_required BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN signed_request_required BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE stored_tokens (
+  token       TEXT PRIMARY KEY,
+  kind        TEXT NOT NULL,
+  item        TEXT NOT NULL,
+  expires_at  TIMESTAMP NOT NULL
+);
+
+CREATE INDEX stored_tokens_expires_at ON stored_tokens (expires_at);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN request_uri_list TEXT NOT NULL DEFAULT '';
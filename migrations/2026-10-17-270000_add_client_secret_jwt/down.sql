@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE client_secrets DROP COLUMN encrypted_secret;
+ALTER TABLE clients DROP COLUMN secret_jwt_allowed;
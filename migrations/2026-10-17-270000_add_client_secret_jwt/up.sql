@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE clients ADD COLUMN secret_jwt_allowed BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE client_secrets ADD COLUMN encrypted_secret TEXT;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE sessions ADD COLUMN authenticated_at TIMESTAMP NOT NULL DEFAULT NOW();
+UPDATE sessions SET authenticated_at = created_at;
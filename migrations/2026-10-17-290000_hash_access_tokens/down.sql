@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+-- The hashes can't be reversed, so all tokens become invalid.
+DELETE FROM initial_access_tokens;
+ALTER TABLE initial_access_tokens RENAME COLUMN hashed_token TO token;
+
+UPDATE clients SET hashed_registration_access_token = NULL;
+ALTER TABLE clients
+	RENAME COLUMN hashed_registration_access_token
+	TO registration_access_token;
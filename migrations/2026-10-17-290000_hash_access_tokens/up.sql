@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE initial_access_tokens RENAME COLUMN token TO hashed_token;
+UPDATE initial_access_tokens
+	SET hashed_token = encode(sha256(hashed_token::bytea), 'hex');
+
+ALTER TABLE clients
+	RENAME COLUMN registration_access_token
+	TO hashed_registration_access_token;
+UPDATE clients
+	SET hashed_registration_access_token =
+		encode(sha256(hashed_registration_access_token::bytea), 'hex')
+	WHERE hashed_registration_access_token IS NOT NULL;
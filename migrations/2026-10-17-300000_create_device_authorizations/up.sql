@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE device_authorizations (
+  device_code  TEXT PRIMARY KEY,
+  user_code    TEXT NOT NULL UNIQUE,
+  client_id    INTEGER NOT NULL REFERENCES clients (id) ON DELETE CASCADE,
+  scope        TEXT,
+  state        TEXT NOT NULL,
+  user_id      INTEGER REFERENCES users (id) ON DELETE CASCADE,
+  auth_time    TIMESTAMP,
+  sid          TEXT,
+  expires_at   TIMESTAMP NOT NULL,
+  last_poll    TIMESTAMP
+);
+
+CREATE INDEX device_authorizations_expires_at
+  ON device_authorizations (expires_at);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+-- The hashes can't be reversed, so all refresh tokens become invalid.
+UPDATE refresh_tokens SET valid = false;
+ALTER TABLE refresh_tokens RENAME COLUMN hashed_key TO key;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE refresh_tokens RENAME COLUMN key TO hashed_key;
+UPDATE refresh_tokens SET hashed_key = encode(sha256(hashed_key::bytea), 'hex');
@@ -0,0 +1,155 @@
+use crate::DbConn;
+use crate::config::Config;
+use crate::errors::{InternalError, Result, ZauthError};
+use crate::http_client::HttpClient;
+use crate::jwt::JWTBuilder;
+use crate::models::client::Client;
+use crate::models::session::Session;
+use crate::models::user::User;
+
+use log::{error, warn};
+use rocket::tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use rocket::tokio::time::sleep;
+use std::time::Duration;
+
+/// A logout token that still has to be delivered to a client.
+#[derive(Debug)]
+pub struct LogoutNotification {
+	uri: String,
+	logout_token: String,
+}
+
+/// Sends logout tokens to the back-channel logout URI of clients (OpenID
+/// Connect Back-Channel Logout 1.0), so they can end their own sessions when
+/// a user session in zauth ends.
+///
+/// Tokens are delivered in the background. Failed deliveries are retried,
+/// waiting twice as long before every new attempt.
+#[derive(Clone)]
+pub struct LogoutNotifier {
+	queue: UnboundedSender<LogoutNotification>,
+}
+
+impl LogoutNotifier {
+	pub fn new(config: &Config, http_client: &HttpClient) -> LogoutNotifier {
+		let (sender, receiver) = mpsc::unbounded_channel();
+		rocket::tokio::spawn(Self::sender(
+			receiver,
+			http_client.client().clone(),
+			config.backchannel_logout_retries,
+			Duration::from_secs(config.backchannel_logout_retry_seconds),
+		));
+		LogoutNotifier { queue: sender }
+	}
+
+	/// Notify the clients that were authorized during this user session that
+	/// the session has ended.
+	pub async fn session_ended(
+		&self,
+		session: &Session,
+		jwt_builder: &JWTBuilder,
+		config: &Config,
+		db: &DbConn,
+	) -> Result<()> {
+		let (Some(user_id), Some(sid)) = (session.user_id, session.sid.clone())
+		else {
+			return Ok(());
+		};
+		for client_id in Session::client_ids_for_sid(sid.clone(), db).await? {
+			let client = Client::find(client_id, db).await?;
+			self.notify(
+				&client,
+				user_id,
+				Some(sid.clone()),
+				jwt_builder,
+				config,
+			)?;
+		}
+		Ok(())
+	}
+
+	/// Notify every client the user ever authorized that all of their
+	/// sessions have ended, e.g. because their account was disabled.
+	pub async fn user_logged_out(
+		&self,
+		user: &User,
+		jwt_builder: &JWTBuilder,
+		config: &Config,
+		db: &DbConn,
+	) -> Result<()> {
+		for client_id in Session::client_ids_for_user(user.id, db).await? {
+			let client = Client::find(client_id, db).await?;
+			self.notify(&client, user.id, None, jwt_builder, config)?;
+		}
+		Ok(())
+	}
+
+	fn notify(
+		&self,
+		client: &Client,
+		user_id: i32,
+		sid: Option<String>,
+		jwt_builder: &JWTBuilder,
+		config: &Config,
+	) -> Result<()> {
+		let Some(uri) = client.backchannel_logout_uri() else {
+			return Ok(());
+		};
+		let logout_token =
+			jwt_builder.encode_logout_token(client, user_id, sid, config)?;
+		self.queue
+			.send(LogoutNotification {
+				uri: uri.to_string(),
+				logout_token,
+			})
+			.map_err(|e| ZauthError::from(InternalError::from(e)))
+	}
+
+	async fn sender(
+		mut receiver: UnboundedReceiver<LogoutNotification>,
+		http_client: reqwest::Client,
+		retries: u32,
+		retry_wait: Duration,
+	) {
+		while let Some(notification) = receiver.recv().await {
+			// Deliver every token on its own, so a client that is down does
+			// not hold up the notifications for other clients.
+			rocket::tokio::spawn(Self::deliver(
+				notification,
+				http_client.clone(),
+				retries,
+				retry_wait,
+			));
+		}
+	}
+
+	async fn deliver(
+		notification: LogoutNotification,
+		http_client: reqwest::Client,
+		retries: u32,
+		mut retry_wait: Duration,
+	) {
+		for attempt in 0..=retries {
+			let result = http_client
+				.post(&notification.uri)
+				.form(&[("logout_token", &notification.logout_token)])
+				.send()
+				.await
+				.and_then(|response| response.error_for_status());
+			match result {
+				Ok(_) => return,
+				Err(err) => warn!(
+					"Error sending logout token to {} (attempt {}): {}",
+					notification.uri,
+					attempt + 1,
+					err
+				),
+			}
+			if attempt < retries {
+				sleep(retry_wait).await;
+				retry_wait *= 2;
+			}
+		}
+		error!("Giving up on sending logout token to {}", notification.uri);
+	}
+}
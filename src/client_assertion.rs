@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use log::warn;
+
+use crate::DbConn;
+use crate::config::Config;
+use crate::errors::{AuthenticationError, OAuthError, Result, ZauthError};
+use crate::http_client::HttpClient;
+use crate::models::client::Client;
+use crate::models::client_secret::ClientSecret;
+use crate::models::used_assertion::UsedAssertion;
+
+pub const CLIENT_ASSERTION_TYPE: &str =
+	"urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+/// Algorithms for `client_secret_jwt`, where the assertion is signed with the
+/// client secret. Only clients that opted in have secrets we can verify those
+/// with, see `ClientSecret::reveal`.
+pub const SECRET_ALGORITHMS: [Algorithm; 3] =
+	[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+/// Algorithms for `private_key_jwt`, where the assertion is signed with a key
+/// from the JWKS of the client.
+pub const KEY_ALGORITHMS: [Algorithm; 9] = [
+	Algorithm::RS256,
+	Algorithm::RS384,
+	Algorithm::RS512,
+	Algorithm::PS256,
+	Algorithm::PS384,
+	Algorithm::PS512,
+	Algorithm::ES256,
+	Algorithm::ES384,
+	Algorithm::EdDSA,
+];
+
+/// Used assertions are remembered until they expire, so clients can't make
+/// them valid for longer than this.
+const MAX_LIFETIME_SECONDS: i64 = 60 * 60;
+
+#[derive(Deserialize)]
+struct UnverifiedClaims {
+	sub: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AssertionClaims {
+	jti: String,
+	exp: i64,
+}
+
+/// A JWT a client signed to authenticate itself instead of sending its secret
+/// (RFC 7523, section 2.2).
+#[derive(Debug)]
+pub struct ClientAssertion(String);
+
+impl ClientAssertion {
+	/// Read the `client_assertion_type` and `client_assertion` parameters of a
+	/// request. Returns `None` when the client does not use an assertion.
+	pub fn from_form(
+		assertion_type: Option<String>,
+		assertion: Option<String>,
+	) -> Result<Option<ClientAssertion>> {
+		match (assertion_type, assertion) {
+			(None, None) => Ok(None),
+			(Some(assertion_type), Some(assertion))
+				if assertion_type == CLIENT_ASSERTION_TYPE =>
+			{
+				Ok(Some(ClientAssertion(assertion)))
+			},
+			_ => Err(ZauthError::from(OAuthError::InvalidRequest)),
+		}
+	}
+
+	/// Find the client that signed this assertion and verify it was meant
+	/// for us and has not been used before.
+	pub async fn authenticate(
+		&self,
+		client_id: Option<String>,
+		http_client: &HttpClient,
+		config: &Config,
+		db: &DbConn,
+	) -> Result<Client> {
+		let unauthorized = || {
+			ZauthError::from(AuthenticationError::Unauthorized(
+				client_id.clone().unwrap_or_default(),
+			))
+		};
+		let header = decode_header(&self.0).map_err(|_| unauthorized())?;
+
+		// The signature can only be checked once we know which client the
+		// assertion claims to come from.
+		let mut validation = Validation::new(header.alg);
+		validation.insecure_disable_signature_validation();
+		validation.validate_exp = false;
+		validation.validate_aud = false;
+		validation.required_spec_claims.clear();
+		let name = decode::<UnverifiedClaims>(
+			&self.0,
+			&DecodingKey::from_secret(&[]),
+			&validation,
+		)
+		.ok()
+		.and_then(|data| data.claims.sub)
+		.ok_or_else(unauthorized)?;
+		if client_id
+			.as_ref()
+			.is_some_and(|client_id| *client_id != name)
+		{
+			return Err(unauthorized());
+		}
+		let client = match Client::find_by_name(name, db).await {
+			Ok(client) => client,
+			Err(ZauthError::NotFound(_)) => return Err(unauthorized()),
+			Err(e) => return Err(e),
+		};
+
+		let keys: Vec<DecodingKey> = if SECRET_ALGORITHMS.contains(&header.alg)
+			&& client.secret_jwt_allowed
+			&& !client.public
+		{
+			let mut keys = vec![];
+			for secret in ClientSecret::for_client(&client, db).await? {
+				if let Some(secret) = secret.reveal(config)? {
+					keys.push(DecodingKey::from_secret(secret.as_bytes()));
+				}
+			}
+			keys
+		} else if KEY_ALGORITHMS.contains(&header.alg) {
+			client_jwks(&client, http_client)
+				.await
+				.ok_or_else(unauthorized)?
+				.keys
+				.iter()
+				.filter(|jwk| {
+					header.kid.is_none() || jwk.common.key_id == header.kid
+				})
+				.filter_map(|jwk| DecodingKey::from_jwk(jwk).ok())
+				.collect()
+		} else {
+			return Err(unauthorized());
+		};
+
+		let mut validation = Validation::new(header.alg);
+		validation.set_audience(&audiences(config));
+		validation.set_issuer(&[&client.name]);
+		validation.sub = Some(client.name.clone());
+		validation.set_required_spec_claims(&["exp", "iss", "sub", "aud"]);
+		let claims = keys
+			.iter()
+			.find_map(|key| {
+				decode::<AssertionClaims>(&self.0, key, &validation).ok()
+			})
+			.ok_or_else(unauthorized)?
+			.claims;
+
+		if claims.exp > Utc::now().timestamp() + MAX_LIFETIME_SECONDS {
+			return Err(unauthorized());
+		}
+		let expires_at = DateTime::from_timestamp(claims.exp, 0)
+			.ok_or_else(unauthorized)?
+			.naive_utc();
+		if !UsedAssertion::record(client.id, claims.jti, expires_at, db).await?
+		{
+			return Err(unauthorized());
+		}
+		Ok(client)
+	}
+}
+
+/// Assertions have to name us as their audience, either by our issuer
+/// identifier or by the endpoint they are sent to.
+fn audiences(config: &Config) -> Vec<String> {
+	let issuer = config.base_url().to_string();
+	let base = issuer.trim_end_matches('/').to_string();
+	let mut audiences: Vec<String> = [
+		"/oauth/token",
+		"/oauth/introspect",
+		"/oauth/revoke",
+		"/oauth/device_authorization",
+		"/oauth/par",
+	]
+	.iter()
+	.map(|path| format!("{}{}", base, path))
+	.collect();
+	audiences.push(issuer);
+	audiences
+}
+
+/// The public keys of a client, registered inline or served at its JWKS URI.
+pub async fn client_jwks(
+	client: &Client,
+	http_client: &HttpClient,
+) -> Option<JwkSet> {
+	if let Some(jwks) = client.jwks() {
+		return serde_json::from_str(jwks).ok();
+	}
+	let jwks_uri = client.jwks_uri()?;
+	match http_client.jwks(jwks_uri).await {
+		Ok(jwks) => Some(jwks),
+		Err(err) => {
+			warn!(
+				"Error fetching JWKS of client {} from {}: {}",
+				client.name, jwks_uri, err
+			);
+			None
+		},
+	}
+}
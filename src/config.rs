@@ -1,8 +1,12 @@
-use chrono::Duration;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chrono::{DateTime, Duration, Utc};
 use lettre::message::Mailbox;
 use rocket::http::uri::Absolute;
 use rocket::serde::Deserialize;
 
+use crate::errors::LaunchError;
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
 pub struct Config {
@@ -10,11 +14,20 @@ pub struct Config {
 	pub user_session_seconds: i64,
 	pub client_session_seconds: i64,
 	pub authorization_token_seconds: i64,
+	pub pushed_request_seconds: i64,
+	pub refresh_token_seconds: i64,
+	pub device_code_seconds: i64,
+	pub device_poll_interval_seconds: i64,
 	pub email_confirmation_token_seconds: i64,
 	pub secure_token_length: usize,
+	pub token_store: TokenStoreBackend,
 	pub bcrypt_cost: u32,
 	pub base_url: String,
 	pub ec_private_key: String,
+	pub ec_retired_keys: Vec<RetiredKey>,
+	pub client_secret_key: Option<String>,
+	pub backchannel_logout_retries: u32,
+	pub backchannel_logout_retry_seconds: u64,
 	pub mail_queue_size: usize,
 	pub mail_queue_wait_seconds: u64,
 	pub mail_from: String,
@@ -27,10 +40,31 @@ pub struct Config {
 	pub mailing_list_email: String,
 	pub maximum_pending_users: usize,
 	pub mailer_role: String,
+	pub client_registration_role: String,
 	pub picture_url_prefix: String,
 	pub user_mail_domain: String,
 }
 
+/// Where authorization codes, pushed authorization requests and device
+/// authorizations are kept until they are used. Keep them in the database to
+/// survive restarts or to run several instances of zauth.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum TokenStoreBackend {
+	Memory,
+	Database,
+}
+
+/// A signing key that has been replaced by `ec_private_key`. Its public key is
+/// still published until `expires_at`, which should be later than the
+/// expiry of the last token it signed.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct RetiredKey {
+	pub path: String,
+	pub expires_at: DateTime<Utc>,
+}
+
 impl Config {
 	pub fn user_session_duration(&self) -> Duration {
 		Duration::seconds(self.user_session_seconds)
@@ -44,6 +78,41 @@ impl Config {
 		Duration::seconds(self.authorization_token_seconds)
 	}
 
+	pub fn pushed_request_duration(&self) -> Duration {
+		Duration::seconds(self.pushed_request_seconds)
+	}
+
+	pub fn refresh_token_duration(&self) -> Duration {
+		Duration::seconds(self.refresh_token_seconds)
+	}
+
+	pub fn device_code_duration(&self) -> Duration {
+		Duration::seconds(self.device_code_seconds)
+	}
+
+	pub fn device_poll_interval(&self) -> Duration {
+		Duration::seconds(self.device_poll_interval_seconds)
+	}
+
+	/// The key the secrets of clients that use `client_secret_jwt` are
+	/// encrypted with. Without one, clients can't use `client_secret_jwt`.
+	pub fn client_secret_key(&self) -> Result<Option<Vec<u8>>, LaunchError> {
+		self.client_secret_key
+			.as_ref()
+			.map(|key| {
+				BASE64_STANDARD
+					.decode(key)
+					.ok()
+					.filter(|key| key.len() == 32)
+					.ok_or_else(|| {
+						LaunchError::BadConfigValueType(
+							"client_secret_key".into(),
+						)
+					})
+			})
+			.transpose()
+	}
+
 	pub fn email_confirmation_token_duration(&self) -> Duration {
 		Duration::seconds(self.email_confirmation_token_seconds)
 	}
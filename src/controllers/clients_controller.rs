@@ -1,3 +1,4 @@
+use rocket::State;
 use rocket::form::Form;
 use rocket::http::Status;
 use rocket::response::status;
@@ -7,20 +8,23 @@ use rocket::serde::json::Json;
 use std::fmt::Debug;
 
 use crate::DbConn;
+use crate::config::Config;
 use crate::ephemeral::from_api::Api;
 use crate::ephemeral::from_api::SplitApi;
 use crate::ephemeral::session::{AdminSession, ClientSession};
-use crate::errors::Result;
+use crate::errors::{Result, ZauthError};
 use crate::models::client::*;
+use crate::models::client_secret::{ClientSecret, NewClientSecret};
 use crate::models::role::Role;
+use crate::models::scope::Scope;
 use crate::models::user::User;
 use crate::views::accepter::Accepter;
 
 // These structs need to be defined separately. Because we use a hidden field in
-// the HTML to make sure we always get some value for `needs_grant`, we need
-// Rocket to be lenient when parsing the form data. However, the `Lenient`
-// struct does not play nice with any other libraries. (So it can't be
-// deserialized by serde.)
+// the HTML to make sure we always get some value for boolean fields such as
+// `needs_grant`, we need Rocket to be lenient when parsing the form data.
+// However, the `Lenient` struct does not play nice with any other libraries.
+// (So it can't be deserialized by serde.)
 
 #[derive(Deserialize, Debug)]
 pub struct JsonClientChange {
@@ -28,6 +32,20 @@ pub struct JsonClientChange {
 	pub needs_grant: Option<bool>,
 	pub description: Option<String>,
 	pub redirect_uri_list: Option<String>,
+	pub pkce_required: Option<bool>,
+	pub public: Option<bool>,
+	pub par_required: Option<bool>,
+	pub signed_request_required: Option<bool>,
+	pub secret_jwt_allowed: Option<bool>,
+	pub userinfo_signed: Option<bool>,
+	pub jwt_access_tokens: Option<bool>,
+	pub allowed_scopes: Option<String>,
+	pub post_logout_redirect_uri_list: Option<String>,
+	pub backchannel_logout_uri: Option<String>,
+	pub token_exchange_audiences: Option<String>,
+	pub jwks: Option<String>,
+	pub jwks_uri: Option<String>,
+	pub request_uri_list: Option<String>,
 }
 
 #[derive(FromForm, Debug)]
@@ -36,6 +54,20 @@ pub struct FormClientChange {
 	pub needs_grant: Vec<bool>,
 	pub description: Option<String>,
 	pub redirect_uri_list: Option<String>,
+	pub pkce_required: Vec<bool>,
+	pub public: Vec<bool>,
+	pub par_required: Vec<bool>,
+	pub signed_request_required: Vec<bool>,
+	pub secret_jwt_allowed: Vec<bool>,
+	pub userinfo_signed: Vec<bool>,
+	pub jwt_access_tokens: Vec<bool>,
+	pub allowed_scopes: Option<String>,
+	pub post_logout_redirect_uri_list: Option<String>,
+	pub backchannel_logout_uri: Option<String>,
+	pub token_exchange_audiences: Option<String>,
+	pub jwks: Option<String>,
+	pub jwks_uri: Option<String>,
+	pub request_uri_list: Option<String>,
 }
 
 impl std::convert::From<JsonClientChange> for ClientChange {
@@ -45,6 +77,20 @@ impl std::convert::From<JsonClientChange> for ClientChange {
 			needs_grant: val.needs_grant,
 			description: val.description,
 			redirect_uri_list: val.redirect_uri_list,
+			pkce_required: val.pkce_required,
+			public: val.public,
+			par_required: val.par_required,
+			signed_request_required: val.signed_request_required,
+			secret_jwt_allowed: val.secret_jwt_allowed,
+			userinfo_signed: val.userinfo_signed,
+			jwt_access_tokens: val.jwt_access_tokens,
+			allowed_scopes: val.allowed_scopes,
+			post_logout_redirect_uri_list: val.post_logout_redirect_uri_list,
+			backchannel_logout_uri: val.backchannel_logout_uri,
+			token_exchange_audiences: val.token_exchange_audiences,
+			jwks: val.jwks,
+			jwks_uri: val.jwks_uri,
+			request_uri_list: val.request_uri_list,
 		}
 	}
 }
@@ -56,6 +102,23 @@ impl std::convert::From<FormClientChange> for ClientChange {
 			needs_grant: val.needs_grant.last().cloned(),
 			description: val.description,
 			redirect_uri_list: val.redirect_uri_list,
+			pkce_required: val.pkce_required.last().cloned(),
+			public: val.public.last().cloned(),
+			par_required: val.par_required.last().cloned(),
+			signed_request_required: val
+				.signed_request_required
+				.last()
+				.cloned(),
+			secret_jwt_allowed: val.secret_jwt_allowed.last().cloned(),
+			userinfo_signed: val.userinfo_signed.last().cloned(),
+			jwt_access_tokens: val.jwt_access_tokens.last().cloned(),
+			allowed_scopes: val.allowed_scopes,
+			post_logout_redirect_uri_list: val.post_logout_redirect_uri_list,
+			backchannel_logout_uri: val.backchannel_logout_uri,
+			token_exchange_audiences: val.token_exchange_audiences,
+			jwks: val.jwks,
+			jwks_uri: val.jwks_uri,
+			request_uri_list: val.request_uri_list,
 		}
 	}
 }
@@ -89,8 +152,11 @@ pub async fn update_client_page<'r>(
 	Ok(template! { "clients/edit_client.html";
 		current_user: User = session.admin,
 		client: Client = client.clone(),
+		secrets: Vec<ClientSecret> =
+			ClientSecret::for_client(&client, &db).await?,
 		client_roles: Vec<Role> = client.roles(&db).await?,
-		roles: Vec<Role> = roles
+		roles: Vec<Role> = roles,
+		scopes: Vec<Scope> = Scope::all(&db).await?,
 	})
 }
 
@@ -137,28 +203,71 @@ pub async fn create_client<'r>(
 	})
 }
 
-#[get("/clients/<id>/generate_secret")]
-pub async fn get_generate_secret<'r>(
+#[get("/clients/<id>/secrets")]
+pub async fn list_client_secrets(
 	id: i32,
 	_session: AdminSession,
 	db: DbConn,
+) -> Result<Json<Vec<ClientSecret>>> {
+	let client = Client::find(id, &db).await?;
+	Ok(Json(ClientSecret::for_client(&client, &db).await?))
+}
+
+/// A secret that was just created, which is the only time the secret itself
+/// is shown.
+#[derive(Serialize)]
+pub struct CreatedClientSecret {
+	#[serde(flatten)]
+	client_secret: ClientSecret,
+	secret: String,
+}
+
+#[post("/clients/<id>/secrets", data = "<secret>")]
+pub async fn create_client_secret<'r>(
+	id: i32,
+	secret: Api<NewClientSecret>,
+	session: AdminSession,
+	config: &State<Config>,
+	db: DbConn,
 ) -> Result<impl Responder<'r, 'static>> {
 	let client = Client::find(id, &db).await?;
-	Ok(template! { "clients/confirm_generate_secret.html";
-		client: Client = client,
+	if client.public {
+		return Err(ZauthError::Unprocessable(
+			"public clients don't have a secret".into(),
+		));
+	}
+	let (client_secret, secret) =
+		ClientSecret::create(&client, secret.into_inner(), config, &db).await?;
+	Ok(Accepter {
+		html: template! { "clients/new_secret.html";
+			current_user: User = session.admin,
+			client: Client = client,
+			client_secret: ClientSecret = client_secret.clone(),
+			secret: String = secret.clone(),
+		},
+		json: status::Created::new(format!("/clients/{}/secrets", id)).body(
+			Json(CreatedClientSecret {
+				client_secret,
+				secret,
+			}),
+		),
 	})
 }
 
-#[post("/clients/<id>/generate_secret")]
-pub async fn post_generate_secret<'r>(
+#[delete("/clients/<id>/secrets/<secret_id>")]
+pub async fn delete_client_secret<'r>(
 	id: i32,
+	secret_id: i32,
 	_session: AdminSession,
 	db: DbConn,
 ) -> Result<impl Responder<'r, 'static>> {
-	let client = Client::find(id, &db).await?;
-	let client = client.generate_secret(&db).await?;
+	let secret = ClientSecret::find(secret_id, &db).await?;
+	if secret.client_id != id {
+		return Err(ZauthError::not_found("client secret"));
+	}
+	secret.delete(&db).await?;
 	Ok(Accepter {
-		html: Redirect::to(uri!(update_client_page(client.id))),
+		html: Redirect::to(uri!(update_client_page(id))),
 		json: Custom(Status::NoContent, ()),
 	})
 }
@@ -0,0 +1,48 @@
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::{Redirect, Responder};
+use rocket::serde::json::Json;
+
+use crate::DbConn;
+use crate::ephemeral::session::UserSession;
+use crate::errors::{AuthenticationError, Result, ZauthError};
+use crate::models::consent::{ConnectedApplication, Consent};
+use crate::models::user::User;
+use crate::views::accepter::Accepter;
+
+#[get("/consents")]
+pub async fn list_consents<'r>(
+	session: UserSession,
+	db: DbConn,
+) -> Result<impl Responder<'r, 'static>> {
+	let applications =
+		Consent::connected_applications(session.user.id, &db).await?;
+	Ok(Accepter {
+		html: template! {
+			"consents/index.html";
+			applications: Vec<ConnectedApplication> = applications.clone(),
+			current_user: User = session.user,
+		},
+		json: Json(applications),
+	})
+}
+
+#[delete("/consents/<id>")]
+pub async fn revoke_consent<'r>(
+	id: i32,
+	session: UserSession,
+	db: DbConn,
+) -> Result<impl Responder<'r, 'static>> {
+	let consent = Consent::find_by_id(id, &db).await?;
+	if session.user.id == consent.user_id {
+		consent.revoke(&db).await?;
+		Ok(Accepter {
+			html: Redirect::to(uri!(list_consents)),
+			json: Custom(Status::NoContent, ()),
+		})
+	} else {
+		Err(ZauthError::AuthError(AuthenticationError::Unauthorized(
+			String::from("consent is given by another user"),
+		)))
+	}
+}
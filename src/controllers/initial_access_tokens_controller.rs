@@ -0,0 +1,80 @@
+use rocket::State;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::{Redirect, Responder, status};
+use rocket::serde::json::Json;
+
+use crate::DbConn;
+use crate::config::Config;
+use crate::ephemeral::from_api::Api;
+use crate::ephemeral::session::AdminSession;
+use crate::errors::Result;
+use crate::models::initial_access_token::{
+	InitialAccessToken, NewInitialAccessToken,
+};
+use crate::models::user::User;
+use crate::views::accepter::Accepter;
+
+#[get("/initial_access_tokens")]
+pub async fn list_initial_access_tokens<'r>(
+	db: DbConn,
+	session: AdminSession,
+) -> Result<impl Responder<'r, 'static>> {
+	let tokens = InitialAccessToken::all(&db).await?;
+
+	Ok(Accepter {
+		html: template! {
+			"initial_access_tokens/index.html";
+			tokens: Vec<InitialAccessToken> = tokens.clone(),
+			current_user: User = session.admin,
+		},
+		json: Json(tokens),
+	})
+}
+
+/// An initial access token that was just created, which is the only time the
+/// token itself is shown.
+#[derive(Serialize)]
+pub struct CreatedInitialAccessToken {
+	#[serde(flatten)]
+	initial_access_token: InitialAccessToken,
+	token: String,
+}
+
+#[post("/initial_access_tokens", data = "<token>")]
+pub async fn create_initial_access_token<'r>(
+	token: Api<NewInitialAccessToken>,
+	session: AdminSession,
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<impl Responder<'r, 'static>> {
+	let (initial_access_token, token) =
+		InitialAccessToken::create(token.into_inner(), config, &db).await?;
+	Ok(Accepter {
+		html: template! { "initial_access_tokens/new_token.html";
+			current_user: User = session.admin,
+			initial_access_token: InitialAccessToken =
+				initial_access_token.clone(),
+			token: String = token.clone(),
+		},
+		json: status::Created::new(String::from("/initial_access_tokens"))
+			.body(Json(CreatedInitialAccessToken {
+				initial_access_token,
+				token,
+			})),
+	})
+}
+
+#[delete("/initial_access_tokens/<id>")]
+pub async fn delete_initial_access_token<'r>(
+	id: i32,
+	_session: AdminSession,
+	db: DbConn,
+) -> Result<impl Responder<'r, 'static>> {
+	let token = InitialAccessToken::find(id, &db).await?;
+	token.delete(&db).await?;
+	Ok(Accepter {
+		html: Redirect::to(uri!(list_initial_access_tokens)),
+		json: Custom(Status::NoContent, ()),
+	})
+}
@@ -1,8 +1,12 @@
 pub mod clients_controller;
+pub mod consents_controller;
+pub mod initial_access_tokens_controller;
 pub mod mailing_list_controller;
 pub mod oauth_controller;
 pub mod pages_controller;
+pub mod registration_controller;
 pub mod roles_controller;
+pub mod scopes_controller;
 pub mod sessions_controller;
 pub mod users_controller;
 pub mod webauthn_controller;
@@ -1,30 +1,55 @@
 use base64::Engine;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration, NaiveDateTime, Utc};
 use jsonwebtoken::jwk::JwkSet;
+use rocket::Request;
 use rocket::State;
 use rocket::form::Form;
-use rocket::http::{Cookie, CookieJar};
-use rocket::response::{Redirect, Responder};
+use rocket::http::{ContentType, Cookie, CookieJar, Status};
+use rocket::response::status::Custom;
+use rocket::response::{self, Redirect, Responder};
 use rocket::serde::json::Json;
 use std::fmt::Debug;
 
 use crate::DbConn;
+use crate::backchannel_logout::LogoutNotifier;
+use crate::client_assertion::{
+	ClientAssertion, KEY_ALGORITHMS, SECRET_ALGORITHMS,
+};
 use crate::config::Config;
-use crate::ephemeral::session::UserSession;
+use crate::device_store::{
+	DeviceAuthorizationState, DevicePoll, DeviceStore, format_user_code,
+};
+use crate::ephemeral::session::{UserClientSession, UserSession};
 use crate::errors::Either::{Left, Right};
 use crate::errors::*;
 use crate::http_authentication::BasicAuthentication;
-use crate::jwt::JWTBuilder;
+use crate::http_client::HttpClient;
+use crate::jwt::{AuthenticationClaims, JWTBuilder};
 use crate::models::client::*;
+use crate::models::consent::Consent;
+use crate::models::refresh_token::RefreshToken;
+use crate::models::scope::Scope;
 use crate::models::session::*;
 use crate::models::user::*;
+use crate::pkce::CodeChallenge;
+use crate::request_object::RequestObject;
 use crate::util::split_scopes;
 
-use crate::ephemeral::session::ensure_logged_in_and_redirect;
+use crate::ephemeral::session::{
+	ensure_logged_in_and_redirect, ensure_reauthenticated_and_redirect,
+};
 use crate::errors::OAuthError::InvalidCookie;
 use crate::token_store::TokenStore;
 
 const OAUTH_COOKIE: &str = "ZAUTH_OAUTH";
+const DEVICE_COOKIE: &str = "ZAUTH_DEVICE";
+const DEVICE_CODE_GRANT_TYPE: &str =
+	"urn:ietf:params:oauth:grant-type:device_code";
+const TOKEN_EXCHANGE_GRANT_TYPE: &str =
+	"urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
 
 #[derive(Serialize, Deserialize, Debug, FromForm, UriDisplayQuery)]
 pub struct AuthState {
@@ -33,15 +58,32 @@ pub struct AuthState {
 	pub redirect_uri: String,
 	pub scope: Option<String>,
 	pub client_state: Option<String>,
+	pub code_challenge: Option<CodeChallenge>,
+	pub nonce: Option<String>,
+	pub login_hint: Option<String>,
+	pub max_age: Option<i64>,
+	pub force_login: bool,
+	pub force_consent: bool,
+	pub response_mode: ResponseMode,
+	/// The user has to have logged in after this moment, in milliseconds
+	/// since the epoch, because the client asked for a fresh login.
+	pub login_after: Option<i64>,
 }
 
 impl AuthState {
-	pub fn redirect_uri_with_state(&self) -> String {
-		let state_param =
-			self.client_state.as_ref().map_or(String::new(), |s| {
-				format!("state={}", urlencoding::encode(s))
-			});
-		format!("{}?{}", self.redirect_uri, state_param)
+	/// Answer the authorization request with the given parameters.
+	pub fn response(
+		self,
+		params: Vec<(&'static str, String)>,
+		config: &Config,
+	) -> AuthorizationResponse {
+		AuthorizationResponse::new(
+			self.redirect_uri,
+			self.response_mode,
+			params,
+			self.client_state,
+			config,
+		)
 	}
 
 	pub fn from_cookies(cookies: &CookieJar) -> Result<Self> {
@@ -58,16 +100,48 @@ impl AuthState {
 	pub fn from_req(
 		client: Client,
 		auth_req: AuthorizationRequest,
+		code_challenge: Option<CodeChallenge>,
+		response_mode: ResponseMode,
 	) -> AuthState {
+		let prompts = auth_req.prompts();
+		let force_login = prompts.contains(&"login");
+		let force_consent = prompts.contains(&"consent");
 		AuthState {
 			client_id: client.id,
 			client_name: client.name,
-			redirect_uri: auth_req.redirect_uri,
+			// Requests only get here with a redirect URI we accepted
+			redirect_uri: auth_req.redirect_uri.unwrap_or_default(),
 			scope: auth_req.scope,
 			client_state: auth_req.state,
+			code_challenge,
+			nonce: auth_req.nonce,
+			login_hint: auth_req.login_hint,
+			max_age: auth_req.max_age,
+			force_login,
+			force_consent,
+			response_mode,
+			login_after: force_login.then(|| Utc::now().timestamp_millis()),
 		}
 	}
 
+	/// Whether the user logged in too long ago for the `max_age` the client
+	/// asked for.
+	pub fn login_expired(&self, session: &UserSession) -> bool {
+		self.max_age.is_some_and(|max_age| {
+			Utc::now().naive_utc() - session.authenticated_at()
+				> Duration::seconds(max_age)
+		})
+	}
+
+	/// Whether the user still has to log in again before granting.
+	pub fn reauthentication_needed(&self, session: &UserSession) -> bool {
+		self.login_expired(session)
+			|| self.login_after.is_some_and(|login_after| {
+				session.authenticated_at().and_utc().timestamp_millis()
+					<= login_after
+			})
+	}
+
 	pub fn encode_url(&self) -> String {
 		serde_urlencoded::to_string(self).unwrap()
 	}
@@ -91,52 +165,489 @@ impl AuthState {
 	}
 }
 
-#[derive(Debug, FromForm, Serialize, Deserialize)]
+/// How the parameters of the authorization response are returned to the
+/// client (OAuth 2.0 Multiple Response Type Encoding Practices and OAuth 2.0
+/// Form Post Response Mode).
+#[derive(
+	Serialize,
+	Deserialize,
+	FromFormField,
+	UriDisplayQuery,
+	Debug,
+	Clone,
+	Copy,
+	PartialEq,
+)]
+pub enum ResponseMode {
+	Query,
+	Fragment,
+	FormPost,
+}
+
+impl ResponseMode {
+	pub fn parse(mode: Option<&str>) -> Result<Self> {
+		match mode {
+			None | Some("query") => Ok(ResponseMode::Query),
+			Some("fragment") => Ok(ResponseMode::Fragment),
+			Some("form_post") => Ok(ResponseMode::FormPost),
+			Some(other) => Err(ZauthError::from(
+				OAuthError::UnsupportedResponseMode(other.to_string()),
+			)),
+		}
+	}
+}
+
+/// The answer to an authorization request, sent to the redirect URI of the
+/// client in the response mode it asked for. The client state and our issuer
+/// identifier (RFC 9207) are added to the parameters.
+pub struct AuthorizationResponse {
+	redirect_uri: String,
+	response_mode: ResponseMode,
+	params: Vec<(&'static str, String)>,
+}
+
+impl AuthorizationResponse {
+	pub fn new(
+		redirect_uri: String,
+		response_mode: ResponseMode,
+		mut params: Vec<(&'static str, String)>,
+		client_state: Option<String>,
+		config: &Config,
+	) -> Self {
+		if let Some(client_state) = client_state {
+			params.push(("state", client_state));
+		}
+		params.push(("iss", config.base_url().to_string()));
+		AuthorizationResponse {
+			redirect_uri,
+			response_mode,
+			params,
+		}
+	}
+}
+
+impl<'r> Responder<'r, 'static> for AuthorizationResponse {
+	fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+		let params = self
+			.params
+			.iter()
+			.map(|(name, value)| {
+				format!("{}={}", name, urlencoding::encode(value))
+			})
+			.collect::<Vec<String>>()
+			.join("&");
+		match self.response_mode {
+			ResponseMode::Query => {
+				// The redirect URI may already have a query of its own
+				let separator = if self.redirect_uri.contains('?') {
+					'&'
+				} else {
+					'?'
+				};
+				Redirect::to(format!(
+					"{}{}{}",
+					self.redirect_uri, separator, params
+				))
+				.respond_to(request)
+			},
+			ResponseMode::Fragment => {
+				Redirect::to(format!("{}#{}", self.redirect_uri, params))
+					.respond_to(request)
+			},
+			ResponseMode::FormPost => template! {
+				"oauth/form_post.html";
+				redirect_uri: String = self.redirect_uri,
+				params: Vec<(&'static str, String)> = self.params,
+			}
+			.respond_to(request),
+		}
+	}
+}
+
+/// The parameters of an error response (RFC 6749, section 4.1.2.1).
+fn error_params(error: &OAuthError) -> Vec<(&'static str, String)> {
+	vec![
+		("error", error.error_code().to_string()),
+		("error_description", error.to_string()),
+	]
+}
+
+/// The parameters of an authorization request. The client can also send
+/// them in a signed `request` object, or refer to a pushed request or request
+/// object with `request_uri`.
+#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
 pub struct AuthorizationRequest {
-	pub response_type: String,
+	pub response_type: Option<String>,
 	pub client_id: String,
-	pub redirect_uri: String,
+	pub redirect_uri: Option<String>,
 	pub scope: Option<String>,
 	pub state: Option<String>,
+	pub code_challenge: Option<String>,
+	pub code_challenge_method: Option<String>,
+	pub nonce: Option<String>,
+	pub prompt: Option<String>,
+	pub max_age: Option<i64>,
+	pub login_hint: Option<String>,
+	pub response_mode: Option<String>,
+	pub request: Option<String>,
+	pub request_uri: Option<String>,
+}
+
+impl AuthorizationRequest {
+	fn prompts(&self) -> Vec<&str> {
+		self.prompt
+			.as_deref()
+			.map_or(vec![], |prompt| prompt.split_whitespace().collect())
+	}
+}
+
+/// An authorization request a client pushed (RFC 9126), waiting for the user
+/// to arrive at the authorization endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushedRequest {
+	request: AuthorizationRequest,
+	signed: bool,
+}
+
+/// How the parameters of an authorization request reached us, as some
+/// clients have to push or sign their requests.
+#[derive(Debug, Default, Clone, Copy)]
+struct RequestOrigin {
+	pushed: bool,
+	signed: bool,
 }
 
 #[get("/oauth/authorize?<req..>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn authorize<'r>(
+	session: Option<UserSession>,
 	cookies: &CookieJar<'_>,
 	req: AuthorizationRequest,
+	pushed_requests: &State<Box<dyn TokenStore<PushedRequest>>>,
+	token_store: &State<Box<dyn TokenStore<UserToken>>>,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
 	db: DbConn,
-) -> Result<impl Responder<'r, 'static> + use<'r>> {
-	if !req.response_type.eq("code") {
-		// This was NotImplemented error, but it makes no sense for a authorise
-		// function not to return an AuthResult
+) -> Result<Either<impl Responder<'r, 'static> + use<'r>, AuthorizationResponse>>
+{
+	let client = match Client::find_by_name(req.client_id.to_owned(), &db).await
+	{
+		Ok(client) => client,
+		_ => {
+			return Err(AuthenticationError::Unauthorized(format!(
+				"client with id {} is not authorized on this server",
+				req.client_id
+			))
+			.into());
+		},
+	};
+	let (req, origin) = resolve_request(
+		&client,
+		req,
+		pushed_requests.inner().as_ref(),
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+	// Errors can only be sent back to redirect URIs the client registered
+	let Some(redirect_uri) = req
+		.redirect_uri
+		.clone()
+		.filter(|uri| client.redirect_uri_acceptable(uri))
+	else {
+		return Err(AuthenticationError::Unauthorized(format!(
+			"client with id {} is not authorized to use redirect_uri '{}'",
+			req.client_id,
+			req.redirect_uri.unwrap_or_default()
+		))
+		.into());
+	};
+
+	let silent = req.prompts().contains(&"none");
+	let client_description = client.description.clone();
+	let needs_grant = client.needs_grant;
+	let client_state = req.state.clone();
+	let response_mode = ResponseMode::parse(req.response_mode.as_deref())
+		.unwrap_or(ResponseMode::Query);
+	let state = match authorization_state(client, req, origin, &db).await {
+		Ok(state) => state,
+		Err(ZauthError::OAuth(error)) => {
+			return Ok(Right(AuthorizationResponse::new(
+				redirect_uri,
+				response_mode,
+				error_params(&error),
+				client_state,
+				config,
+			)));
+		},
+		Err(err) => return Err(err),
+	};
+	if silent {
+		return Ok(Right(
+			silent_authorization(
+				state,
+				needs_grant,
+				session,
+				token_store.inner().as_ref(),
+				config,
+				&db,
+			)
+			.await?,
+		));
+	}
+	cookies.add_private(state.into_cookie()?);
+	Ok(Left(template! {
+		"oauth/authorize.html";
+		authorize_post_url: String = uri!(do_authorize).to_string(),
+		client_description: String = client_description,
+	}))
+}
+
+/// Take the parameters of an authorization request from the signed request
+/// object or the pushed request the client sent along, if any. A pushed
+/// request can only be used once.
+async fn resolve_request(
+	client: &Client,
+	mut req: AuthorizationRequest,
+	pushed_requests: &dyn TokenStore<PushedRequest>,
+	http_client: &HttpClient,
+	config: &Config,
+	db: &DbConn,
+) -> Result<(AuthorizationRequest, RequestOrigin)> {
+	let signed = RequestOrigin {
+		pushed: false,
+		signed: true,
+	};
+	match (req.request.take(), req.request_uri.take()) {
+		(None, None) => Ok((req, RequestOrigin::default())),
+		(Some(object), None) => {
+			let object =
+				RequestObject::verify(&object, client, http_client, config)
+					.await?;
+			Ok((object.into_request(req.client_id), signed))
+		},
+		(None, Some(uri)) if uri.starts_with(REQUEST_URI_PREFIX) => {
+			let key = uri.trim_start_matches(REQUEST_URI_PREFIX).to_string();
+			let pushed = pushed_requests
+				.fetch_token(key, db)
+				.await?
+				.map(|token| token.item)
+				.filter(|pushed| pushed.request.client_id == client.name)
+				.ok_or_else(|| {
+					OAuthError::InvalidRequestUri(format!(
+						"'{}' is unknown or has expired",
+						uri
+					))
+				})?;
+			Ok((
+				pushed.request,
+				RequestOrigin {
+					pushed: true,
+					signed: pushed.signed,
+				},
+			))
+		},
+		(None, Some(uri)) => {
+			let object =
+				RequestObject::fetch(&uri, client, http_client).await?;
+			let object =
+				RequestObject::verify(&object, client, http_client, config)
+					.await?;
+			Ok((object.into_request(req.client_id), signed))
+		},
+		(Some(_), Some(_)) => Err(ZauthError::from(OAuthError::InvalidRequest)),
+	}
+}
+
+/// Check the parameters of an authorization request with a trusted redirect
+/// URI, so errors can be sent back to the client.
+async fn authorization_state(
+	client: Client,
+	mut req: AuthorizationRequest,
+	origin: RequestOrigin,
+	db: &DbConn,
+) -> Result<AuthState> {
+	if req.response_type.as_deref() != Some("code") {
 		return Err(ZauthError::from(OAuthError::ResponseTypeMismatch));
 	}
+	if client.par_required && !origin.pushed {
+		return Err(ZauthError::from(OAuthError::ParRequired));
+	}
+	if client.signed_request_required && !origin.signed {
+		return Err(ZauthError::from(OAuthError::SignedRequestRequired));
+	}
+	let prompts = req.prompts();
+	if prompts.contains(&"none") && prompts.len() > 1 {
+		return Err(ZauthError::from(OAuthError::InvalidRequest));
+	}
+	let response_mode = ResponseMode::parse(req.response_mode.as_deref())?;
+	let code_challenge = CodeChallenge::from_request(
+		req.code_challenge.clone(),
+		req.code_challenge_method.clone(),
+	)?;
+	if client.requires_pkce() && code_challenge.is_none() {
+		return Err(ZauthError::from(OAuthError::PkceRequired));
+	}
+	req.scope = Scope::permitted(&client, &req.scope, db).await?;
+	Ok(AuthState::from_req(
+		client,
+		req,
+		code_challenge,
+		response_mode,
+	))
+}
 
-	match Client::find_by_name(req.client_id.to_owned(), &db).await {
-		Ok(client) => {
-			if client.redirect_uri_acceptable(&req.redirect_uri) {
-				let client_description = client.description.clone();
-				let state = AuthState::from_req(client, req);
-				cookies.add_private(state.into_cookie()?);
-				Ok(template! {
-					"oauth/authorize.html";
-					authorize_post_url: String = uri!(do_authorize).to_string(),
-					client_description: String = client_description,
-				})
-			} else {
-				Err(AuthenticationError::Unauthorized(format!(
-					"client with id {} is not authorized to use redirect_uri '{}'",
-					req.client_id, req.redirect_uri
-				))
-				.into())
-			}
+/// Handle `prompt=none`, where the client wants to know whether the user can
+/// be authorized without showing them anything.
+async fn silent_authorization(
+	state: AuthState,
+	needs_grant: bool,
+	session: Option<UserSession>,
+	token_store: &dyn TokenStore<UserToken>,
+	config: &Config,
+	db: &DbConn,
+) -> Result<AuthorizationResponse> {
+	let session = match session {
+		Some(session) if !state.login_expired(&session) => session,
+		_ => {
+			return Ok(authorization_error(
+				state,
+				OAuthError::LoginRequired,
+				config,
+			));
 		},
-		_ => Err(AuthenticationError::Unauthorized(format!(
-			"client with id {} is not authorized on this server",
-			req.client_id
+	};
+	let consented = Consent::find(session.user.id, state.client_id, db)
+		.await?
+		.is_some_and(|consent| consent.covers(&state.scope));
+	if needs_grant && !consented {
+		Ok(authorization_error(
+			state,
+			OAuthError::ConsentRequired,
+			config,
 		))
-		.into()),
+	} else {
+		authorization_granted(state, session, token_store, config, db).await
+	}
+}
+
+#[derive(FromForm, Debug)]
+pub struct PushedAuthorizationFormData {
+	client_id: Option<String>,
+	client_secret: Option<String>,
+	client_assertion_type: Option<String>,
+	client_assertion: Option<String>,
+	request: Option<String>,
+	request_uri: Option<String>,
+	response_type: Option<String>,
+	redirect_uri: Option<String>,
+	scope: Option<String>,
+	state: Option<String>,
+	code_challenge: Option<String>,
+	code_challenge_method: Option<String>,
+	nonce: Option<String>,
+	prompt: Option<String>,
+	max_age: Option<i64>,
+	login_hint: Option<String>,
+	response_mode: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PushedAuthorizationResponse {
+	request_uri: String,
+	expires_in: i64,
+}
+
+/// Let a client push the parameters of an authorization request, so it only
+/// has to send the user to the authorization endpoint with a short-lived
+/// reference to them (RFC 9126).
+#[post("/oauth/par", data = "<form>")]
+pub async fn push_authorization_request(
+	auth: Option<BasicAuthentication>,
+	form: Form<PushedAuthorizationFormData>,
+	pushed_requests: &State<Box<dyn TokenStore<PushedRequest>>>,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Custom<Json<PushedAuthorizationResponse>>> {
+	let data = form.into_inner();
+	let client = authenticate_client(
+		auth,
+		data.client_id.clone(),
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+	// A pushed request can't refer to another one
+	if data.request_uri.is_some()
+		|| data.client_id.is_some_and(|id| id != client.name)
+	{
+		return Err(ZauthError::from(OAuthError::InvalidRequest));
+	}
+
+	let req = AuthorizationRequest {
+		response_type: data.response_type,
+		client_id: client.name.clone(),
+		redirect_uri: data.redirect_uri,
+		scope: data.scope,
+		state: data.state,
+		code_challenge: data.code_challenge,
+		code_challenge_method: data.code_challenge_method,
+		nonce: data.nonce,
+		prompt: data.prompt,
+		max_age: data.max_age,
+		login_hint: data.login_hint,
+		response_mode: data.response_mode,
+		request: None,
+		request_uri: None,
+	};
+	let signed = data.request.is_some();
+	let req = match data.request {
+		Some(object) => {
+			RequestObject::verify(&object, &client, http_client, config)
+				.await?
+				.into_request(client.name.clone())
+		},
+		None => req,
+	};
+	if !req
+		.redirect_uri
+		.as_deref()
+		.is_some_and(|uri| client.redirect_uri_acceptable(uri))
+	{
+		return Err(ZauthError::from(OAuthError::InvalidRequest));
 	}
+	// Check the request now, so the client learns about its mistakes before
+	// it sends the user to us
+	let origin = RequestOrigin {
+		pushed: true,
+		signed,
+	};
+	authorization_state(client, req.clone(), origin, &db).await?;
+
+	let reference = pushed_requests
+		.create_token(
+			PushedRequest {
+				request: req,
+				signed,
+			},
+			&db,
+		)
+		.await?;
+	Ok(Custom(
+		Status::Created,
+		Json(PushedAuthorizationResponse {
+			request_uri: format!("{}{}", REQUEST_URI_PREFIX, reference),
+			expires_in: pushed_requests.token_validity().num_seconds(),
+		}),
+	))
 }
 
 #[derive(FromForm, Debug)]
@@ -146,14 +657,32 @@ pub struct AuthorizeFormData {
 
 #[post("/oauth/authorize", data = "<form>")]
 pub async fn do_authorize(
+	session: Option<UserSession>,
 	cookies: &CookieJar<'_>,
 	form: Form<AuthorizeFormData>,
-) -> Result<Redirect> {
+	config: &State<Config>,
+) -> Result<Either<Redirect, AuthorizationResponse>> {
 	let state = AuthState::from_cookies(cookies)?;
 	if form.into_inner().authorized {
-		Ok(ensure_logged_in_and_redirect(cookies, uri!(grant_get)))
+		// The user has to log in again before granting, without ending the
+		// session they have with other clients
+		if session
+			.as_ref()
+			.is_some_and(|session| state.reauthentication_needed(session))
+		{
+			return Ok(Left(ensure_reauthenticated_and_redirect(
+				cookies,
+				uri!(grant_get),
+				state.login_hint,
+			)));
+		}
+		Ok(Left(ensure_logged_in_and_redirect(
+			cookies,
+			uri!(grant_get),
+			state.login_hint,
+		)))
 	} else {
-		Ok(authorization_denied(state))
+		Ok(Right(authorization_denied(state, config)))
 	}
 }
 
@@ -162,6 +691,7 @@ pub struct GrantFormData {
 	grant: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
 pub struct UserToken {
 	pub user_id: i32,
 	pub username: String,
@@ -169,37 +699,62 @@ pub struct UserToken {
 	pub client_name: String,
 	pub redirect_uri: String,
 	pub scope: Option<String>,
+	pub code_challenge: Option<CodeChallenge>,
+	pub nonce: Option<String>,
+	pub auth_time: NaiveDateTime,
+	pub sid: Option<String>,
 }
 
 #[get("/oauth/grant")]
 pub async fn grant_get<'r>(
 	session: UserSession,
 	cookies: &CookieJar<'_>,
-	token_store: &State<TokenStore<UserToken>>,
+	token_store: &State<Box<dyn TokenStore<UserToken>>>,
+	config: &State<Config>,
 	db: DbConn,
 ) -> Result<
 	Either<
-		impl Responder<'r, 'static> + use<'r>,
-		impl Responder<'r, 'static> + use<'r>,
+		Redirect,
+		Either<
+			impl Responder<'r, 'static> + use<'r>,
+			impl Responder<'r, 'static> + use<'r>,
+		>,
 	>,
 > {
 	let state = AuthState::from_cookies(cookies)?;
+	if state.reauthentication_needed(&session) {
+		return Ok(Left(ensure_reauthenticated_and_redirect(
+			cookies,
+			uri!(grant_get),
+			state.login_hint,
+		)));
+	}
 	match Client::find(state.client_id, &db).await {
 		Ok(client) => {
-			if client.needs_grant {
-				Ok(Left(template! {
+			let consented = Consent::find(session.user.id, client.id, &db)
+				.await?
+				.is_some_and(|consent| consent.covers(&state.scope));
+			if state.force_consent || (client.needs_grant && !consented) {
+				let scopes =
+					Scope::find_by_names(split_scopes(&state.scope), &db)
+						.await?;
+				Ok(Right(Left(template! {
 					"oauth/grant.html";
 					client_description: String = client.description.clone(),
-				}))
+					scopes: Vec<Scope> = scopes,
+					grant_post_url: String = uri!(grant_post).to_string(),
+				})))
 			} else {
-				Ok(Right(
+				Ok(Right(Right(
 					authorization_granted(
 						state,
-						session.user,
-						token_store.inner(),
+						session,
+						token_store.inner().as_ref(),
+						config,
+						&db,
 					)
-					.await,
-				))
+					.await?,
+				)))
 			}
 		},
 		_ => Err(ZauthError::not_found("client not found")),
@@ -207,52 +762,84 @@ pub async fn grant_get<'r>(
 }
 
 #[post("/oauth/grant", data = "<form>")]
-pub async fn grant_post<'r>(
+pub async fn grant_post(
 	session: UserSession,
 	cookies: &CookieJar<'_>,
 	form: Form<GrantFormData>,
-	token_store: &State<TokenStore<UserToken>>,
-) -> Result<impl Responder<'r, 'static> + use<'r>> {
+	token_store: &State<Box<dyn TokenStore<UserToken>>>,
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Either<Redirect, AuthorizationResponse>> {
 	let data = form.into_inner();
 	let state = AuthState::from_cookies(cookies)?;
+	if state.reauthentication_needed(&session) {
+		return Ok(Left(ensure_reauthenticated_and_redirect(
+			cookies,
+			uri!(grant_get),
+			state.login_hint,
+		)));
+	}
 	if data.grant {
-		Ok(
-			authorization_granted(state, session.user, token_store.inner())
-				.await,
-		)
+		Consent::grant(session.user.id, state.client_id, &state.scope, &db)
+			.await?;
+		Ok(Right(
+			authorization_granted(
+				state,
+				session,
+				token_store.inner().as_ref(),
+				config,
+				&db,
+			)
+			.await?,
+		))
 	} else {
-		Ok(authorization_denied(state))
+		Ok(Right(authorization_denied(state, config)))
 	}
 }
 
 async fn authorization_granted(
 	state: AuthState,
-	user: User,
-	token_store: &TokenStore<UserToken>,
-) -> Redirect {
+	session: UserSession,
+	token_store: &dyn TokenStore<UserToken>,
+	config: &Config,
+	db: &DbConn,
+) -> Result<AuthorizationResponse> {
+	let auth_time = session.authenticated_at();
+	let sid = session.sid();
+	let user = session.user;
 	let authorization_code = token_store
-		.create_token(UserToken {
-			user_id: user.id,
-			scope: state.scope.clone(),
-			username: user.username.clone(),
-			client_id: state.client_id,
-			client_name: state.client_name.clone(),
-			redirect_uri: state.redirect_uri.clone(),
-		})
-		.await;
-	let uri = format!(
-		"{}&code={}",
-		state.redirect_uri_with_state(),
-		authorization_code
-	);
-	Redirect::to(uri)
-}
-
-fn authorization_denied(state: AuthState) -> Redirect {
-	Redirect::to(format!(
-		"{}&error=access_denied",
-		state.redirect_uri_with_state()
-	))
+		.create_token(
+			UserToken {
+				user_id: user.id,
+				scope: state.scope.clone(),
+				username: user.username.clone(),
+				client_id: state.client_id,
+				client_name: state.client_name.clone(),
+				redirect_uri: state.redirect_uri.clone(),
+				code_challenge: state.code_challenge.clone(),
+				nonce: state.nonce.clone(),
+				auth_time,
+				sid,
+			},
+			db,
+		)
+		.await?;
+	Ok(state.response(vec![("code", authorization_code)], config))
+}
+
+fn authorization_denied(
+	state: AuthState,
+	config: &Config,
+) -> AuthorizationResponse {
+	authorization_error(state, OAuthError::AccessDenied, config)
+}
+
+fn authorization_error(
+	state: AuthState,
+	error: OAuthError,
+	config: &Config,
+) -> AuthorizationResponse {
+	state.response(error_params(&error), config)
 }
 
 #[derive(Serialize, Debug)]
@@ -261,7 +848,13 @@ pub struct TokenSuccess {
 	token_type: String,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	id_token: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	refresh_token: Option<String>,
 	expires_in: i64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	issued_token_type: Option<&'static str>,
 }
 
 #[derive(FromForm, Debug)]
@@ -271,14 +864,172 @@ pub struct TokenFormData {
 	redirect_uri: Option<String>,
 	client_id: Option<String>,
 	client_secret: Option<String>,
+	client_assertion_type: Option<String>,
+	client_assertion: Option<String>,
+	scope: Option<String>,
+	code_verifier: Option<String>,
+	refresh_token: Option<String>,
+	device_code: Option<String>,
+	subject_token: Option<String>,
+	subject_token_type: Option<String>,
+	requested_token_type: Option<String>,
+	audience: Option<String>,
+	resource: Option<String>,
+}
+
+/// Authenticate a client using either HTTP Basic Authentication, the
+/// `client_id` and `client_secret` parameters in the request body or a signed
+/// client assertion. Public clients have no secret and only send their
+/// `client_id`.
+async fn authenticate_client(
+	auth: Option<BasicAuthentication>,
+	client_id: Option<String>,
+	client_secret: Option<String>,
+	assertion: Option<ClientAssertion>,
+	http_client: &HttpClient,
+	config: &Config,
+	db: &DbConn,
+) -> Result<Client> {
+	if let Some(assertion) = assertion {
+		// Clients have to pick one way to authenticate
+		if auth.is_some() || client_secret.is_some() {
+			return Err(ZauthError::from(OAuthError::InvalidRequest));
+		}
+		return assertion
+			.authenticate(client_id, http_client, config, db)
+			.await;
+	}
+
+	let (client_id, client_secret) = auth
+		.map(|auth| (auth.user, Some(auth.password)))
+		.or_else(|| Some((client_id?, client_secret)))
+		.ok_or(ZauthError::from(OAuthError::InvalidRequest))?;
+
+	let client = match client_secret.filter(|secret| !secret.is_empty()) {
+		Some(secret) => {
+			Client::find_and_authenticate(client_id.clone(), &secret, db).await
+		},
+		None => Client::find_by_name(client_id.clone(), db).await.and_then(
+			|client| {
+				if client.public {
+					Ok(client)
+				} else {
+					Err(ZauthError::from(AuthenticationError::AuthFailed))
+				}
+			},
+		),
+	};
+	match client {
+		Ok(client) => Ok(client),
+		Err(ZauthError::AuthError(_)) => Err(ZauthError::AuthError(
+			AuthenticationError::Unauthorized(client_id),
+		)),
+		Err(e) => Err(e),
+	}
+}
+
+/// The access token handed to the client for a session. This is the session
+/// key itself, unless the client wants JWT access tokens that can be validated
+/// without contacting us. Those are meant for the `audience`, or for our own
+/// API when there is none.
+async fn access_token(
+	session: &Session,
+	client: &Client,
+	audience: Option<&str>,
+	config: &Config,
+	jwt_builder: &JWTBuilder,
+	db: &DbConn,
+) -> Result<String> {
+	if !client.jwt_access_tokens {
+		return Ok(session.key.clone().unwrap());
+	}
+	let roles = match session.user_id {
+		Some(_) => {
+			session
+				.user(db)
+				.await?
+				.roles_for_client(client.id, db)
+				.await?
+		},
+		None => client.clone().roles(db).await?,
+	};
+	jwt_builder.encode_access_token(
+		client,
+		session,
+		roles.into_iter().map(|r| r.name).collect(),
+		audience,
+		config,
+	)
+}
+
+/// Create a new client session for the user and build the token response for
+/// it.
+async fn user_token_response(
+	user: &User,
+	client: &Client,
 	scope: Option<String>,
+	authentication: AuthenticationClaims,
+	config: &Config,
+	jwt_builder: &JWTBuilder,
+	db: &DbConn,
+) -> Result<(Session, TokenSuccess)> {
+	let sid = authentication.sid.clone();
+	let scopes = split_scopes(&scope);
+	let id_token = if scopes.contains(&"openid".into()) {
+		let roles = if scopes.contains(&"roles".into()) {
+			Some(
+				user.clone()
+					.roles_for_client(client.id, db)
+					.await?
+					.iter()
+					.map(|r| r.clone().name)
+					.collect(),
+			)
+		} else {
+			None
+		};
+		jwt_builder
+			.encode_id_token(client, user, config, roles, authentication)
+			.ok()
+	} else {
+		None
+	};
+
+	let session = Session::create_client_session(
+		Some(user),
+		client,
+		scope,
+		sid,
+		config,
+		db,
+	)
+	.await?;
+	let response = TokenSuccess {
+		access_token: access_token(
+			&session,
+			client,
+			None,
+			config,
+			jwt_builder,
+			db,
+		)
+		.await?,
+		token_type: String::from("bearer"),
+		id_token,
+		refresh_token: None,
+		expires_in: config.client_session_seconds,
+		scope: session.scope.clone(),
+		issued_token_type: None,
+	};
+	Ok((session, response))
 }
 
 pub async fn authorization_code_grant(
 	auth: Option<BasicAuthentication>,
 	data: TokenFormData,
+	http_client: &State<HttpClient>,
 	config: &State<Config>,
-	token_state: &State<TokenStore<UserToken>>,
+	token_state: &State<Box<dyn TokenStore<UserToken>>>,
 	jwt_builder: &State<JWTBuilder>,
 	db: DbConn,
 ) -> Result<Json<TokenSuccess>> {
@@ -289,27 +1040,23 @@ pub async fn authorization_code_grant(
 	let data_redirect_uri = data.redirect_uri.clone();
 	let token_store = token_state.inner();
 
-	let auth = auth
-		.map(|auth| (auth.user, auth.password))
-		.or_else(|| Some((data.client_id?, data.client_secret?)))
-		.ok_or(ZauthError::from(OAuthError::InvalidRequest))?;
-
-	let client =
-		match Client::find_and_authenticate(auth.0.to_string(), &auth.1, &db)
-			.await
-		{
-			Ok(client) => client,
-			Err(ZauthError::AuthError(_)) => {
-				return Err(ZauthError::AuthError(
-					AuthenticationError::Unauthorized(auth.0),
-				));
-			},
-			Err(e) => return Err(e),
-		};
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
 
 	let token = token_store
-		.fetch_token(token)
-		.await
+		.fetch_token(token, &db)
+		.await?
 		.ok_or(ZauthError::from(OAuthError::InvalidGrant(
 			"incorrect token".to_string(),
 		)))?
@@ -326,85 +1073,305 @@ pub async fn authorization_code_grant(
 		Err(ZauthError::from(OAuthError::InvalidGrant(
 			"redirect uri does not match".to_string(),
 		)))
+	} else if !code_verifier_acceptable(&token, &data.code_verifier) {
+		Err(ZauthError::from(OAuthError::InvalidGrant(
+			"code verifier does not match".to_string(),
+		)))
 	} else {
 		let user = User::find(token.user_id, &db).await?;
-		let scopes = split_scopes(&token.scope);
-		let id_token = if scopes.contains(&"openid".into()) {
-			let roles = if scopes.contains(&"roles".into()) {
-				Some(
-					user.clone()
-						.roles_for_client(client.id, &db)
-						.await?
-						.iter()
-						.map(|r| r.clone().name)
-						.collect(),
-				)
-			} else {
-				None
-			};
-			jwt_builder
-				.encode_id_token(&client, &user, config, roles)
-				.ok()
-		} else {
-			None
+		let authentication = AuthenticationClaims {
+			auth_time: token.auth_time,
+			nonce: token.nonce,
+			sid: token.sid,
 		};
+		Ok(Json(
+			issue_user_tokens(
+				&user,
+				&client,
+				token.scope,
+				authentication,
+				config,
+				jwt_builder,
+				&db,
+			)
+			.await?,
+		))
+	}
+}
 
-		let session = Session::create_client_session(
-			Some(&user),
-			&client,
-			token.scope,
-			config,
-			&db,
-		)
-		.await?;
-		Ok(Json(TokenSuccess {
-			access_token: session.key.unwrap().clone(),
-			token_type: String::from("bearer"),
-			id_token,
-			expires_in: config.client_session_seconds,
-		}))
+/// Build the token response for a user that just authorized a client. A new
+/// refresh token family is started when the client asked for offline access.
+async fn issue_user_tokens(
+	user: &User,
+	client: &Client,
+	scope: Option<String>,
+	authentication: AuthenticationClaims,
+	config: &Config,
+	jwt_builder: &JWTBuilder,
+	db: &DbConn,
+) -> Result<TokenSuccess> {
+	let offline_access =
+		split_scopes(&scope).contains(&"offline_access".into());
+	let auth_time = authentication.auth_time;
+	let (session, mut response) = user_token_response(
+		user,
+		client,
+		scope,
+		authentication,
+		config,
+		jwt_builder,
+		db,
+	)
+	.await?;
+	if offline_access {
+		let (_, refresh_token) =
+			RefreshToken::create(&session, None, auth_time, config, db).await?;
+		response.refresh_token = Some(refresh_token);
 	}
+	Ok(response)
 }
 
-pub async fn client_credentials_grant(
+/// A code verifier has to be sent if, and only if, the authorization request
+/// contained a code challenge.
+fn code_verifier_acceptable(
+	token: &UserToken,
+	code_verifier: &Option<String>,
+) -> bool {
+	match (&token.code_challenge, code_verifier) {
+		(Some(challenge), Some(verifier)) => challenge.verify(verifier),
+		(None, None) => true,
+		_ => false,
+	}
+}
+
+pub async fn refresh_token_grant(
+	auth: Option<BasicAuthentication>,
 	data: TokenFormData,
+	http_client: &State<HttpClient>,
 	config: &State<Config>,
 	jwt_builder: &State<JWTBuilder>,
 	db: DbConn,
 ) -> Result<Json<TokenSuccess>> {
-	let client_id = data
-		.client_id
-		.ok_or(ZauthError::from(OAuthError::InvalidRequest))?;
-
-	let client_secret = data
-		.client_secret
+	let key = data
+		.refresh_token
+		.clone()
 		.ok_or(ZauthError::from(OAuthError::InvalidRequest))?;
 
-	let client = match Client::find_and_authenticate(
-		client_id.clone(),
-		&client_secret,
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
 		&db,
 	)
-	.await
-	{
-		Ok(client) => client,
-		Err(ZauthError::AuthError(_)) => {
-			return Err(ZauthError::AuthError(
-				AuthenticationError::Unauthorized(client_id),
-			));
+	.await?;
+
+	let (refresh_token, session) =
+		match RefreshToken::find_by_key(key, &db).await {
+			Ok(found) => found,
+			Err(ZauthError::NotFound(_)) => {
+				return Err(ZauthError::from(OAuthError::InvalidGrant(
+					"incorrect refresh token".to_string(),
+				)));
+			},
+			Err(e) => return Err(e),
+		};
+
+	if session.client_id != Some(client.id) {
+		return Err(ZauthError::from(OAuthError::InvalidGrant(
+			"refresh token was not issued to this client".to_string(),
+		)));
+	}
+
+	// Refresh tokens can only be used once. When a used token is presented
+	// again, either the client or an attacker holds a stolen copy, so we
+	// revoke every token that descends from the same authorization.
+	if refresh_token.used {
+		return Err(refresh_token_reused(&refresh_token, &db).await);
+	}
+
+	let granted_scopes = split_scopes(&session.scope);
+	let scope = match data.scope {
+		Some(scope) => {
+			if split_scopes(&Some(scope.clone()))
+				.iter()
+				.any(|s| !granted_scopes.contains(s))
+			{
+				return Err(ZauthError::from(OAuthError::InvalidScope));
+			}
+			Some(scope)
 		},
-		Err(e) => return Err(e),
+		None => session.scope.clone(),
 	};
+	// The client may no longer be allowed every scope it was granted before,
+	// and scopes may have been removed from the registry
+	let scope = Scope::still_permitted(&client, &scope, &db).await?;
 
-	let scopes = split_scopes(&data.scope);
-	let id_token = if scopes.contains(&"openid".into()) {
-		let roles = if scopes.contains(&"roles".into()) {
-			Some(
-				client
-					.clone()
-					.roles(&db)
-					.await?
-					.iter()
+	let user = session.user(&db).await?;
+	if !user.is_active() {
+		refresh_token.revoke_family(&db).await?;
+		return Err(ZauthError::from(OAuthError::InvalidGrant(
+			"user is no longer active".to_string(),
+		)));
+	}
+
+	// Only use up the token once the request turned out to be valid, so a
+	// client can retry a rejected request with the same token. Another
+	// request may have used it in the meantime.
+	if !refresh_token.mark_used(&db).await? {
+		return Err(refresh_token_reused(&refresh_token, &db).await);
+	}
+
+	// A refreshed ID token keeps the time of the original login, but should not
+	// repeat the nonce of the original authorization request.
+	let authentication = AuthenticationClaims {
+		auth_time: refresh_token.auth_time,
+		nonce: None,
+		sid: session.sid.clone(),
+	};
+	let (new_session, mut response) = user_token_response(
+		&user,
+		&client,
+		scope,
+		authentication,
+		config,
+		jwt_builder,
+		&db,
+	)
+	.await?;
+	let (_, new_refresh_token) = RefreshToken::create(
+		&new_session,
+		Some(refresh_token.family),
+		refresh_token.auth_time,
+		config,
+		&db,
+	)
+	.await?;
+	response.refresh_token = Some(new_refresh_token);
+	Ok(Json(response))
+}
+
+async fn refresh_token_reused(
+	refresh_token: &RefreshToken,
+	db: &DbConn,
+) -> ZauthError {
+	match refresh_token.revoke_family(db).await {
+		Ok(()) => ZauthError::from(OAuthError::InvalidGrant(
+			"refresh token was already used".to_string(),
+		)),
+		Err(err) => err,
+	}
+}
+
+pub async fn device_code_grant(
+	auth: Option<BasicAuthentication>,
+	data: TokenFormData,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	device_store: &State<DeviceStore>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<Json<TokenSuccess>> {
+	let device_code = data
+		.device_code
+		.clone()
+		.ok_or(ZauthError::from(OAuthError::InvalidRequest))?;
+
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+
+	match device_store.poll(&device_code, client.id, &db).await? {
+		DevicePoll::Invalid => Err(ZauthError::from(OAuthError::InvalidGrant(
+			"incorrect device code".to_string(),
+		))),
+		DevicePoll::Pending => {
+			Err(ZauthError::from(OAuthError::AuthorizationPending))
+		},
+		DevicePoll::SlowDown => Err(ZauthError::from(OAuthError::SlowDown)),
+		DevicePoll::Expired => Err(ZauthError::from(OAuthError::ExpiredToken)),
+		DevicePoll::Denied => Err(ZauthError::from(OAuthError::AccessDenied)),
+		DevicePoll::Granted {
+			user_id,
+			scope,
+			auth_time,
+			sid,
+		} => {
+			let user = User::find(user_id, &db).await?;
+			let authentication = AuthenticationClaims {
+				auth_time,
+				nonce: None,
+				sid,
+			};
+			Ok(Json(
+				issue_user_tokens(
+					&user,
+					&client,
+					scope,
+					authentication,
+					config,
+					jwt_builder,
+					&db,
+				)
+				.await?,
+			))
+		},
+	}
+}
+
+pub async fn client_credentials_grant(
+	auth: Option<BasicAuthentication>,
+	data: TokenFormData,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<Json<TokenSuccess>> {
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+	// Anyone can act as a public client, so it can't get tokens of its own
+	if client.public {
+		return Err(ZauthError::from(OAuthError::UnauthorizedClient(
+			"public clients can't use the client credentials grant".into(),
+		)));
+	}
+
+	let scope = Scope::permitted(&client, &data.scope, &db).await?;
+	let scopes = split_scopes(&scope);
+	let id_token = if scopes.contains(&"openid".into()) {
+		let roles = if scopes.contains(&"roles".into()) {
+			Some(
+				client
+					.clone()
+					.roles(&db)
+					.await?
+					.iter()
 					.map(|r| r.clone().name)
 					.collect(),
 			)
@@ -419,22 +1386,174 @@ pub async fn client_credentials_grant(
 	};
 
 	let session =
-		Session::create_client_session(None, &client, data.scope, config, &db)
+		Session::create_client_session(None, &client, scope, None, config, &db)
 			.await?;
 	Ok(Json(TokenSuccess {
-		access_token: session.key.unwrap().clone(),
+		access_token: access_token(
+			&session,
+			&client,
+			None,
+			config,
+			jwt_builder,
+			&db,
+		)
+		.await?,
 		token_type: String::from("bearer"),
 		id_token,
+		refresh_token: None,
 		expires_in: config.client_session_seconds,
+		scope: session.scope.clone(),
+		issued_token_type: None,
+	}))
+}
+
+/// Exchange an access token the client received for a user for a token it can
+/// use to call another client on behalf of that user (RFC 8693). The other
+/// client, named by `audience` or `resource`, has to be listed in the token
+/// exchange audiences of the client.
+pub async fn token_exchange_grant(
+	auth: Option<BasicAuthentication>,
+	data: TokenFormData,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<Json<TokenSuccess>> {
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+
+	if data.subject_token_type.as_deref() != Some(ACCESS_TOKEN_TYPE)
+		|| data
+			.requested_token_type
+			.as_deref()
+			.is_some_and(|token_type| token_type != ACCESS_TOKEN_TYPE)
+	{
+		return Err(ZauthError::from(OAuthError::InvalidRequest));
+	}
+	let subject_token = data
+		.subject_token
+		.ok_or(ZauthError::from(OAuthError::InvalidRequest))?;
+
+	// Only tokens issued to the client itself can be exchanged, so a client
+	// can't act with a token that was meant for someone else.
+	let session =
+		match Session::find_by_access_token(subject_token, jwt_builder, &db)
+			.await
+		{
+			Ok(session)
+				if session.client_id == Some(client.id)
+					&& session.user_id.is_some() =>
+			{
+				session
+			},
+			Ok(_) | Err(ZauthError::NotFound(_)) => {
+				return Err(ZauthError::from(OAuthError::InvalidGrant(
+					"incorrect subject token".to_string(),
+				)));
+			},
+			Err(e) => return Err(e),
+		};
+
+	let audience = match (data.audience, data.resource) {
+		(Some(audience), Some(resource)) if audience != resource => {
+			return Err(ZauthError::from(OAuthError::InvalidRequest));
+		},
+		(Some(audience), _) | (None, Some(audience)) => audience,
+		(None, None) => {
+			return Err(ZauthError::from(OAuthError::InvalidRequest));
+		},
+	};
+	if !client.token_exchange_allowed(&audience) {
+		return Err(ZauthError::from(OAuthError::InvalidTarget(format!(
+			"client may not exchange tokens for '{}'",
+			audience
+		))));
+	}
+	let target = match Client::find_by_name(audience.clone(), &db).await {
+		Ok(target) => target,
+		Err(ZauthError::NotFound(_)) => {
+			return Err(ZauthError::from(OAuthError::InvalidTarget(format!(
+				"unknown client '{}'",
+				audience
+			))));
+		},
+		Err(e) => return Err(e),
+	};
+
+	// The new token can't carry more than the subject token, nor more than the
+	// target client is allowed to have.
+	let granted_scopes = split_scopes(&session.scope);
+	if split_scopes(&data.scope)
+		.iter()
+		.any(|scope| !granted_scopes.contains(scope))
+	{
+		return Err(ZauthError::from(OAuthError::InvalidScope));
+	}
+	let scope = data.scope.or(session.scope.clone());
+	let scope = Scope::permitted(&target, &scope, &db).await?;
+
+	let user = session.user(&db).await?;
+	if !user.is_active() {
+		return Err(ZauthError::from(OAuthError::InvalidGrant(
+			"user is no longer active".to_string(),
+		)));
+	}
+
+	let mut target_session = Session::create_client_session(
+		Some(&user),
+		&target,
+		scope,
+		session.sid.clone(),
+		config,
+		&db,
+	)
+	.await?;
+	// Exchanging a token must not extend how long the user can be acted for
+	if target_session.expires_at > session.expires_at {
+		target_session.expires_at = session.expires_at;
+		target_session = target_session.update(&db).await?;
+	}
+	let expires_in =
+		(target_session.expires_at - Utc::now().naive_utc()).num_seconds();
+	Ok(Json(TokenSuccess {
+		access_token: access_token(
+			&target_session,
+			&target,
+			Some(&audience),
+			config,
+			jwt_builder,
+			&db,
+		)
+		.await?,
+		token_type: String::from("bearer"),
+		id_token: None,
+		refresh_token: None,
+		expires_in,
+		scope: target_session.scope.clone(),
+		issued_token_type: Some(ACCESS_TOKEN_TYPE),
 	}))
 }
 
 #[post("/oauth/token", data = "<form>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn token(
 	auth: Option<BasicAuthentication>,
 	form: Form<TokenFormData>,
+	http_client: &State<HttpClient>,
 	config: &State<Config>,
-	token_state: &State<TokenStore<UserToken>>,
+	token_state: &State<Box<dyn TokenStore<UserToken>>>,
+	device_store: &State<DeviceStore>,
 	jwt_builder: &State<JWTBuilder>,
 	db: DbConn,
 ) -> Result<Json<TokenSuccess>> {
@@ -445,6 +1564,7 @@ pub async fn token(
 			authorization_code_grant(
 				auth,
 				data,
+				http_client,
 				config,
 				token_state,
 				jwt_builder,
@@ -452,14 +1572,854 @@ pub async fn token(
 			)
 			.await
 		},
+		DEVICE_CODE_GRANT_TYPE => {
+			device_code_grant(
+				auth,
+				data,
+				http_client,
+				config,
+				device_store,
+				jwt_builder,
+				db,
+			)
+			.await
+		},
+		"refresh_token" => {
+			refresh_token_grant(
+				auth,
+				data,
+				http_client,
+				config,
+				jwt_builder,
+				db,
+			)
+			.await
+		},
 		"client_credentials" => {
-			client_credentials_grant(data, config, jwt_builder, db).await
+			client_credentials_grant(
+				auth,
+				data,
+				http_client,
+				config,
+				jwt_builder,
+				db,
+			)
+			.await
+		},
+		TOKEN_EXCHANGE_GRANT_TYPE => {
+			token_exchange_grant(
+				auth,
+				data,
+				http_client,
+				config,
+				jwt_builder,
+				db,
+			)
+			.await
 		},
 		_ => Err(ZauthError::from(OAuthError::GrantTypeMismatch)),
 	}
 }
 
+#[derive(FromForm, Debug)]
+pub struct RevokeFormData {
+	token: String,
+	token_type_hint: Option<String>,
+	client_id: Option<String>,
+	client_secret: Option<String>,
+	client_assertion_type: Option<String>,
+	client_assertion: Option<String>,
+}
+
+/// Revoke an access or refresh token (RFC 7009). Tokens that are unknown or
+/// belong to another client are ignored, so the response does not reveal
+/// anything about them.
+#[post("/oauth/revoke", data = "<form>")]
+pub async fn revoke(
+	auth: Option<BasicAuthentication>,
+	form: Form<RevokeFormData>,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<()> {
+	let data = form.into_inner();
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+
+	let refresh_first =
+		data.token_type_hint.as_deref() == Some("refresh_token");
+	if refresh_first && revoke_refresh_token(&client, &data.token, &db).await? {
+		return Ok(());
+	}
+	if revoke_access_token(&client, &data.token, jwt_builder, &db).await? {
+		return Ok(());
+	}
+	if !refresh_first {
+		revoke_refresh_token(&client, &data.token, &db).await?;
+	}
+	Ok(())
+}
+
+/// Returns whether the token was a refresh token issued to this client.
+async fn revoke_refresh_token(
+	client: &Client,
+	token: &str,
+	db: &DbConn,
+) -> Result<bool> {
+	match RefreshToken::find_by_key(token.to_string(), db).await {
+		Ok((refresh_token, session))
+			if session.client_id == Some(client.id) =>
+		{
+			refresh_token.revoke_family(db).await?;
+			Ok(true)
+		},
+		Ok(_) | Err(ZauthError::NotFound(_)) => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+/// Returns whether the token was an access token issued to this client.
+async fn revoke_access_token(
+	client: &Client,
+	token: &str,
+	jwt_builder: &JWTBuilder,
+	db: &DbConn,
+) -> Result<bool> {
+	match Session::find_by_access_token(token.to_string(), jwt_builder, db)
+		.await
+	{
+		Ok(session) if session.client_id == Some(client.id) => {
+			RefreshToken::revoke_for_session(&session, db).await?;
+			session.invalidate(db).await?;
+			Ok(true)
+		},
+		Ok(_) | Err(ZauthError::NotFound(_)) => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+#[derive(FromForm, Debug)]
+pub struct IntrospectFormData {
+	token: String,
+	client_id: Option<String>,
+	client_secret: Option<String>,
+	client_assertion_type: Option<String>,
+	client_assertion: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct IntrospectionResponse {
+	active: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	client_id: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	username: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	token_type: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sub: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	exp: Option<i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	iat: Option<i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	roles: Option<Vec<String>>,
+}
+
+impl IntrospectionResponse {
+	async fn active(
+		session: &Session,
+		token_type: &str,
+		expires_at: NaiveDateTime,
+		db: &DbConn,
+	) -> Result<Self> {
+		let client = session.client(db).await?;
+		let user = match session.user_id {
+			Some(_) => Some(session.user(db).await?),
+			None => None,
+		};
+		let roles = match (&user, &client) {
+			(Some(user), Some(client)) => {
+				user.clone().roles_for_client(client.id, db).await?
+			},
+			(None, Some(client)) => client.clone().roles(db).await?,
+			_ => vec![],
+		};
+		Ok(IntrospectionResponse {
+			active: true,
+			scope: session.scope.clone(),
+			client_id: client.map(|c| c.name),
+			sub: user.as_ref().map(|u| u.id.to_string()),
+			username: user.map(|u| u.username),
+			token_type: Some(token_type.to_string()),
+			exp: Some(expires_at.and_utc().timestamp()),
+			iat: Some(session.created_at.and_utc().timestamp()),
+			roles: Some(roles.into_iter().map(|r| r.name).collect()),
+		})
+	}
+}
+
+/// Describe an access or refresh token to a resource server (RFC 7662).
+/// Unknown, expired and revoked tokens are reported as inactive.
+#[post("/oauth/introspect", data = "<form>")]
+pub async fn introspect(
+	auth: Option<BasicAuthentication>,
+	form: Form<IntrospectFormData>,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<Json<IntrospectionResponse>> {
+	let data = form.into_inner();
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+	// Only resource servers, which can keep a secret, may look into tokens
+	if client.public {
+		return Err(ZauthError::from(AuthenticationError::Unauthorized(
+			client.name,
+		)));
+	}
+
+	match Session::find_by_access_token(data.token.clone(), jwt_builder, &db)
+		.await
+	{
+		Ok(session) if session.client_id.is_some() => {
+			return Ok(Json(
+				IntrospectionResponse::active(
+					&session,
+					"bearer",
+					session.expires_at,
+					&db,
+				)
+				.await?,
+			));
+		},
+		Ok(_) | Err(ZauthError::NotFound(_)) => (),
+		Err(e) => return Err(e),
+	}
+
+	match RefreshToken::find_by_key(data.token, &db).await {
+		Ok((refresh_token, session)) if !refresh_token.used => Ok(Json(
+			IntrospectionResponse::active(
+				&session,
+				"refresh_token",
+				refresh_token.expires_at,
+				&db,
+			)
+			.await?,
+		)),
+		Ok(_) | Err(ZauthError::NotFound(_)) => {
+			Ok(Json(IntrospectionResponse::default()))
+		},
+		Err(e) => Err(e),
+	}
+}
+
+#[derive(FromForm, Debug)]
+pub struct DeviceAuthorizationFormData {
+	client_id: Option<String>,
+	client_secret: Option<String>,
+	client_assertion_type: Option<String>,
+	client_assertion: Option<String>,
+	scope: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeviceAuthorizationResponse {
+	device_code: String,
+	user_code: String,
+	verification_uri: String,
+	verification_uri_complete: String,
+	expires_in: i64,
+	interval: i64,
+}
+
+/// Start the device authorization grant (RFC 8628) for devices that can't
+/// open a browser themselves.
+#[post("/oauth/device_authorization", data = "<form>")]
+pub async fn device_authorization(
+	auth: Option<BasicAuthentication>,
+	form: Form<DeviceAuthorizationFormData>,
+	http_client: &State<HttpClient>,
+	config: &State<Config>,
+	device_store: &State<DeviceStore>,
+	db: DbConn,
+) -> Result<Json<DeviceAuthorizationResponse>> {
+	let data = form.into_inner();
+	let client = authenticate_client(
+		auth,
+		data.client_id,
+		data.client_secret,
+		ClientAssertion::from_form(
+			data.client_assertion_type,
+			data.client_assertion,
+		)?,
+		http_client,
+		config,
+		&db,
+	)
+	.await?;
+
+	let scope = Scope::permitted(&client, &data.scope, &db).await?;
+	let (device_code, user_code) =
+		device_store.create(&client, scope, &db).await?;
+	let user_code = format_user_code(&user_code);
+	Ok(Json(DeviceAuthorizationResponse {
+		device_code,
+		verification_uri: uri!(config.base_url(), device_get(_)).to_string(),
+		verification_uri_complete: uri!(
+			config.base_url(),
+			device_get(Some(user_code.clone()))
+		)
+		.to_string(),
+		user_code,
+		expires_in: device_store.validity.num_seconds(),
+		interval: device_store.interval.num_seconds(),
+	}))
+}
+
+#[get("/oauth/device?<user_code>")]
+pub async fn device_get<'r>(
+	session: Option<UserSession>,
+	user_code: Option<String>,
+	cookies: &CookieJar<'_>,
+) -> Either<Redirect, impl Responder<'r, 'static> + use<'r>> {
+	match session {
+		Some(_) => Right(template! {
+			"oauth/device.html";
+			device_post_url: String = uri!(device_post).to_string(),
+			user_code: String = user_code.unwrap_or_default(),
+			error: Option<String> = None,
+		}),
+		None => Left(ensure_logged_in_and_redirect(
+			cookies,
+			uri!(device_get(user_code)),
+			None,
+		)),
+	}
+}
+
+#[derive(FromForm, Debug)]
+pub struct DeviceFormData {
+	user_code: String,
+}
+
+#[post("/oauth/device", data = "<form>")]
+pub async fn device_post<'r>(
+	_session: UserSession,
+	form: Form<DeviceFormData>,
+	cookies: &CookieJar<'_>,
+	device_store: &State<DeviceStore>,
+	db: DbConn,
+) -> Result<
+	Either<
+		impl Responder<'r, 'static> + use<'r>,
+		impl Responder<'r, 'static> + use<'r>,
+	>,
+> {
+	let user_code = form.into_inner().user_code;
+	match device_store.find_pending(&user_code, &db).await? {
+		Some(authorization) => {
+			let client = Client::find(authorization.client_id, &db).await?;
+			cookies.add_private(Cookie::new(
+				DEVICE_COOKIE,
+				authorization.user_code,
+			));
+			let scopes =
+				Scope::find_by_names(split_scopes(&authorization.scope), &db)
+					.await?;
+			Ok(Left(template! {
+				"oauth/grant.html";
+				client_description: String = client.description,
+				scopes: Vec<Scope> = scopes,
+				grant_post_url: String = uri!(device_grant).to_string(),
+			}))
+		},
+		None => Ok(Right(template! {
+			"oauth/device.html";
+			device_post_url: String = uri!(device_post).to_string(),
+			user_code: String = user_code,
+			error: Option<String> =
+				Some(String::from("This code is invalid or has expired.")),
+		})),
+	}
+}
+
+#[post("/oauth/device/grant", data = "<form>")]
+pub async fn device_grant<'r>(
+	session: UserSession,
+	form: Form<GrantFormData>,
+	cookies: &CookieJar<'_>,
+	device_store: &State<DeviceStore>,
+	db: DbConn,
+) -> Result<
+	Either<
+		impl Responder<'r, 'static> + use<'r>,
+		impl Responder<'r, 'static> + use<'r>,
+	>,
+> {
+	let user_code = cookies
+		.get_private(DEVICE_COOKIE)
+		.map(|cookie| cookie.value().to_string())
+		.ok_or(ZauthError::OAuth(InvalidCookie))?;
+	cookies.remove_private(Cookie::from(DEVICE_COOKIE));
+
+	let granted = form.into_inner().grant;
+	let state = if granted {
+		DeviceAuthorizationState::Granted {
+			user_id: session.user.id,
+			auth_time: session.authenticated_at(),
+			sid: session.sid(),
+		}
+	} else {
+		DeviceAuthorizationState::Denied
+	};
+
+	if device_store.resolve(&user_code, state, &db).await? {
+		Ok(Left(template! {
+			"oauth/device_done.html";
+			granted: bool = granted,
+		}))
+	} else {
+		Ok(Right(template! {
+			"oauth/device.html";
+			device_post_url: String = uri!(device_post).to_string(),
+			user_code: String = String::new(),
+			error: Option<String> =
+				Some(String::from("This code is invalid or has expired.")),
+		}))
+	}
+}
+
+/// The standard OpenID Connect claims about a user. Which claims are present
+/// depends on the scope of the access token.
+#[derive(Serialize, Debug)]
+pub struct UserInfoClaims {
+	sub: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	preferred_username: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	picture: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	email: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	email_verified: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	roles: Option<Vec<String>>,
+}
+
+impl UserInfoClaims {
+	async fn new(
+		user: User,
+		client: &Client,
+		scope: &Option<String>,
+		config: &Config,
+		db: &DbConn,
+	) -> Result<Self> {
+		let scopes = split_scopes(scope);
+		let profile = scopes.contains(&"profile".into());
+		let email = scopes.contains(&"email".into());
+		let roles = if scopes.contains(&"roles".into()) {
+			Some(
+				user.clone()
+					.roles_for_client(client.id, db)
+					.await?
+					.into_iter()
+					.map(|r| r.name)
+					.collect(),
+			)
+		} else {
+			None
+		};
+
+		Ok(UserInfoClaims {
+			sub: user.id.to_string(),
+			name: profile.then(|| user.full_name.clone()),
+			preferred_username: profile.then(|| user.username.clone()),
+			picture: profile
+				.then(|| format!("{}{}", config.picture_url_prefix(), user.id)),
+			// Like `/current_user`, we hand out the address in our own mail
+			// domain instead of the backing address of the user.
+			email: email.then(|| {
+				format!("{}@{}", user.username, config.user_mail_domain)
+			}),
+			email_verified: email.then_some(true),
+			roles,
+		})
+	}
+}
+
+#[derive(Serialize, Debug)]
+struct SignedUserInfo {
+	iss: String,
+	aud: String,
+	#[serde(flatten)]
+	claims: UserInfoClaims,
+}
+
+async fn userinfo_response(
+	session: UserClientSession,
+	config: &Config,
+	jwt_builder: &JWTBuilder,
+	db: &DbConn,
+) -> Result<Either<Json<UserInfoClaims>, (ContentType, String)>> {
+	// The userinfo endpoint is part of OpenID Connect, which the client has to
+	// have been granted
+	if !split_scopes(&session.scope).contains(&"openid".into()) {
+		return Err(AuthenticationError::InsufficientScope(
+			"openid".to_string(),
+		)
+		.into());
+	}
+	let claims = UserInfoClaims::new(
+		session.user,
+		&session.client,
+		&session.scope,
+		config,
+		db,
+	)
+	.await?;
+	if session.client.userinfo_signed {
+		let jwt = jwt_builder.encode(&SignedUserInfo {
+			iss: config.base_url().to_string(),
+			aud: session.client.name,
+			claims,
+		})?;
+		Ok(Right((ContentType::new("application", "jwt"), jwt)))
+	} else {
+		Ok(Left(Json(claims)))
+	}
+}
+
+#[get("/oauth/userinfo")]
+pub async fn userinfo_get(
+	session: UserClientSession,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<Either<Json<UserInfoClaims>, (ContentType, String)>> {
+	userinfo_response(session, config, jwt_builder, &db).await
+}
+
+#[post("/oauth/userinfo")]
+pub async fn userinfo_post(
+	session: UserClientSession,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<Either<Json<UserInfoClaims>, (ContentType, String)>> {
+	userinfo_response(session, config, jwt_builder, &db).await
+}
+
+#[derive(FromForm, Debug)]
+pub struct LogoutRequest {
+	id_token_hint: Option<String>,
+	client_id: Option<String>,
+	post_logout_redirect_uri: Option<String>,
+	state: Option<String>,
+	confirmed: bool,
+	logout_client: bool,
+}
+
+impl LogoutRequest {
+	/// Find the client that wants to log the user out, and check that it may
+	/// send the user to the given post logout redirect URI.
+	async fn client(
+		&self,
+		jwt_builder: &JWTBuilder,
+		db: &DbConn,
+	) -> Result<Option<Client>> {
+		let hinted_client = match &self.id_token_hint {
+			Some(hint) => Some(
+				jwt_builder
+					.decode_id_token_hint(hint)
+					.ok_or(ZauthError::from(OAuthError::InvalidRequest))?,
+			),
+			None => None,
+		};
+		let client_name = match (hinted_client, &self.client_id) {
+			(Some(hinted), Some(given)) if hinted != *given => {
+				return Err(ZauthError::from(OAuthError::InvalidRequest));
+			},
+			(Some(hinted), _) => Some(hinted),
+			(None, given) => given.clone(),
+		};
+		let client = match client_name {
+			Some(name) => match Client::find_by_name(name.clone(), db).await {
+				Ok(client) => Some(client),
+				Err(ZauthError::NotFound(_)) => {
+					return Err(AuthenticationError::Unauthorized(format!(
+						"client with id {} is not authorized on this server",
+						name
+					))
+					.into());
+				},
+				Err(err) => return Err(err),
+			},
+			None => None,
+		};
+		if let Some(uri) = &self.post_logout_redirect_uri
+			&& !client.as_ref().is_some_and(|client| {
+				client.post_logout_redirect_uri_acceptable(uri)
+			}) {
+			return Err(AuthenticationError::Unauthorized(format!(
+				"client is not authorized to use post_logout_redirect_uri '{}'",
+				uri
+			))
+			.into());
+		}
+		Ok(client)
+	}
+
+	fn redirect(self) -> Redirect {
+		match (self.post_logout_redirect_uri, self.state) {
+			(Some(uri), Some(state)) => {
+				let separator = if uri.contains('?') { '&' } else { '?' };
+				Redirect::to(format!(
+					"{}{}state={}",
+					uri,
+					separator,
+					urlencoding::encode(&state)
+				))
+			},
+			(Some(uri), None) => Redirect::to(uri),
+			(None, _) => Redirect::to("/"),
+		}
+	}
+}
+
+/// Ask the user to confirm they want to log out. Cookies aren't sent along
+/// with requests coming from other sites, so we can only see who is logged
+/// in once the user submits this page.
+fn logout_confirmation<'r>(
+	req: LogoutRequest,
+	client: Option<Client>,
+) -> impl Responder<'r, 'static> {
+	template! {
+		"oauth/logout.html";
+		logout_post_url: String = uri!(end_session_post).to_string(),
+		client_description: Option<String> =
+			client.map(|client| client.description),
+		id_token_hint: Option<String> = req.id_token_hint,
+		client_id: Option<String> = req.client_id,
+		post_logout_redirect_uri: Option<String> =
+			req.post_logout_redirect_uri,
+		state: Option<String> = req.state,
+	}
+}
+
+/// RP-initiated logout (OpenID Connect RP-Initiated Logout 1.0).
+#[get("/oauth/logout?<req..>")]
+pub async fn end_session_get<'r>(
+	req: LogoutRequest,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<impl Responder<'r, 'static>> {
+	let client = req.client(jwt_builder, &db).await?;
+	Ok(logout_confirmation(req, client))
+}
+
+#[post("/oauth/logout", data = "<form>")]
+pub async fn end_session_post<'r>(
+	session: Option<UserSession>,
+	cookies: &CookieJar<'_>,
+	form: Form<LogoutRequest>,
+	logout_notifier: &State<LogoutNotifier>,
+	jwt_builder: &State<JWTBuilder>,
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Either<impl Responder<'r, 'static>, Redirect>> {
+	let req = form.into_inner();
+	let client = req.client(jwt_builder, &db).await?;
+	if !req.confirmed {
+		return Ok(Left(logout_confirmation(req, client)));
+	}
+	if let Some(session) = session {
+		if req.logout_client
+			&& let Some(client) = &client
+		{
+			Session::invalidate_for_client(session.user.id, client.id, &db)
+				.await?;
+		}
+		let session = session.destroy(cookies, &db).await?;
+		logout_notifier
+			.session_ended(&session, jwt_builder, config, &db)
+			.await?;
+	}
+	Ok(Right(req.redirect()))
+}
+
 #[get("/oauth/jwks")]
 pub async fn jwks(jwt_builder: &State<JWTBuilder>) -> Json<JwkSet> {
-	Json(jwt_builder.jwks.clone())
+	Json(jwt_builder.jwks())
+}
+
+/// Server metadata as described by OpenID Connect Discovery 1.0 and RFC 8414.
+/// Both documents share the same fields, so one struct serves both.
+#[derive(Serialize, Debug)]
+pub struct ProviderMetadata {
+	issuer: String,
+	authorization_endpoint: String,
+	token_endpoint: String,
+	device_authorization_endpoint: String,
+	revocation_endpoint: String,
+	introspection_endpoint: String,
+	userinfo_endpoint: String,
+	end_session_endpoint: String,
+	registration_endpoint: String,
+	pushed_authorization_request_endpoint: String,
+	require_pushed_authorization_requests: bool,
+	request_parameter_supported: bool,
+	request_uri_parameter_supported: bool,
+	require_request_uri_registration: bool,
+	request_object_signing_alg_values_supported: Vec<String>,
+	authorization_response_iss_parameter_supported: bool,
+	backchannel_logout_supported: bool,
+	backchannel_logout_session_supported: bool,
+	jwks_uri: String,
+	scopes_supported: Vec<String>,
+	response_types_supported: Vec<&'static str>,
+	response_modes_supported: Vec<&'static str>,
+	grant_types_supported: Vec<&'static str>,
+	subject_types_supported: Vec<&'static str>,
+	id_token_signing_alg_values_supported: Vec<&'static str>,
+	userinfo_signing_alg_values_supported: Vec<&'static str>,
+	token_endpoint_auth_methods_supported: Vec<&'static str>,
+	token_endpoint_auth_signing_alg_values_supported: Vec<String>,
+	code_challenge_methods_supported: Vec<&'static str>,
+	claims_supported: Vec<&'static str>,
+}
+
+impl ProviderMetadata {
+	/// The registered scopes are the ones clients can request.
+	pub fn new(config: &Config, scopes: Vec<Scope>) -> Self {
+		let issuer = config.base_url().to_string();
+		let endpoint =
+			|path: &str| format!("{}{}", issuer.trim_end_matches('/'), path);
+		// Secrets can only be kept for client_secret_jwt with a key to
+		// encrypt them
+		let secret_jwt = config.client_secret_key.is_some();
+		ProviderMetadata {
+			authorization_endpoint: endpoint("/oauth/authorize"),
+			token_endpoint: endpoint("/oauth/token"),
+			device_authorization_endpoint: endpoint(
+				"/oauth/device_authorization",
+			),
+			revocation_endpoint: endpoint("/oauth/revoke"),
+			introspection_endpoint: endpoint("/oauth/introspect"),
+			userinfo_endpoint: endpoint("/oauth/userinfo"),
+			end_session_endpoint: endpoint("/oauth/logout"),
+			registration_endpoint: endpoint("/oauth/register"),
+			pushed_authorization_request_endpoint: endpoint("/oauth/par"),
+			// Only clients that are configured to do so have to push requests
+			require_pushed_authorization_requests: false,
+			request_parameter_supported: true,
+			request_uri_parameter_supported: true,
+			require_request_uri_registration: true,
+			request_object_signing_alg_values_supported: KEY_ALGORITHMS
+				.iter()
+				.map(|alg| format!("{:?}", alg))
+				.collect(),
+			authorization_response_iss_parameter_supported: true,
+			backchannel_logout_supported: true,
+			backchannel_logout_session_supported: true,
+			jwks_uri: endpoint("/oauth/jwks"),
+			scopes_supported: scopes
+				.into_iter()
+				.map(|scope| scope.name)
+				.collect(),
+			response_types_supported: vec!["code"],
+			response_modes_supported: vec!["query", "fragment", "form_post"],
+			grant_types_supported: vec![
+				"authorization_code",
+				"refresh_token",
+				"client_credentials",
+				DEVICE_CODE_GRANT_TYPE,
+				TOKEN_EXCHANGE_GRANT_TYPE,
+			],
+			subject_types_supported: vec!["public"],
+			id_token_signing_alg_values_supported: vec!["ES384"],
+			userinfo_signing_alg_values_supported: vec!["ES384"],
+			token_endpoint_auth_methods_supported: [
+				"client_secret_basic",
+				"client_secret_post",
+				"client_secret_jwt",
+				"private_key_jwt",
+				"none",
+			]
+			.into_iter()
+			.filter(|method| secret_jwt || *method != "client_secret_jwt")
+			.collect(),
+			token_endpoint_auth_signing_alg_values_supported: SECRET_ALGORITHMS
+				.iter()
+				.filter(|_| secret_jwt)
+				.chain(KEY_ALGORITHMS.iter())
+				.map(|alg| format!("{:?}", alg))
+				.collect(),
+			code_challenge_methods_supported: vec!["plain", "S256"],
+			claims_supported: vec![
+				"sub",
+				"iss",
+				"aud",
+				"exp",
+				"iat",
+				"auth_time",
+				"azp",
+				"nonce",
+				"sid",
+				"name",
+				"preferred_username",
+				"email",
+				"email_verified",
+				"picture",
+				"roles",
+			],
+			issuer,
+		}
+	}
+}
+
+#[get("/.well-known/openid-configuration")]
+pub async fn openid_configuration(
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Json<ProviderMetadata>> {
+	let scopes = Scope::all(&db).await?;
+	Ok(Json(ProviderMetadata::new(config, scopes)))
+}
+
+#[get("/.well-known/oauth-authorization-server")]
+pub async fn authorization_server_metadata(
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Json<ProviderMetadata>> {
+	let scopes = Scope::all(&db).await?;
+	Ok(Json(ProviderMetadata::new(config, scopes)))
 }
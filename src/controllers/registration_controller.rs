@@ -0,0 +1,462 @@
+use diesel::result::DatabaseErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use rocket::State;
+use rocket::http::Status;
+use rocket::http::uri::Absolute;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::status;
+use rocket::serde::json::{Json, Value};
+
+use crate::DbConn;
+use crate::config::Config;
+use crate::errors::{
+	AuthenticationError, InternalError, OAuthError, Result, ZauthError,
+};
+use crate::jwt::JWTBuilder;
+use crate::models::client::{Client, ClientChange, NewClient};
+use crate::models::client_secret::{ClientSecret, NewClientSecret};
+use crate::models::initial_access_token::InitialAccessToken;
+use crate::models::scope::Scope;
+use crate::models::session::Session;
+use crate::redirect_uri;
+use crate::util::{hash_token, random_token};
+
+/// The token in the `Authorization: Bearer` header of a request.
+pub struct BearerToken(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+	type Error = &'static str;
+
+	async fn from_request(
+		request: &'r Request<'_>,
+	) -> request::Outcome<Self, Self::Error> {
+		match request
+			.headers()
+			.get_one("Authorization")
+			.and_then(|header| header.strip_prefix("Bearer "))
+		{
+			Some(token) => Outcome::Success(BearerToken(token.trim().into())),
+			None => Outcome::Error((Status::Unauthorized, "no bearer token")),
+		}
+	}
+}
+
+/// The client metadata a client registers itself with (RFC 7591, section 2).
+/// Next to the standard fields, clients can give a `description` that is
+/// shown to users on the grant page.
+#[derive(Deserialize, Debug)]
+pub struct ClientMetadata {
+	client_id: Option<String>,
+	client_name: Option<String>,
+	description: Option<String>,
+	#[serde(default)]
+	redirect_uris: Vec<String>,
+	#[serde(default)]
+	post_logout_redirect_uris: Vec<String>,
+	backchannel_logout_uri: Option<String>,
+	jwks: Option<JwkSet>,
+	jwks_uri: Option<String>,
+	scope: Option<String>,
+	token_endpoint_auth_method: Option<String>,
+	#[serde(default)]
+	require_pushed_authorization_requests: bool,
+	#[serde(default)]
+	require_signed_request_object: bool,
+	#[serde(default)]
+	request_uris: Vec<String>,
+}
+
+/// The registered metadata of a client together with the credentials it got
+/// (RFC 7591, section 3.2.1). Client secrets and registration access tokens
+/// are only stored hashed, so they are only included in the response that
+/// created them.
+#[derive(Serialize, Debug)]
+pub struct ClientInformation {
+	client_id: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	client_secret: Option<String>,
+	client_id_issued_at: i64,
+	client_secret_expires_at: i64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	registration_access_token: Option<String>,
+	registration_client_uri: String,
+	client_name: String,
+	description: String,
+	redirect_uris: Vec<String>,
+	post_logout_redirect_uris: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	backchannel_logout_uri: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	jwks: Option<Value>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	jwks_uri: Option<String>,
+	scope: String,
+	token_endpoint_auth_method: &'static str,
+	require_pushed_authorization_requests: bool,
+	require_signed_request_object: bool,
+	request_uris: Vec<String>,
+}
+
+impl ClientInformation {
+	fn new(
+		client: Client,
+		client_secret: Option<String>,
+		registration_access_token: Option<String>,
+		config: &Config,
+	) -> ClientInformation {
+		let lines = |list: &str| list.lines().map(String::from).collect();
+		ClientInformation {
+			registration_client_uri: uri!(
+				config.base_url(),
+				read_registration(&client.name)
+			)
+			.to_string(),
+			client_id_issued_at: client.created_at.and_utc().timestamp(),
+			client_secret_expires_at: 0,
+			redirect_uris: lines(&client.redirect_uri_list),
+			post_logout_redirect_uris: lines(
+				&client.post_logout_redirect_uri_list,
+			),
+			backchannel_logout_uri: client
+				.backchannel_logout_uri()
+				.map(String::from),
+			jwks: client
+				.jwks()
+				.and_then(|jwks| serde_json::from_str(jwks).ok()),
+			jwks_uri: client.jwks_uri().map(String::from),
+			client_id: client.name.clone(),
+			client_name: client.name,
+			client_secret,
+			require_pushed_authorization_requests: client.par_required,
+			require_signed_request_object: client.signed_request_required,
+			request_uris: lines(&client.request_uri_list),
+			registration_access_token,
+			description: client.description,
+			scope: client.allowed_scopes,
+			token_endpoint_auth_method: if client.public {
+				"none"
+			} else if client.secret_jwt_allowed {
+				"client_secret_jwt"
+			} else {
+				"client_secret_basic"
+			},
+		}
+	}
+}
+
+impl ClientMetadata {
+	fn into_change(self, config: &Config) -> Result<ClientChange> {
+		// Redirect URI patterns can only be configured by admins
+		for uri in &self.redirect_uris {
+			if uri.starts_with(redirect_uri::PATTERN_PREFIX)
+				|| Absolute::parse(uri).is_err()
+			{
+				return Err(ZauthError::from(OAuthError::InvalidRedirectUri(
+					uri.clone(),
+				)));
+			}
+		}
+		for uri in &self.post_logout_redirect_uris {
+			if Absolute::parse(uri).is_err() {
+				return Err(ZauthError::from(
+					OAuthError::InvalidClientMetadata(format!(
+						"invalid post logout redirect URI '{}'",
+						uri
+					)),
+				));
+			}
+		}
+		if let Some(uri) = &self.backchannel_logout_uri
+			&& !Absolute::parse(uri).is_ok_and(|uri| uri.scheme() == "https")
+		{
+			return Err(ZauthError::from(OAuthError::InvalidClientMetadata(
+				format!("backchannel logout URI '{}' has to use https", uri),
+			)));
+		}
+		// Clients that don't authenticate at all are public clients, which
+		// don't get a secret.
+		let (public, secret_jwt_allowed) = match self
+			.token_endpoint_auth_method
+			.as_deref()
+		{
+			None
+			| Some("client_secret_basic")
+			| Some("client_secret_post")
+			| Some("private_key_jwt") => (false, false),
+			Some("client_secret_jwt") if config.client_secret_key.is_some() => {
+				(false, true)
+			},
+			Some("none") => (true, false),
+			Some(method) => {
+				return Err(ZauthError::from(
+					OAuthError::InvalidClientMetadata(format!(
+						"unsupported token_endpoint_auth_method '{}'",
+						method
+					)),
+				));
+			},
+		};
+		let jwks = match self.jwks {
+			Some(jwks) => {
+				serde_json::to_string(&jwks).map_err(InternalError::from)?
+			},
+			None => String::new(),
+		};
+		Ok(ClientChange {
+			name: None,
+			// We don't know who is behind a registered client, so users always
+			// have to agree before it gets their information.
+			needs_grant: Some(true),
+			description: Some(self.description.unwrap_or_default()),
+			redirect_uri_list: Some(self.redirect_uris.join("\n")),
+			pkce_required: None,
+			public: Some(public),
+			secret_jwt_allowed: Some(secret_jwt_allowed),
+			par_required: Some(self.require_pushed_authorization_requests),
+			signed_request_required: Some(self.require_signed_request_object),
+			userinfo_signed: None,
+			jwt_access_tokens: None,
+			allowed_scopes: self.scope,
+			post_logout_redirect_uri_list: Some(
+				self.post_logout_redirect_uris.join("\n"),
+			),
+			backchannel_logout_uri: Some(
+				self.backchannel_logout_uri.unwrap_or_default(),
+			),
+			token_exchange_audiences: None,
+			jwks: Some(jwks),
+			jwks_uri: Some(self.jwks_uri.unwrap_or_default()),
+			request_uri_list: Some(self.request_uris.join("\n")),
+		})
+	}
+}
+
+/// Clients can be registered with an initial access token, or with an access
+/// token of a user that has the client registration role.
+async fn registration_allowed(
+	token: BearerToken,
+	config: &Config,
+	jwt_builder: &JWTBuilder,
+	db: &DbConn,
+) -> Result<bool> {
+	if InitialAccessToken::find_by_token(&token.0, db)
+		.await?
+		.is_some()
+	{
+		return Ok(true);
+	}
+	if config.client_registration_role.is_empty() {
+		return Ok(false);
+	}
+
+	let user = match Session::find_by_access_token(token.0, jwt_builder, db)
+		.await
+	{
+		Ok(session) if session.user_id.is_some() => session.user(db).await?,
+		Ok(_) | Err(ZauthError::NotFound(_)) => return Ok(false),
+		Err(e) => return Err(e),
+	};
+	Ok(user.is_active()
+		&& user
+			.roles(db)
+			.await?
+			.iter()
+			.any(|role| role.name == config.client_registration_role))
+}
+
+/// Apply the metadata to the client, reporting invalid metadata with the
+/// errors of RFC 7591.
+async fn apply_metadata(
+	client: &mut Client,
+	metadata: ClientMetadata,
+	config: &Config,
+	db: &DbConn,
+) -> Result<()> {
+	if let Some(scope) = &metadata.scope {
+		let names: Vec<String> =
+			scope.split_whitespace().map(String::from).collect();
+		let registered = Scope::find_by_names(names.clone(), db).await?;
+		if let Some(unknown) = names
+			.iter()
+			.find(|name| !registered.iter().any(|scope| &scope.name == *name))
+		{
+			return Err(ZauthError::from(OAuthError::InvalidClientMetadata(
+				format!("unknown scope '{}'", unknown),
+			)));
+		}
+	}
+	client
+		.change_with(metadata.into_change(config)?)
+		.map_err(|err| match err {
+			ZauthError::ValidationError(errors)
+				if errors.field_errors().contains_key("redirect_uri_list") =>
+			{
+				ZauthError::from(OAuthError::InvalidRedirectUri(
+					errors.to_string(),
+				))
+			},
+			ZauthError::ValidationError(errors) => ZauthError::from(
+				OAuthError::InvalidClientMetadata(errors.to_string()),
+			),
+			err => err,
+		})
+}
+
+/// Give a confidential client that has no secret it can use yet a new one.
+/// A client that switches to `client_secret_jwt` needs a secret we keep
+/// encrypted.
+async fn first_secret(
+	client: &Client,
+	config: &Config,
+	db: &DbConn,
+) -> Result<Option<String>> {
+	let secrets = ClientSecret::for_client(client, db).await?;
+	if client.public
+		|| secrets.iter().any(|secret| {
+			!client.secret_jwt_allowed || secret.encrypted_secret.is_some()
+		}) {
+		return Ok(None);
+	}
+	let secret = NewClientSecret {
+		label: "Registration".into(),
+		expires_in_days: None,
+	};
+	let (_, secret) = ClientSecret::create(client, secret, config, db).await?;
+	Ok(Some(secret))
+}
+
+/// Find the client a registration access token was issued to.
+async fn registered_client(
+	client_id: String,
+	token: BearerToken,
+	db: &DbConn,
+) -> Result<Client> {
+	match Client::find_by_name(client_id.clone(), db).await {
+		Ok(client) if client.registration_access_token_matches(&token.0) => {
+			Ok(client)
+		},
+		Ok(_) | Err(ZauthError::NotFound(_)) => Err(ZauthError::from(
+			AuthenticationError::Unauthorized(client_id),
+		)),
+		Err(e) => Err(e),
+	}
+}
+
+/// Register a new client (RFC 7591). The client gets a registration access
+/// token to manage its registration with afterwards.
+#[post("/oauth/register", data = "<metadata>")]
+pub async fn register(
+	token: BearerToken,
+	metadata: Json<ClientMetadata>,
+	config: &State<Config>,
+	jwt_builder: &State<JWTBuilder>,
+	db: DbConn,
+) -> Result<status::Created<Json<ClientInformation>>> {
+	if !registration_allowed(token, config, jwt_builder, &db).await? {
+		return Err(ZauthError::from(AuthenticationError::Unauthorized(
+			"client registration".into(),
+		)));
+	}
+
+	let mut metadata = metadata.into_inner();
+	let name = metadata.client_name.take().ok_or_else(|| {
+		OAuthError::InvalidClientMetadata("client_name is required".into())
+	})?;
+	let mut client = match Client::create(NewClient { name }, &db).await {
+		Ok(client) => client,
+		Err(ZauthError::ValidationError(errors)) => {
+			return Err(ZauthError::from(OAuthError::InvalidClientMetadata(
+				errors.to_string(),
+			)));
+		},
+		Err(ZauthError::Internal(InternalError::DatabaseError(
+			diesel::result::Error::DatabaseError(
+				DatabaseErrorKind::UniqueViolation,
+				_,
+			),
+		))) => {
+			return Err(ZauthError::from(OAuthError::InvalidClientMetadata(
+				"client_name is already taken".into(),
+			)));
+		},
+		Err(e) => return Err(e),
+	};
+	if let Err(err) = apply_metadata(&mut client, metadata, config, &db).await {
+		client.delete(&db).await?;
+		return Err(err);
+	}
+	let registration_access_token = random_token(config.secure_token_length);
+	client.hashed_registration_access_token =
+		Some(hash_token(&registration_access_token));
+	let client = client.update(&db).await?;
+	let secret = first_secret(&client, config, &db).await?;
+
+	let information = ClientInformation::new(
+		client,
+		secret,
+		Some(registration_access_token),
+		config,
+	);
+	Ok(
+		status::Created::new(information.registration_client_uri.clone())
+			.body(Json(information)),
+	)
+}
+
+/// Read the current registration of a client (RFC 7592, section 2.1).
+#[get("/oauth/register/<client_id>")]
+pub async fn read_registration(
+	client_id: String,
+	token: BearerToken,
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Json<ClientInformation>> {
+	let client = registered_client(client_id, token, &db).await?;
+	Ok(Json(ClientInformation::new(client, None, None, config)))
+}
+
+/// Replace the registered metadata of a client (RFC 7592, section 2.2). The
+/// name is the client id, so it can't be changed.
+#[put("/oauth/register/<client_id>", data = "<metadata>")]
+pub async fn update_registration(
+	client_id: String,
+	token: BearerToken,
+	metadata: Json<ClientMetadata>,
+	config: &State<Config>,
+	db: DbConn,
+) -> Result<Json<ClientInformation>> {
+	let mut client = registered_client(client_id, token, &db).await?;
+	let metadata = metadata.into_inner();
+	if metadata
+		.client_id
+		.as_ref()
+		.is_some_and(|id| *id != client.name)
+		|| metadata
+			.client_name
+			.as_ref()
+			.is_some_and(|name| *name != client.name)
+	{
+		return Err(ZauthError::from(OAuthError::InvalidClientMetadata(
+			"the client id and name can't be changed".into(),
+		)));
+	}
+	apply_metadata(&mut client, metadata, config, &db).await?;
+	let client = client.update(&db).await?;
+	// A public client that becomes confidential needs a secret
+	let secret = first_secret(&client, config, &db).await?;
+	Ok(Json(ClientInformation::new(client, secret, None, config)))
+}
+
+/// Remove a registered client (RFC 7592, section 2.3).
+#[delete("/oauth/register/<client_id>")]
+pub async fn delete_registration(
+	client_id: String,
+	token: BearerToken,
+	db: DbConn,
+) -> Result<Status> {
+	let client = registered_client(client_id, token, &db).await?;
+	client.delete(&db).await?;
+	Ok(Status::NoContent)
+}
@@ -0,0 +1,76 @@
+use diesel::result::DatabaseErrorKind;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::{Redirect, Responder, status};
+use rocket::serde::json::Json;
+
+use crate::DbConn;
+use crate::ephemeral::from_api::Api;
+use crate::ephemeral::session::AdminSession;
+use crate::errors::{Either, InternalError, Result, ZauthError};
+use crate::models::scope::{NewScope, Scope};
+use crate::models::user::User;
+use crate::views::accepter::Accepter;
+
+#[get("/scopes?<error>")]
+pub async fn list_scopes<'r>(
+	error: Option<String>,
+	db: DbConn,
+	session: AdminSession,
+) -> Result<impl Responder<'r, 'static>> {
+	let scopes = Scope::all(&db).await?;
+
+	Ok(Accepter {
+		html: template! {
+			"scopes/index.html";
+			scopes: Vec<Scope> = scopes.clone(),
+			error: Option<String> = error,
+			current_user: User = session.admin,
+		},
+		json: Json(scopes),
+	})
+}
+
+#[post("/scopes", data = "<scope>")]
+pub async fn create_scope<'r, 'a>(
+	scope: Api<NewScope>,
+	db: DbConn,
+	_admin: AdminSession,
+) -> Result<
+	Either<impl Responder<'a, 'static>, impl Responder<'r, 'static> + use<'r>>,
+> {
+	let scope = Scope::create(scope.into_inner(), &db).await;
+	match scope {
+		Ok(scope) => Ok(Either::Left(Accepter {
+			html: Redirect::to(uri!(list_scopes(None::<String>))),
+			json: status::Created::new(String::from("/scope"))
+				.body(Json(scope)),
+		})),
+		Err(ZauthError::Internal(InternalError::DatabaseError(
+			diesel::result::Error::DatabaseError(
+				DatabaseErrorKind::UniqueViolation,
+				_,
+			),
+		))) => Ok(Either::Right(Accepter {
+			html: Redirect::to(uri!(list_scopes(Some(
+				"scope name already exists"
+			)))),
+			json: Custom(Status::Conflict, "scope name already exists"),
+		})),
+		Err(err) => Err(err),
+	}
+}
+
+#[delete("/scopes/<id>")]
+pub async fn delete_scope<'r>(
+	id: i32,
+	_session: AdminSession,
+	db: DbConn,
+) -> Result<impl Responder<'r, 'static>> {
+	let scope = Scope::find(id, &db).await?;
+	scope.delete(&db).await?;
+	Ok(Accepter {
+		html: Redirect::to(uri!(list_scopes(None::<String>))),
+		json: Custom(Status::NoContent, ()),
+	})
+}
@@ -4,26 +4,34 @@ use rocket::response::{Redirect, Responder};
 
 use crate::Config;
 use crate::DbConn;
+use crate::backchannel_logout::LogoutNotifier;
 use crate::controllers::pages_controller::rocket_uri_macro_home_page;
 use crate::ephemeral::session::{
 	SessionCookie, UserSession, stored_redirect_or,
 };
 use crate::errors::{Either, Result, ZauthError};
+use crate::jwt::JWTBuilder;
 use crate::models::session::Session;
 use crate::models::user::User;
 use rocket::http::CookieJar;
 
-#[get("/login")]
+/// Users that are logged in are sent on, unless they have to `reauthenticate`.
+#[get("/login?<login_hint>&<reauthenticate>")]
 pub fn new_session<'r>(
 	session: Option<UserSession>,
+	login_hint: Option<String>,
+	reauthenticate: Option<bool>,
 	cookies: &CookieJar,
 ) -> Either<Redirect, impl Responder<'r, 'static> + use<'r>> {
 	match session {
-		None => Either::Right(template! {
+		Some(_) if reauthenticate != Some(true) => {
+			Either::Left(stored_redirect_or(cookies, uri!(home_page)))
+		},
+		_ => Either::Right(template! {
 			"session/login.html";
-			error: Option<String> = None
+			error: Option<String> = None,
+			login_hint: Option<String> = login_hint,
 		}),
-		_ => Either::Left(stored_redirect_or(cookies, uri!(home_page))),
 	}
 }
 
@@ -41,10 +49,15 @@ pub struct LoginFormData {
 	password: String,
 }
 
+/// Logging in again as the same user keeps the session, so clients the user
+/// is logged in to are not affected. Logging in as someone else ends it.
 #[post("/login", data = "<form>")]
 pub async fn create_session<'r>(
 	form: Form<LoginFormData>,
+	session: Option<UserSession>,
 	cookies: &'r CookieJar<'_>,
+	logout_notifier: &'r State<LogoutNotifier>,
+	jwt_builder: &'r State<JWTBuilder>,
 	config: &'r State<Config>,
 	db: DbConn,
 ) -> Result<Either<Redirect, impl Responder<'r, 'static> + use<'r>>> {
@@ -54,13 +67,30 @@ pub async fn create_session<'r>(
 			Ok(Either::Right(template! {
 				"session/login.html";
 				error: Option<String> = Some(login_error.to_string()),
+				login_hint: Option<String> = None,
 			}))
 		},
 		Ok(user) => {
-			let session =
-				Session::create(&user, config.user_session_duration(), &db)
+			match session {
+				Some(session) if session.user.id == user.id => {
+					session.reauthenticate(&db).await?;
+				},
+				_ => {
+					if let Some(session) = session {
+						let session = session.destroy(cookies, &db).await?;
+						logout_notifier
+							.session_ended(&session, jwt_builder, config, &db)
+							.await?;
+					}
+					let session = Session::create(
+						&user,
+						config.user_session_duration(),
+						&db,
+					)
 					.await?;
-			SessionCookie::new(session).login(cookies);
+					SessionCookie::new(session).login(cookies);
+				},
+			}
 			user.update_last_login(&db).await?;
 			Ok(Either::Left(stored_redirect_or(cookies, uri!(home_page))))
 		},
@@ -72,8 +102,14 @@ pub async fn create_session<'r>(
 pub async fn destroy_session<'r>(
 	session: UserSession,
 	cookies: &'r CookieJar<'_>,
+	logout_notifier: &'r State<LogoutNotifier>,
+	jwt_builder: &'r State<JWTBuilder>,
+	config: &'r State<Config>,
 	db: DbConn,
 ) -> Result<Redirect> {
-	session.destroy(cookies, &db).await?;
+	let session = session.destroy(cookies, &db).await?;
+	logout_notifier
+		.session_ended(&session, jwt_builder, config, &db)
+		.await?;
 	Ok(Redirect::to("/"))
 }
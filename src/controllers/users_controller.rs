@@ -6,12 +6,14 @@ use rocket::response::{Redirect, Responder};
 use std::fmt::Debug;
 use validator::ValidationErrors;
 
+use crate::backchannel_logout::LogoutNotifier;
 use crate::config::{AdminEmail, Config};
 use crate::controllers::sessions_controller::rocket_uri_macro_new_session;
 use crate::ephemeral::from_api::Api;
 use crate::ephemeral::session::{AdminSession, UserClientSession, UserSession};
 use crate::errors::Either::{self, Left, Right};
 use crate::errors::{InternalError, OneOf, Result, ZauthError};
+use crate::jwt::JWTBuilder;
 use crate::mailer::Mailer;
 use crate::models::client::Client;
 use crate::models::role::Role;
@@ -364,11 +366,20 @@ pub async fn change_state<'r>(
 	username: String,
 	value: Api<ChangeStatus>,
 	_session: AdminSession,
+	logout_notifier: &'r State<LogoutNotifier>,
+	jwt_builder: &'r State<JWTBuilder>,
+	conf: &'r State<Config>,
 	db: DbConn,
 ) -> Result<impl Responder<'r, 'static>> {
 	let mut user = User::find_by_username(username, &db).await?;
+	let was_active = user.is_active();
 	user.state = value.into_inner().state;
 	let user = user.update(&db).await?;
+	if was_active && !user.is_active() {
+		logout_notifier
+			.user_logged_out(&user, jwt_builder, conf, &db)
+			.await?;
+	}
 	Ok(Accepter {
 		html: Redirect::to(uri!(show_user(user.username))),
 		json: Custom(Status::NoContent, ()),
@@ -386,7 +397,8 @@ pub async fn set_approved<'r>(
 	let user = User::find_by_username(username, &db).await?;
 	let user = user.approve(&db).await?;
 
-	let login_url = uri!(conf.base_url(), new_session);
+	let login_url =
+		uri!(conf.base_url(), new_session(None::<String>, None::<bool>));
 
 	mailer
 		.create(
@@ -261,6 +261,7 @@ pub async fn finish_authentication<'r>(
 			Ok(Either::Right(template! {
 				"session/login.html";
 				error: Option<String> = Some(login_error.to_string()),
+				login_hint: Option<String> = None,
 			}))
 		},
 		Err(e) => Err(e),
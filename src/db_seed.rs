@@ -1,6 +1,8 @@
 use crate::DbConn;
+use crate::config::Config;
 use crate::errors::{Result, ZauthError};
 use crate::models::client::{Client, NewClient};
+use crate::models::client_secret::{ClientSecret, NewClientSecret};
 use crate::models::schema::clients;
 use crate::models::schema::users;
 use crate::models::user::{NewUser, User};
@@ -54,21 +56,21 @@ impl Seeder {
 		seeder
 	}
 
-	pub async fn run(&self, bcrypt_cost: u32, db: &DbConn) -> Result<()> {
+	pub async fn run(&self, config: &Config, db: &DbConn) -> Result<()> {
 		if self.empty_db {
 			self.delete_all(db).await?;
 		}
 		if self.admin_password.is_some() {
-			self.seed_admin(bcrypt_cost, db).await?;
+			self.seed_admin(config.bcrypt_cost, db).await?;
 		}
 		if self.client_name.is_some() {
-			self.seed_client(db).await?;
+			self.seed_client(config, db).await?;
 		}
 		if self.clients_to_seed > 0 {
 			self.seed_clients(db).await?;
 		}
 		if self.users_to_seed > 0 {
-			self.seed_users(bcrypt_cost, db).await?;
+			self.seed_users(config.bcrypt_cost, db).await?;
 		}
 		Ok(())
 	}
@@ -153,7 +155,7 @@ impl Seeder {
 		Ok(())
 	}
 
-	async fn seed_client(&self, db: &DbConn) -> Result<()> {
+	async fn seed_client(&self, config: &Config, db: &DbConn) -> Result<()> {
 		let name = self.client_name.as_ref().expect("client name");
 		let client = Client::find_by_name(name.clone(), db).await;
 		if client.is_err() {
@@ -164,8 +166,21 @@ impl Seeder {
 				.as_ref()
 				.unwrap_or(&String::from(""))
 				.to_string();
-			client.update(db).await?;
-			eprintln!("Seeded client with name \"{}\"", name)
+			let client = client.update(db).await?;
+			let (_, secret) = ClientSecret::create(
+				&client,
+				NewClientSecret {
+					label: String::from("Seeded"),
+					expires_in_days: None,
+				},
+				config,
+				db,
+			)
+			.await?;
+			eprintln!(
+				"Seeded client with name \"{}\" and secret \"{}\"",
+				name, secret
+			)
 		}
 		Ok(())
 	}
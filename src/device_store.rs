@@ -0,0 +1,393 @@
+use crate::DbConn;
+use crate::config::{Config, TokenStoreBackend};
+use crate::errors::Result;
+use crate::models::client::Client;
+use crate::models::device_authorization::{
+	DeviceAuthorizationCode, StoredDeviceAuthorization,
+};
+use crate::util;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+use rand::{Rng, rng};
+use rocket::tokio::sync::Mutex;
+use std::collections::HashMap;
+
+/// User codes only use consonants that are hard to mistake for one another,
+/// so they can't spell words and are easy to type over (RFC 8628, section
+/// 6.1).
+const USER_CODE_CHARACTERS: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum DeviceAuthorizationState {
+	Pending,
+	Granted {
+		user_id: i32,
+		auth_time: NaiveDateTime,
+		sid: Option<String>,
+	},
+	Denied,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceAuthorization {
+	pub client_id: i32,
+	pub scope: Option<String>,
+	pub user_code: String,
+	pub state: DeviceAuthorizationState,
+	expiry: DateTime<Local>,
+	last_poll: Option<DateTime<Local>>,
+}
+
+/// The answer to a device polling the token endpoint.
+#[derive(Debug)]
+pub enum DevicePoll {
+	Invalid,
+	Pending,
+	SlowDown,
+	Expired,
+	Denied,
+	Granted {
+		user_id: i32,
+		scope: Option<String>,
+		auth_time: NaiveDateTime,
+		sid: Option<String>,
+	},
+}
+
+/// Keeps track of the device authorization requests that are waiting for a
+/// user to enter their user code. Like tokens, they are kept where
+/// `token_store` says, so devices can poll any instance of zauth.
+#[derive(Debug)]
+pub struct DeviceStore {
+	backend: Backend,
+	pub validity: Duration,
+	pub interval: Duration,
+	device_code_length: usize,
+}
+
+#[derive(Debug)]
+enum Backend {
+	Memory(Mutex<HashMap<String, DeviceAuthorization>>),
+	Database,
+}
+
+impl DeviceStore {
+	pub fn new(config: &Config) -> DeviceStore {
+		DeviceStore {
+			backend: match config.token_store {
+				TokenStoreBackend::Memory => {
+					Backend::Memory(Mutex::new(HashMap::new()))
+				},
+				TokenStoreBackend::Database => Backend::Database,
+			},
+			validity: config.device_code_duration(),
+			interval: config.device_poll_interval(),
+			device_code_length: config.secure_token_length,
+		}
+	}
+
+	/// Expired authorizations are kept around for another validity period,
+	/// so polling devices learn that their code expired instead of it being
+	/// unknown.
+	fn remove_stale(
+		&self,
+		authorizations: &mut HashMap<String, DeviceAuthorization>,
+	) {
+		let now = Local::now();
+		authorizations.retain(|_, auth| now < auth.expiry + self.validity);
+	}
+
+	async fn remove_stale_stored(&self, db: &DbConn) -> Result<()> {
+		let before = (Local::now() - self.validity).naive_utc();
+		StoredDeviceAuthorization::remove_expired_before(before, db).await
+	}
+
+	fn generate_user_code() -> String {
+		let mut rng = rng();
+		(0..USER_CODE_LENGTH)
+			.map(|_| {
+				let i = rng.random_range(0..USER_CODE_CHARACTERS.len());
+				USER_CODE_CHARACTERS[i] as char
+			})
+			.collect()
+	}
+
+	/// Start a new device authorization, returning the device code and the
+	/// user code.
+	pub async fn create(
+		&self,
+		client: &Client,
+		scope: Option<String>,
+		db: &DbConn,
+	) -> Result<(String, String)> {
+		let authorization = |user_code: String| DeviceAuthorization {
+			client_id: client.id,
+			scope: scope.clone(),
+			user_code,
+			state: DeviceAuthorizationState::Pending,
+			expiry: Local::now() + self.validity,
+			last_poll: None,
+		};
+		match &self.backend {
+			Backend::Memory(authorizations) => {
+				let mut authorizations = authorizations.lock().await;
+				self.remove_stale(&mut authorizations);
+
+				let mut device_code =
+					util::random_token(self.device_code_length);
+				while authorizations.contains_key(&device_code) {
+					device_code = util::random_token(self.device_code_length);
+				}
+				let mut user_code = Self::generate_user_code();
+				while authorizations.values().any(|a| a.user_code == user_code)
+				{
+					user_code = Self::generate_user_code();
+				}
+
+				authorizations.insert(
+					device_code.clone(),
+					authorization(user_code.clone()),
+				);
+				Ok((device_code, user_code))
+			},
+			Backend::Database => {
+				self.remove_stale_stored(db).await?;
+				loop {
+					let device_code =
+						util::random_token(self.device_code_length);
+					let user_code = Self::generate_user_code();
+					let stored = authorization(user_code.clone())
+						.into_stored(device_code.clone());
+					if stored.insert(db).await? {
+						return Ok((device_code, user_code));
+					}
+				}
+			},
+		}
+	}
+
+	/// Find the pending authorization a user code belongs to.
+	pub async fn find_pending(
+		&self,
+		user_code: &str,
+		db: &DbConn,
+	) -> Result<Option<DeviceAuthorization>> {
+		let user_code = normalize_user_code(user_code);
+		let now = Local::now();
+		let authorization = match &self.backend {
+			Backend::Memory(authorizations) => authorizations
+				.lock()
+				.await
+				.values()
+				.find(|auth| auth.user_code == user_code)
+				.cloned(),
+			Backend::Database => {
+				StoredDeviceAuthorization::find_by_user_code(user_code, db)
+					.await?
+					.map(DeviceAuthorization::from)
+			},
+		};
+		Ok(authorization.filter(|auth| auth.pending(now)))
+	}
+
+	/// Record the decision of the user for a pending authorization. Returns
+	/// false when the user code does not belong to a pending authorization.
+	pub async fn resolve(
+		&self,
+		user_code: &str,
+		state: DeviceAuthorizationState,
+		db: &DbConn,
+	) -> Result<bool> {
+		let user_code = normalize_user_code(user_code);
+		let now = Local::now();
+		match &self.backend {
+			Backend::Memory(authorizations) => {
+				let mut authorizations = authorizations.lock().await;
+				match authorizations.values_mut().find(|auth| {
+					auth.user_code == user_code && auth.pending(now)
+				}) {
+					Some(auth) => {
+						auth.state = state;
+						Ok(true)
+					},
+					None => Ok(false),
+				}
+			},
+			Backend::Database => {
+				let resolved = StoredDeviceAuthorization::change(
+					DeviceAuthorizationCode::User(user_code),
+					move |stored| {
+						let device_code = stored.device_code.clone();
+						let mut auth = DeviceAuthorization::from(stored);
+						let pending = auth.pending(now);
+						if pending {
+							auth.state = state;
+						}
+						(pending, Some(auth.into_stored(device_code)))
+					},
+					db,
+				)
+				.await?;
+				Ok(resolved.unwrap_or(false))
+			},
+		}
+	}
+
+	/// Handle a device polling for its tokens. Finished authorizations are
+	/// removed, so a device code can only be exchanged once.
+	pub async fn poll(
+		&self,
+		device_code: &str,
+		client_id: i32,
+		db: &DbConn,
+	) -> Result<DevicePoll> {
+		let interval = self.interval;
+		match &self.backend {
+			Backend::Memory(authorizations) => {
+				let mut authorizations = authorizations.lock().await;
+				self.remove_stale(&mut authorizations);
+
+				let Some(auth) = authorizations.get_mut(device_code) else {
+					return Ok(DevicePoll::Invalid);
+				};
+				let (poll, keep) = auth.poll(client_id, interval);
+				if !keep {
+					authorizations.remove(device_code);
+				}
+				Ok(poll)
+			},
+			Backend::Database => {
+				self.remove_stale_stored(db).await?;
+				let poll = StoredDeviceAuthorization::change(
+					DeviceAuthorizationCode::Device(device_code.to_string()),
+					move |stored| {
+						let device_code = stored.device_code.clone();
+						let mut auth = DeviceAuthorization::from(stored);
+						let (poll, keep) = auth.poll(client_id, interval);
+						(poll, keep.then(|| auth.into_stored(device_code)))
+					},
+					db,
+				)
+				.await?;
+				Ok(poll.unwrap_or(DevicePoll::Invalid))
+			},
+		}
+	}
+}
+
+impl DeviceAuthorization {
+	fn pending(&self, now: DateTime<Local>) -> bool {
+		now < self.expiry
+			&& matches!(self.state, DeviceAuthorizationState::Pending)
+	}
+
+	/// The answer to a poll of the device. Also returns whether the
+	/// authorization has to be kept for later polls.
+	fn poll(
+		&mut self,
+		client_id: i32,
+		interval: Duration,
+	) -> (DevicePoll, bool) {
+		let now = Local::now();
+		if self.client_id != client_id {
+			return (DevicePoll::Invalid, true);
+		}
+		if now >= self.expiry {
+			return (DevicePoll::Expired, false);
+		}
+		match self.state.clone() {
+			DeviceAuthorizationState::Pending => {
+				let too_fast = self
+					.last_poll
+					.is_some_and(|last_poll| now - last_poll < interval);
+				self.last_poll = Some(now);
+				if too_fast {
+					(DevicePoll::SlowDown, true)
+				} else {
+					(DevicePoll::Pending, true)
+				}
+			},
+			DeviceAuthorizationState::Denied => (DevicePoll::Denied, false),
+			DeviceAuthorizationState::Granted {
+				user_id,
+				auth_time,
+				sid,
+			} => (
+				DevicePoll::Granted {
+					user_id,
+					scope: self.scope.clone(),
+					auth_time,
+					sid,
+				},
+				false,
+			),
+		}
+	}
+
+	fn into_stored(self, device_code: String) -> StoredDeviceAuthorization {
+		let (state, user_id, auth_time, sid) = match self.state {
+			DeviceAuthorizationState::Pending => ("pending", None, None, None),
+			DeviceAuthorizationState::Granted {
+				user_id,
+				auth_time,
+				sid,
+			} => ("granted", Some(user_id), Some(auth_time), sid),
+			DeviceAuthorizationState::Denied => ("denied", None, None, None),
+		};
+		StoredDeviceAuthorization {
+			device_code,
+			user_code: self.user_code,
+			client_id: self.client_id,
+			scope: self.scope,
+			state: state.to_string(),
+			user_id,
+			auth_time,
+			sid,
+			expires_at: self.expiry.naive_utc(),
+			last_poll: self.last_poll.map(|last_poll| last_poll.naive_utc()),
+		}
+	}
+}
+
+impl From<StoredDeviceAuthorization> for DeviceAuthorization {
+	fn from(stored: StoredDeviceAuthorization) -> Self {
+		let state =
+			match (stored.state.as_str(), stored.user_id, stored.auth_time) {
+				("pending", _, _) => DeviceAuthorizationState::Pending,
+				("granted", Some(user_id), Some(auth_time)) => {
+					DeviceAuthorizationState::Granted {
+						user_id,
+						auth_time,
+						sid: stored.sid,
+					}
+				},
+				_ => DeviceAuthorizationState::Denied,
+			};
+		DeviceAuthorization {
+			client_id: stored.client_id,
+			scope: stored.scope,
+			user_code: stored.user_code,
+			state,
+			expiry: Local.from_utc_datetime(&stored.expires_at),
+			last_poll: stored
+				.last_poll
+				.map(|last_poll| Local.from_utc_datetime(&last_poll)),
+		}
+	}
+}
+
+/// Users may type the code in lowercase and with or without the dash we show
+/// them.
+pub fn normalize_user_code(user_code: &str) -> String {
+	user_code
+		.chars()
+		.filter(|c| c.is_ascii_alphanumeric())
+		.map(|c| c.to_ascii_uppercase())
+		.collect()
+}
+
+/// Split a user code in two halves to make it easier to read.
+pub fn format_user_code(user_code: &str) -> String {
+	let (first, second) = user_code.split_at(user_code.len() / 2);
+	format!("{}-{}", first, second)
+}
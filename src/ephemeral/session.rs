@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use rocket::http::{Cookie, CookieJar, Status};
 use rocket::outcome::try_outcome;
 use rocket::request::{FromRequest, Outcome, Request};
@@ -6,21 +7,37 @@ use std::str::FromStr;
 use crate::DbConn;
 use crate::controllers::sessions_controller::rocket_uri_macro_new_session;
 use crate::errors::Result;
+use crate::jwt::JWTBuilder;
 use crate::models::client::Client;
 use crate::models::session::Session;
 use crate::models::user::User;
+use rocket::State;
 use rocket::http::uri::Origin;
 use rocket::response::Redirect;
 
 const REDIRECT_COOKIE: &str = "ZAUTH_REDIRECT";
 const SESSION_COOKIE: &str = "ZAUTH_SESSION";
 
+/// Send the user to the login page, and back to `uri` once they're logged in.
+/// The login hint is used to fill in the username.
 pub fn ensure_logged_in_and_redirect(
 	cookies: &CookieJar,
 	uri: Origin,
+	login_hint: Option<String>,
 ) -> Redirect {
 	cookies.add_private(Cookie::new(REDIRECT_COOKIE, uri.to_string()));
-	Redirect::to(uri!(new_session))
+	Redirect::to(uri!(new_session(login_hint, None::<bool>)))
+}
+
+/// Like `ensure_logged_in_and_redirect`, but users that are already logged in
+/// have to enter their password again too.
+pub fn ensure_reauthenticated_and_redirect(
+	cookies: &CookieJar,
+	uri: Origin,
+	login_hint: Option<String>,
+) -> Redirect {
+	cookies.add_private(Cookie::new(REDIRECT_COOKIE, uri.to_string()));
+	Redirect::to(uri!(new_session(login_hint, Some(true))))
 }
 
 pub fn stored_redirect_or(cookies: &CookieJar, fallback: Origin) -> Redirect {
@@ -91,14 +108,31 @@ pub struct UserSession {
 }
 
 impl UserSession {
+	/// The last moment the user entered their credentials in this session.
+	pub fn authenticated_at(&self) -> NaiveDateTime {
+		self.session.authenticated_at
+	}
+
+	/// Record that the user logged in again. The session stays the same, so
+	/// clients don't get logged out.
+	pub async fn reauthenticate(self, db: &DbConn) -> Result<()> {
+		self.session.reauthenticate(db).await?;
+		Ok(())
+	}
+
+	/// The session id we share with clients in ID tokens.
+	pub fn sid(&self) -> Option<String> {
+		self.session.sid.clone()
+	}
+
+	/// Log the user out, returning the session that ended.
 	pub async fn destroy(
 		self,
 		cookies: &CookieJar<'_>,
 		db: &DbConn,
-	) -> Result<()> {
+	) -> Result<Session> {
 		cookies.remove_private(Cookie::from(SESSION_COOKIE));
-		self.session.invalidate(db).await?;
-		Ok(())
+		self.session.invalidate(db).await
 	}
 }
 
@@ -171,14 +205,20 @@ impl<'r> FromRequest<'r> for UserClientSession {
 		if !auth_header.starts_with(prefix) {
 			return Outcome::Forward(Status::BadRequest);
 		}
-		let key = &auth_header[prefix.len()..];
+		let token = auth_header[prefix.len()..].to_string();
 
 		let db =
 			try_outcome!(request.guard::<DbConn>().await.map_error(|_| {
 				(Status::InternalServerError, "could not connect to database")
 			}));
-
-		match Session::find_by_key(key.to_string(), &db).await {
+		let jwt_builder = try_outcome!(
+			request
+				.guard::<&State<JWTBuilder>>()
+				.await
+				.map_error(|_| (Status::InternalServerError, "no JWT builder"))
+		);
+
+		match Session::find_by_access_token(token, jwt_builder, &db).await {
 			Ok(session) => match session.user(&db).await {
 				Ok(user) => match session.client(&db).await {
 					Ok(Some(client)) => Outcome::Success(UserClientSession {
@@ -218,14 +258,20 @@ impl<'r> FromRequest<'r> for ClientSession {
 		if !auth_header.starts_with(prefix) {
 			return Outcome::Forward(Status::BadRequest);
 		}
-		let key = &auth_header[prefix.len()..];
+		let token = auth_header[prefix.len()..].to_string();
 
 		let db =
 			try_outcome!(request.guard::<DbConn>().await.map_error(|_| {
 				(Status::InternalServerError, "could not connect to database")
 			}));
-
-		match Session::find_by_key(key.to_string(), &db).await {
+		let jwt_builder = try_outcome!(
+			request
+				.guard::<&State<JWTBuilder>>()
+				.await
+				.map_error(|_| (Status::InternalServerError, "no JWT builder"))
+		);
+
+		match Session::find_by_access_token(token, jwt_builder, &db).await {
 			Ok(session) => match session.user(&db).await {
 				Ok(_) => Outcome::Forward(Status::Unauthorized),
 				Err(_) => match session.client(&db).await {
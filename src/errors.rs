@@ -12,6 +12,7 @@ use std::convert::Infallible;
 use validator::ValidationErrors;
 use webauthn_rs::prelude::WebauthnError;
 
+use crate::backchannel_logout::LogoutNotification;
 use crate::views::accepter::Accepter;
 
 #[derive(Error, Debug)]
@@ -54,6 +55,12 @@ struct JsonError {
 	message: Option<String>,
 }
 
+#[derive(Serialize)]
+struct OAuthErrorResponse {
+	error: &'static str,
+	error_description: String,
+}
+
 impl<'r, 'o: 'r> Responder<'r, 'o> for ZauthError {
 	fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
 		let mut builder = Response::build();
@@ -83,10 +90,43 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for ZauthError {
 						.respond_to(request)?,
 				);
 			},
+			ZauthError::AuthError(AuthenticationError::InsufficientScope(
+				scope,
+			)) => {
+				// RFC 6750, section 3.1
+				builder.status(Status::Forbidden);
+				builder.raw_header(
+					"WWW-Authenticate",
+					format!(
+						"Bearer error=\"insufficient_scope\", scope=\"{}\"",
+						scope
+					),
+				);
+				builder.merge(
+					Json(OAuthErrorResponse {
+						error: "insufficient_scope",
+						error_description: format!(
+							"The access token requires the '{}' scope",
+							scope
+						),
+					})
+					.respond_to(request)?,
+				);
+			},
 			ZauthError::AuthError(_) => {
 				builder.status(Status::Unauthorized);
 				builder.merge(unauthorized().respond_to(request)?);
 			},
+			ZauthError::OAuth(e) => {
+				builder.status(Status::BadRequest);
+				builder.merge(
+					Json(OAuthErrorResponse {
+						error: e.error_code(),
+						error_description: e.to_string(),
+					})
+					.respond_to(request)?,
+				);
+			},
 			_ => {
 				warn!("Unmapped error occurred: {:?}", self);
 				let message = if debug {
@@ -213,6 +253,8 @@ pub enum InternalError {
 	MailerStopped(#[from] SendError<Message>),
 	#[error("Mail queue full")]
 	MailQueueFull(#[from] TrySendError<Message>),
+	#[error("Logout notifier stopped processing items")]
+	LogoutNotifierStopped(#[from] SendError<LogoutNotification>),
 	#[error("Bincode encoding error")]
 	BincodeEncodeError(#[from] bincode::error::EncodeError),
 	#[error("Bincode decoding error")]
@@ -223,6 +265,10 @@ pub enum InternalError {
 	JWTError(#[from] jsonwebtoken::errors::Error),
 	#[error("Serde error")]
 	SerdeError(#[from] serde_json::Error),
+	#[error("OpenSSL error")]
+	OpensslError(#[from] openssl::error::ErrorStack),
+	#[error("HTTP error: {0}")]
+	HttpError(#[from] reqwest::Error),
 }
 
 pub type InternalResult<T> = std::result::Result<T, InternalError>;
@@ -254,6 +300,8 @@ pub enum AuthenticationError {
 	AuthFailed,
 	#[error("Session expired")]
 	SessionExpired,
+	#[error("Insufficient scope, '{0}' is required")]
+	InsufficientScope(String),
 }
 pub type AuthResult<T> = std::result::Result<T, AuthenticationError>;
 
@@ -276,12 +324,87 @@ pub enum OAuthError {
 	InvalidCookie,
 	#[error("Only response_type=code is supported")]
 	ResponseTypeMismatch,
-	#[error("Only grant_type=authorization_code is supported")]
+	#[error("Unsupported grant_type")]
 	GrantTypeMismatch,
 	#[error("Invalid request")]
 	InvalidRequest,
 	#[error("Invalid grant: {0}")]
 	InvalidGrant(String),
+	#[error("The requested scope exceeds the granted scope")]
+	InvalidScope,
+	#[error("Unknown scope '{0}'")]
+	UnknownScope(String),
+	#[error("This client is required to use PKCE")]
+	PkceRequired,
+	#[error("This client is required to push its authorization requests")]
+	ParRequired,
+	#[error("This client is required to sign its authorization requests")]
+	SignedRequestRequired,
+	#[error("Invalid code_challenge")]
+	InvalidCodeChallenge,
+	#[error("Unsupported code_challenge_method '{0}'")]
+	UnsupportedChallengeMethod(String),
+	#[error("The user has not yet completed the authorization")]
+	AuthorizationPending,
+	#[error("Polling too fast, slow down")]
+	SlowDown,
+	#[error("The device code has expired")]
+	ExpiredToken,
+	#[error("The user denied the authorization request")]
+	AccessDenied,
+	#[error("The user has to log in")]
+	LoginRequired,
+	#[error("The user has to consent to the requested access")]
+	ConsentRequired,
+	#[error("Unsupported response_mode '{0}'")]
+	UnsupportedResponseMode(String),
+	#[error("Invalid target: {0}")]
+	InvalidTarget(String),
+	#[error("Invalid redirect URI: {0}")]
+	InvalidRedirectUri(String),
+	#[error("Invalid client metadata: {0}")]
+	InvalidClientMetadata(String),
+	#[error("Unauthorized client: {0}")]
+	UnauthorizedClient(String),
+	#[error("Invalid request_uri: {0}")]
+	InvalidRequestUri(String),
+	#[error("Invalid request object: {0}")]
+	InvalidRequestObject(String),
+}
+
+impl OAuthError {
+	/// The error code as defined in RFC 6749, sections 4.1.2.1 and 5.2, RFC
+	/// 7591, section 3.2.2, RFC 8628, section 3.5, RFC 8693, section 2.2.2,
+	/// RFC 9101, section 6.2, and OpenID Connect Core 1.0, section 3.1.2.6.
+	pub fn error_code(&self) -> &'static str {
+		match self {
+			OAuthError::ResponseTypeMismatch => "unsupported_response_type",
+			OAuthError::GrantTypeMismatch => "unsupported_grant_type",
+			OAuthError::InvalidGrant(_) => "invalid_grant",
+			OAuthError::InvalidScope => "invalid_scope",
+			OAuthError::UnknownScope(_) => "invalid_scope",
+			OAuthError::AuthorizationPending => "authorization_pending",
+			OAuthError::SlowDown => "slow_down",
+			OAuthError::ExpiredToken => "expired_token",
+			OAuthError::AccessDenied => "access_denied",
+			OAuthError::LoginRequired => "login_required",
+			OAuthError::ConsentRequired => "consent_required",
+			OAuthError::InvalidTarget(_) => "invalid_target",
+			OAuthError::InvalidRedirectUri(_) => "invalid_redirect_uri",
+			OAuthError::InvalidClientMetadata(_) => "invalid_client_metadata",
+			OAuthError::UnauthorizedClient(_) => "unauthorized_client",
+			OAuthError::InvalidRequestUri(_) => "invalid_request_uri",
+			OAuthError::InvalidRequestObject(_) => "invalid_request_object",
+			OAuthError::InvalidCookie
+			| OAuthError::InvalidRequest
+			| OAuthError::PkceRequired
+			| OAuthError::ParRequired
+			| OAuthError::SignedRequestRequired
+			| OAuthError::InvalidCodeChallenge
+			| OAuthError::UnsupportedChallengeMethod(_)
+			| OAuthError::UnsupportedResponseMode(_) => "invalid_request",
+		}
+	}
 }
 
 pub enum Either<R, E> {
@@ -0,0 +1,76 @@
+use jsonwebtoken::jwk::JwkSet;
+use rocket::tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::errors::InternalResult;
+
+/// How long a client gets to answer a single request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a fetched JWKS is used before it is fetched again. Clients that
+/// rotate their keys should keep serving the old key for at least this long.
+const JWKS_CACHE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// The HTTP client for the requests we make to clients: fetching their keys
+/// and request objects, and delivering logout tokens. It is shared, so
+/// connections to clients are reused.
+pub struct HttpClient {
+	client: reqwest::Client,
+	jwks: Mutex<HashMap<String, (Instant, JwkSet)>>,
+}
+
+impl HttpClient {
+	pub fn new() -> HttpClient {
+		HttpClient {
+			client: reqwest::Client::builder()
+				.timeout(REQUEST_TIMEOUT)
+				.build()
+				.expect("http client"),
+			jwks: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub fn client(&self) -> &reqwest::Client {
+		&self.client
+	}
+
+	/// The body served at the URI, which has to be served successfully.
+	pub async fn get_text(&self, uri: &str) -> InternalResult<String> {
+		Ok(self
+			.client
+			.get(uri)
+			.send()
+			.await?
+			.error_for_status()?
+			.text()
+			.await?)
+	}
+
+	/// The JWK Set served at the URI. Sets are cached for a short while, so
+	/// not every signed request needs a request to the client.
+	pub async fn jwks(&self, uri: &str) -> InternalResult<JwkSet> {
+		let mut cache = self.jwks.lock().await;
+		cache.retain(|_, (fetched_at, _)| {
+			fetched_at.elapsed() < JWKS_CACHE_DURATION
+		});
+		if let Some((_, jwks)) = cache.get(uri) {
+			return Ok(jwks.clone());
+		}
+		// Don't hold up other requests while we wait for the client
+		drop(cache);
+
+		let jwks: JwkSet = serde_json::from_str(&self.get_text(uri).await?)?;
+		self.jwks
+			.lock()
+			.await
+			.insert(uri.to_string(), (Instant::now(), jwks.clone()));
+		Ok(jwks)
+	}
+}
+
+impl Default for HttpClient {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -1,26 +1,71 @@
 use crate::config::Config;
 use crate::errors::{InternalError, LaunchError, Result};
 use crate::models::client::Client;
+use crate::models::session::Session;
 use crate::models::user::User;
+use crate::util::random_token;
 use base64::engine::Engine;
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-use chrono::Utc;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use jsonwebtoken::jwk::{
 	CommonParameters, EllipticCurveKeyParameters, Jwk, JwkSet,
 };
-use jsonwebtoken::{EncodingKey, Header, encode};
+use jsonwebtoken::{
+	DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
 use openssl::bn::{BigNum, BigNumContext};
 use openssl::ec::EcKey;
-use serde::Serialize;
+use openssl::sha::sha256;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 
 pub struct JWTBuilder {
 	pub key: EncodingKey,
 	pub header: Header,
-	pub jwks: JwkSet,
+	verification_keys: Vec<VerificationKey>,
+}
+
+/// A public key that tokens can be verified with. Retired keys are no longer
+/// used for signing, but are still published until they expire so relying
+/// parties can verify tokens that were signed before the rotation.
+struct VerificationKey {
+	jwk: Jwk,
+	decoding_key: DecodingKey,
+	expires_at: Option<DateTime<Utc>>,
+}
+
+impl VerificationKey {
+	fn from_pem(
+		buffer: &[u8],
+		expires_at: Option<DateTime<Utc>>,
+	) -> Result<VerificationKey> {
+		let jwk = public_jwk(buffer)?;
+		let decoding_key = DecodingKey::from_jwk(&jwk)
+			.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
+		Ok(VerificationKey {
+			jwk,
+			decoding_key,
+			expires_at,
+		})
+	}
+
+	fn kid(&self) -> Option<&str> {
+		self.jwk.common.key_id.as_deref()
+	}
+
+	fn is_expired(&self) -> bool {
+		self.expires_at
+			.is_some_and(|expires_at| expires_at <= Utc::now())
+	}
 }
 
+const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+const LOGOUT_TOKEN_TYPE: &str = "logout+jwt";
+const BACKCHANNEL_LOGOUT_EVENT: &str =
+	"http://schemas.openid.net/event/backchannel-logout";
+
 #[derive(Serialize, Debug)]
 struct IDToken {
 	sub: String,
@@ -28,6 +73,12 @@ struct IDToken {
 	aud: String,
 	exp: i64,
 	iat: i64,
+	auth_time: i64,
+	azp: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	nonce: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sid: Option<String>,
 	preferred_username: String,
 	email: String,
 	#[serde(skip_serializing_if = "Option::is_none")]
@@ -35,6 +86,58 @@ struct IDToken {
 	picture: String,
 }
 
+/// Describes the authentication of the user an ID token is issued for.
+#[derive(Debug, Clone)]
+pub struct AuthenticationClaims {
+	pub auth_time: NaiveDateTime,
+	pub nonce: Option<String>,
+	/// Identifies the user session the client was authorized in, so the
+	/// client can match it with a back-channel logout token.
+	pub sid: Option<String>,
+}
+
+/// A JWT access token as described in RFC 9068. The `jti` is the id of the
+/// session backing the token, so it can still be revoked and introspected
+/// without the token revealing the session key.
+#[derive(Serialize, Debug)]
+struct AccessToken {
+	iss: String,
+	sub: String,
+	aud: String,
+	client_id: String,
+	exp: i64,
+	iat: i64,
+	jti: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	scope: Option<String>,
+	roles: Vec<String>,
+}
+
+/// A logout token as described in OpenID Connect Back-Channel Logout 1.0,
+/// telling a client that a user session ended.
+#[derive(Serialize, Debug)]
+struct LogoutToken {
+	iss: String,
+	sub: String,
+	aud: String,
+	iat: i64,
+	exp: i64,
+	jti: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sid: Option<String>,
+	events: HashMap<&'static str, HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AccessTokenId {
+	jti: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IDTokenAudience {
+	aud: String,
+}
+
 #[derive(Serialize, Debug)]
 struct ClientIDToken {
 	sub: String,
@@ -46,61 +149,98 @@ struct ClientIDToken {
 	roles: Option<Vec<String>>,
 }
 
+fn read_key(path: &str) -> Result<Vec<u8>> {
+	let mut file = File::open(path)
+		.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
+	let mut buffer = Vec::new();
+	file.read_to_end(&mut buffer)
+		.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
+	Ok(buffer)
+}
+
+/// Build the public JWK for a PEM encoded EC private key. The key id is the
+/// JWK thumbprint (RFC 7638), so it is stable for a key without any extra
+/// configuration.
+fn public_jwk(buffer: &[u8]) -> Result<Jwk> {
+	let private_key = EcKey::private_key_from_pem(buffer)
+		.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
+
+	let mut ctx: BigNumContext = BigNumContext::new().unwrap();
+	let public_key = private_key.public_key();
+	let mut x = BigNum::new().unwrap();
+	let mut y = BigNum::new().unwrap();
+	public_key
+		.affine_coordinates(private_key.group(), &mut x, &mut y, &mut ctx)
+		.expect("x,y coordinates");
+
+	// Coordinates on P-384 are 48 bytes long, including leading zeroes
+	let x = URL_SAFE_NO_PAD.encode(x.to_vec_padded(48).expect("x coordinate"));
+	let y = URL_SAFE_NO_PAD.encode(y.to_vec_padded(48).expect("y coordinate"));
+	let thumbprint = sha256(
+		format!(r#"{{"crv":"P-384","kty":"EC","x":"{}","y":"{}"}}"#, x, y)
+			.as_bytes(),
+	);
+
+	Ok(Jwk {
+		common: CommonParameters {
+			public_key_use: Some(jsonwebtoken::jwk::PublicKeyUse::Signature),
+			key_algorithm: Some(jsonwebtoken::jwk::KeyAlgorithm::ES384),
+			key_operations: None,
+			key_id: Some(URL_SAFE_NO_PAD.encode(thumbprint)),
+			x509_url: None,
+			x509_chain: None,
+			x509_sha1_fingerprint: None,
+			x509_sha256_fingerprint: None,
+		},
+		algorithm: jsonwebtoken::jwk::AlgorithmParameters::EllipticCurve(
+			EllipticCurveKeyParameters {
+				key_type: jsonwebtoken::jwk::EllipticCurveKeyType::EC,
+				curve: jsonwebtoken::jwk::EllipticCurve::P384,
+				x,
+				y,
+			},
+		),
+	})
+}
+
 impl JWTBuilder {
 	pub fn new(config: &Config) -> Result<JWTBuilder> {
-		let mut file = File::open(&config.ec_private_key)
-			.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
-		let mut buffer = Vec::new();
-		file.read_to_end(&mut buffer)
-			.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
-
+		let buffer = read_key(&config.ec_private_key)?;
 		let key = EncodingKey::from_ec_pem(&buffer)
 			.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
-		let header = Header::new(jsonwebtoken::Algorithm::ES384);
+		let active_key = VerificationKey::from_pem(&buffer, None)?;
 
-		let private_key = EcKey::private_key_from_pem(&buffer)
-			.map_err(|err| LaunchError::BadConfigValueType(err.to_string()))?;
+		let mut header = Header::new(jsonwebtoken::Algorithm::ES384);
+		header.kid = active_key.kid().map(String::from);
 
-		let mut ctx: BigNumContext = BigNumContext::new().unwrap();
-		let public_key = private_key.public_key();
-		let mut x = BigNum::new().unwrap();
-		let mut y = BigNum::new().unwrap();
-		public_key
-			.affine_coordinates(private_key.group(), &mut x, &mut y, &mut ctx)
-			.expect("x,y coordinates");
-
-		let jwk = Jwk {
-			common: CommonParameters {
-				public_key_use: Some(
-					jsonwebtoken::jwk::PublicKeyUse::Signature,
-				),
-				key_algorithm: Some(jsonwebtoken::jwk::KeyAlgorithm::ES384),
-				key_operations: None,
-				key_id: None,
-				x509_url: None,
-				x509_chain: None,
-				x509_sha1_fingerprint: None,
-				x509_sha256_fingerprint: None,
-			},
-			algorithm: jsonwebtoken::jwk::AlgorithmParameters::EllipticCurve(
-				EllipticCurveKeyParameters {
-					key_type: jsonwebtoken::jwk::EllipticCurveKeyType::EC,
-					curve: jsonwebtoken::jwk::EllipticCurve::P384,
-					x: URL_SAFE_NO_PAD.encode(x.to_vec()),
-					y: URL_SAFE_NO_PAD.encode(y.to_vec()),
-				},
-			),
-		};
+		let mut verification_keys = vec![active_key];
+		for retired_key in &config.ec_retired_keys {
+			verification_keys.push(VerificationKey::from_pem(
+				&read_key(&retired_key.path)?,
+				Some(retired_key.expires_at),
+			)?);
+		}
 
 		Ok(JWTBuilder {
 			key,
 			header,
-			jwks: JwkSet {
-				keys: Vec::from([jwk]),
-			},
+			verification_keys,
 		})
 	}
 
+	/// The public keys of the active key and all retired keys that have not
+	/// yet expired.
+	pub fn jwks(&self) -> JwkSet {
+		JwkSet {
+			keys: self
+				.verification_keys
+				.iter()
+				.filter(|key| !key.is_expired())
+				.map(|key| key.jwk.clone())
+				.collect(),
+		}
+	}
+
 	pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String> {
 		Ok(encode(&self.header, claims, &self.key)
 			.map_err(InternalError::from)?)
@@ -112,6 +252,7 @@ impl JWTBuilder {
 		user: &User,
 		config: &Config,
 		roles: Option<Vec<String>>,
+		authentication: AuthenticationClaims,
 	) -> Result<String> {
 		let id_token = IDToken {
 			sub: user.id.to_string(),
@@ -119,6 +260,10 @@ impl JWTBuilder {
 			aud: client.name.clone(),
 			iat: Utc::now().timestamp(),
 			exp: Utc::now().timestamp() + config.client_session_seconds,
+			auth_time: authentication.auth_time.and_utc().timestamp(),
+			azp: client.name.clone(),
+			nonce: authentication.nonce,
+			sid: authentication.sid,
 			preferred_username: user.username.clone(),
 			email: user.email.clone(),
 			roles,
@@ -143,4 +288,102 @@ impl JWTBuilder {
 		};
 		self.encode(&id_token)
 	}
+
+	/// The `audience` is the resource server the token is meant for. Without
+	/// one, the token is meant for our own API, like the userinfo endpoint.
+	pub fn encode_access_token(
+		&self,
+		client: &Client,
+		session: &Session,
+		roles: Vec<String>,
+		audience: Option<&str>,
+		config: &Config,
+	) -> Result<String> {
+		let access_token = AccessToken {
+			iss: config.base_url().to_string(),
+			sub: session.user_id.unwrap_or(client.id).to_string(),
+			aud: audience
+				.map(str::to_string)
+				.unwrap_or_else(|| config.base_url().to_string()),
+			client_id: client.name.clone(),
+			exp: session.expires_at.and_utc().timestamp(),
+			iat: session.created_at.and_utc().timestamp(),
+			jti: session.id.to_string(),
+			scope: session.scope.clone(),
+			roles,
+		};
+		let mut header = self.header.clone();
+		header.typ = Some(ACCESS_TOKEN_TYPE.to_string());
+		Ok(encode(&header, &access_token, &self.key)
+			.map_err(InternalError::from)?)
+	}
+
+	/// Without a `sid`, the token logs the user out of every session they
+	/// have with the client.
+	pub fn encode_logout_token(
+		&self,
+		client: &Client,
+		user_id: i32,
+		sid: Option<String>,
+		config: &Config,
+	) -> Result<String> {
+		let logout_token = LogoutToken {
+			iss: config.base_url().to_string(),
+			sub: user_id.to_string(),
+			aud: client.name.clone(),
+			iat: Utc::now().timestamp(),
+			exp: Utc::now().timestamp() + config.client_session_seconds,
+			jti: random_token(config.secure_token_length),
+			sid,
+			events: HashMap::from([(BACKCHANNEL_LOGOUT_EVENT, HashMap::new())]),
+		};
+		let mut header = self.header.clone();
+		header.typ = Some(LOGOUT_TOKEN_TYPE.to_string());
+		Ok(encode(&header, &logout_token, &self.key)
+			.map_err(InternalError::from)?)
+	}
+
+	/// Find the key a token issued by us was signed with.
+	fn verification_key(&self, header: &Header) -> Option<&VerificationKey> {
+		self.verification_keys
+			.iter()
+			.filter(|key| !key.is_expired())
+			.find(|key| {
+				header.kid.is_none() || key.kid() == header.kid.as_deref()
+			})
+	}
+
+	/// Returns the session id of a JWT access token issued by us, or `None`
+	/// if the token is not a valid JWT access token.
+	pub fn decode_access_token(&self, token: &str) -> Option<i32> {
+		let header = decode_header(token).ok()?;
+		if header.typ.as_deref() != Some(ACCESS_TOKEN_TYPE) {
+			return None;
+		}
+		let key = self.verification_key(&header)?;
+		let mut validation = Validation::new(self.header.alg);
+		validation.validate_aud = false;
+		decode::<AccessTokenId>(token, &key.decoding_key, &validation)
+			.ok()
+			.and_then(|data| data.claims.jti.parse().ok())
+	}
+
+	/// Returns the client name an ID token issued by us was meant for.
+	/// Expired ID tokens are accepted, as clients often only log out after
+	/// their ID token expired.
+	pub fn decode_id_token_hint(&self, token: &str) -> Option<String> {
+		let header = decode_header(token).ok()?;
+		if header.typ.as_deref() == Some(ACCESS_TOKEN_TYPE)
+			|| header.typ.as_deref() == Some(LOGOUT_TOKEN_TYPE)
+		{
+			return None;
+		}
+		let key = self.verification_key(&header)?;
+		let mut validation = Validation::new(self.header.alg);
+		validation.validate_aud = false;
+		validation.validate_exp = false;
+		decode::<IDTokenAudience>(token, &key.decoding_key, &validation)
+			.ok()
+			.map(|data| data.claims.aud)
+	}
 }
@@ -26,15 +26,22 @@ extern crate diesel_migrations;
 
 #[macro_use]
 pub mod views;
+pub mod backchannel_logout;
+pub mod client_assertion;
 pub mod config;
 pub mod controllers;
 pub mod db_seed;
+pub mod device_store;
 pub mod ephemeral;
 pub mod errors;
 pub mod http_authentication;
+pub mod http_client;
 pub mod jwt;
 pub mod mailer;
 pub mod models;
+pub mod pkce;
+pub mod redirect_uri;
+pub mod request_object;
 pub mod token_store;
 pub mod util;
 pub mod webauthn;
@@ -51,14 +58,16 @@ use rocket_sync_db_pools::diesel::PgConnection;
 use simple_logger::SimpleLogger;
 use webauthn::WebAuthnStore;
 
+use crate::backchannel_logout::LogoutNotifier;
 use crate::config::{AdminEmail, Config};
 use crate::controllers::*;
 use crate::db_seed::Seeder;
+use crate::device_store::DeviceStore;
 use crate::errors::{
 	internal_server_error, not_found, not_implemented, unauthorized,
 };
+use crate::http_client::HttpClient;
 use crate::mailer::Mailer;
-use crate::token_store::TokenStore;
 
 use std::str::FromStr;
 
@@ -91,9 +100,23 @@ fn assemble(rocket: Rocket<Build>) -> Rocket<Build> {
 	let admin_email: AdminEmail = AdminEmail(
 		Mailbox::from_str(&config.admin_email).expect("admin email"),
 	);
-	let token_store = TokenStore::<oauth_controller::UserToken>::new(&config);
+	let token_store = token_store::from_config::<oauth_controller::UserToken>(
+		"authorization_code",
+		config.authorization_token_duration(),
+		&config,
+	);
+	let pushed_requests =
+		token_store::from_config::<oauth_controller::PushedRequest>(
+			"pushed_request",
+			config.pushed_request_duration(),
+			&config,
+		);
+	let device_store = DeviceStore::new(&config);
 	let mailer = Mailer::new(&config).unwrap();
+	let http_client = HttpClient::new();
+	let logout_notifier = LogoutNotifier::new(&config, &http_client);
 	let jwt_builder = JWTBuilder::new(&config).expect("config");
+	config.client_secret_key().expect("config");
 	let webauthn = WebAuthnStore::new(&config);
 
 	// if rocket.config().environment.is_dev() {
@@ -110,8 +133,9 @@ fn assemble(rocket: Rocket<Build>) -> Rocket<Build> {
 				clients_controller::update_client,
 				clients_controller::create_client,
 				clients_controller::delete_client,
-				clients_controller::get_generate_secret,
-				clients_controller::post_generate_secret,
+				clients_controller::list_client_secrets,
+				clients_controller::create_client_secret,
+				clients_controller::delete_client_secret,
 				clients_controller::current_client,
 				clients_controller::add_role,
 				clients_controller::delete_role,
@@ -122,12 +146,34 @@ fn assemble(rocket: Rocket<Build>) -> Rocket<Build> {
 				webauthn_controller::list_passkeys,
 				webauthn_controller::new_passkey,
 				webauthn_controller::delete_passkey,
+				consents_controller::list_consents,
+				consents_controller::revoke_consent,
 				oauth_controller::authorize,
+				oauth_controller::push_authorization_request,
 				oauth_controller::do_authorize,
 				oauth_controller::grant_get,
 				oauth_controller::grant_post,
 				oauth_controller::token,
+				oauth_controller::revoke,
+				oauth_controller::introspect,
+				oauth_controller::device_authorization,
+				oauth_controller::device_get,
+				oauth_controller::device_post,
+				oauth_controller::device_grant,
+				oauth_controller::end_session_get,
+				oauth_controller::end_session_post,
+				oauth_controller::userinfo_get,
+				oauth_controller::userinfo_post,
 				oauth_controller::jwks,
+				oauth_controller::openid_configuration,
+				oauth_controller::authorization_server_metadata,
+				registration_controller::register,
+				registration_controller::read_registration,
+				registration_controller::update_registration,
+				registration_controller::delete_registration,
+				initial_access_tokens_controller::list_initial_access_tokens,
+				initial_access_tokens_controller::create_initial_access_token,
+				initial_access_tokens_controller::delete_initial_access_token,
 				pages_controller::home_page,
 				sessions_controller::create_session,
 				sessions_controller::new_session,
@@ -170,6 +216,9 @@ fn assemble(rocket: Rocket<Build>) -> Rocket<Build> {
 				roles_controller::delete_user,
 				roles_controller::add_client,
 				roles_controller::delete_client,
+				scopes_controller::list_scopes,
+				scopes_controller::create_scope,
+				scopes_controller::delete_scope,
 			],
 		)
 		.register(
@@ -183,7 +232,11 @@ fn assemble(rocket: Rocket<Build>) -> Rocket<Build> {
 		)
 		.mount("/static/", FileServer::from("static/"))
 		.manage(token_store)
+		.manage(pushed_requests)
+		.manage(device_store)
 		.manage(mailer)
+		.manage(logout_notifier)
+		.manage(http_client)
 		.manage(admin_email)
 		.manage(jwt_builder)
 		.manage(webauthn)
@@ -206,10 +259,7 @@ async fn prepare_database(rocket: Rocket<Build>) -> Rocket<Build> {
 		eprintln!("Seeding database.");
 		let config: Config = rocket.figment().extract().expect("config");
 		let seeder = Seeder::from_env();
-		seeder
-			.run(config.bcrypt_cost, &db)
-			.await
-			.expect("database seed");
+		seeder.run(&config, &db).await.expect("database seed");
 	}
 
 	rocket
@@ -5,14 +5,16 @@ use crate::errors::{AuthenticationError, Result, ZauthError};
 
 use crate::models::schema::{clients, roles};
 
-use crate::util::random_token;
+use crate::redirect_uri;
+use crate::util::token_matches;
 use chrono::NaiveDateTime;
-use validator::Validate;
+use jsonwebtoken::jwk::JwkSet;
+use rocket::http::uri::Absolute;
+use validator::{Validate, ValidationError, ValidationErrors};
 
+use super::client_secret::ClientSecret;
 use super::role::{ClientRole, Role};
 
-const SECRET_LENGTH: usize = 64;
-
 #[derive(
 	Serialize, AsChangeset, Queryable, Debug, Clone, Identifiable, Selectable,
 )]
@@ -20,31 +22,54 @@ pub struct Client {
 	pub id: i32,
 	pub name: String,
 	pub description: String,
-	pub secret: String,
 	pub needs_grant: bool,
 	pub redirect_uri_list: String,
 	pub created_at: NaiveDateTime,
+	pub pkce_required: bool,
+	pub userinfo_signed: bool,
+	pub jwt_access_tokens: bool,
+	pub allowed_scopes: String,
+	pub post_logout_redirect_uri_list: String,
+	pub backchannel_logout_uri: String,
+	pub token_exchange_audiences: String,
+	pub jwks: String,
+	pub jwks_uri: String,
+	#[serde(skip)]
+	pub hashed_registration_access_token: Option<String>,
+	pub public: bool,
+	pub par_required: bool,
+	pub signed_request_required: bool,
+	pub request_uri_list: String,
+	pub secret_jwt_allowed: bool,
 }
 
-#[derive(Validate, FromForm, Deserialize, Debug, Clone)]
+#[derive(Validate, FromForm, Deserialize, Insertable, Debug, Clone)]
+#[diesel(table_name = clients)]
 pub struct NewClient {
 	#[validate(length(min = 3, max = 80))]
 	pub name: String,
 }
 
-#[derive(Insertable, Debug, Clone)]
-#[diesel(table_name = clients)]
-pub struct NewClientWithSecret {
-	pub name: String,
-	pub secret: String,
-}
-
 #[derive(Debug, Clone)]
 pub struct ClientChange {
 	pub name: Option<String>,
 	pub needs_grant: Option<bool>,
 	pub description: Option<String>,
 	pub redirect_uri_list: Option<String>,
+	pub pkce_required: Option<bool>,
+	pub userinfo_signed: Option<bool>,
+	pub jwt_access_tokens: Option<bool>,
+	pub allowed_scopes: Option<String>,
+	pub post_logout_redirect_uri_list: Option<String>,
+	pub backchannel_logout_uri: Option<String>,
+	pub token_exchange_audiences: Option<String>,
+	pub jwks: Option<String>,
+	pub jwks_uri: Option<String>,
+	pub public: Option<bool>,
+	pub par_required: Option<bool>,
+	pub signed_request_required: Option<bool>,
+	pub request_uri_list: Option<String>,
+	pub secret_jwt_allowed: Option<bool>,
 }
 
 impl Client {
@@ -55,16 +80,8 @@ impl Client {
 		Ok(all_clients)
 	}
 
-	fn generate_random_secret() -> String {
-		random_token(SECRET_LENGTH)
-	}
-
 	pub async fn create(client: NewClient, db: &DbConn) -> Result<Client> {
 		client.validate()?;
-		let client = NewClientWithSecret {
-			name: client.name,
-			secret: Self::generate_random_secret(),
-		};
 		db.run(move |conn| {
 			conn.transaction(|conn| {
 				// Create a new client
@@ -86,15 +103,128 @@ impl Client {
 		if let Some(needs_grant) = change.needs_grant {
 			self.needs_grant = needs_grant;
 		}
+		if let Some(pkce_required) = change.pkce_required {
+			self.pkce_required = pkce_required;
+		}
+		if let Some(public) = change.public {
+			self.public = public;
+		}
+		if let Some(par_required) = change.par_required {
+			self.par_required = par_required;
+		}
+		if let Some(signed_request_required) = change.signed_request_required {
+			self.signed_request_required = signed_request_required;
+		}
+		if let Some(secret_jwt_allowed) = change.secret_jwt_allowed {
+			self.secret_jwt_allowed = secret_jwt_allowed;
+		}
+		if let Some(userinfo_signed) = change.userinfo_signed {
+			self.userinfo_signed = userinfo_signed;
+		}
+		if let Some(jwt_access_tokens) = change.jwt_access_tokens {
+			self.jwt_access_tokens = jwt_access_tokens;
+		}
 		if let Some(description) = change.description {
 			self.description = description;
 		}
 		if let Some(redirect_uri_list) = change.redirect_uri_list {
+			for pattern in
+				redirect_uri_list.split_whitespace().filter_map(|uri| {
+					uri.strip_prefix(redirect_uri::PATTERN_PREFIX)
+				}) {
+				if let Err(message) = redirect_uri::validate_pattern(pattern) {
+					let mut err = ValidationErrors::new();
+					err.add("redirect_uri_list", ValidationError::new(message));
+					return Err(ZauthError::from(err));
+				}
+			}
 			self.redirect_uri_list = redirect_uri_list
 				.split_whitespace()
 				.collect::<Vec<&str>>()
 				.join("\n")
 		}
+		if let Some(post_logout_redirect_uri_list) =
+			change.post_logout_redirect_uri_list
+		{
+			self.post_logout_redirect_uri_list = post_logout_redirect_uri_list
+				.split_whitespace()
+				.collect::<Vec<&str>>()
+				.join("\n")
+		}
+		if let Some(request_uri_list) = change.request_uri_list {
+			if request_uri_list.split_whitespace().any(|uri| {
+				!Absolute::parse(uri).is_ok_and(|uri| uri.scheme() == "https")
+			}) {
+				let mut err = ValidationErrors::new();
+				err.add(
+					"request_uri_list",
+					ValidationError::new("Request URIs have to use https."),
+				);
+				return Err(ZauthError::from(err));
+			}
+			self.request_uri_list = request_uri_list
+				.split_whitespace()
+				.collect::<Vec<&str>>()
+				.join("\n")
+		}
+		if let Some(backchannel_logout_uri) = change.backchannel_logout_uri {
+			let backchannel_logout_uri = backchannel_logout_uri.trim();
+			if !backchannel_logout_uri.is_empty()
+				&& !Absolute::parse(backchannel_logout_uri)
+					.is_ok_and(|uri| uri.scheme() == "https")
+			{
+				let mut err = ValidationErrors::new();
+				err.add(
+					"backchannel_logout_uri",
+					ValidationError::new(
+						"The backchannel logout URI has to use https.",
+					),
+				);
+				return Err(ZauthError::from(err));
+			}
+			self.backchannel_logout_uri = backchannel_logout_uri.into();
+		}
+		if let Some(allowed_scopes) = change.allowed_scopes {
+			self.allowed_scopes = allowed_scopes
+				.split_whitespace()
+				.collect::<Vec<&str>>()
+				.join(" ")
+		}
+		if let Some(token_exchange_audiences) = change.token_exchange_audiences
+		{
+			self.token_exchange_audiences = token_exchange_audiences
+				.split_whitespace()
+				.collect::<Vec<&str>>()
+				.join(" ")
+		}
+		if let Some(jwks) = change.jwks {
+			let jwks = jwks.trim();
+			if !jwks.is_empty() && serde_json::from_str::<JwkSet>(jwks).is_err()
+			{
+				let mut err = ValidationErrors::new();
+				err.add(
+					"jwks",
+					ValidationError::new("The JWKS has to be a valid JWK Set."),
+				);
+				return Err(ZauthError::from(err));
+			}
+			self.jwks = jwks.into();
+		}
+		if let Some(jwks_uri) = change.jwks_uri {
+			let jwks_uri = jwks_uri.trim();
+			if !jwks_uri.is_empty()
+				&& !Absolute::parse(jwks_uri)
+					.is_ok_and(|uri| uri.scheme() == "https")
+			{
+				let mut err = ValidationErrors::new();
+				err.add(
+					"jwks_uri",
+					ValidationError::new("The JWKS URI has to use https."),
+				);
+				return Err(ZauthError::from(err));
+			}
+			self.jwks_uri = jwks_uri.into();
+		}
 		Ok(())
 	}
 
@@ -143,30 +273,89 @@ impl Client {
 		Ok(client)
 	}
 
+	/// Redirect URIs have to match an entry in the list exactly, except for
+	/// the port of loopback URIs and the entries marked as patterns.
 	pub fn redirect_uri_acceptable(&self, redirect_uri: &str) -> bool {
 		self.redirect_uri_list
 			.lines()
-			.any(|uri| uri == redirect_uri)
+			.any(|entry| redirect_uri::entry_matches(entry, redirect_uri))
+	}
+
+	pub fn post_logout_redirect_uri_acceptable(
+		&self,
+		post_logout_redirect_uri: &str,
+	) -> bool {
+		self.post_logout_redirect_uri_list
+			.lines()
+			.any(|uri| uri == post_logout_redirect_uri)
+	}
+
+	/// Request objects are only fetched from URIs the client registered, so
+	/// we can't be used to make requests to arbitrary hosts.
+	pub fn request_uri_acceptable(&self, request_uri: &str) -> bool {
+		self.request_uri_list.lines().any(|uri| uri == request_uri)
+	}
+
+	/// Whether the token is the registration access token the client got
+	/// when it registered itself.
+	pub fn registration_access_token_matches(&self, token: &str) -> bool {
+		self.hashed_registration_access_token
+			.as_deref()
+			.is_some_and(|hashed| token_matches(hashed, token))
+	}
+
+	/// Where to send logout tokens when a user's session ends, if the client
+	/// wants to be notified.
+	pub fn backchannel_logout_uri(&self) -> Option<&str> {
+		Some(self.backchannel_logout_uri.as_str()).filter(|uri| !uri.is_empty())
+	}
+
+	/// The public keys the client signs its assertions with, registered
+	/// either inline or as a URI to fetch them from.
+	pub fn jwks(&self) -> Option<&str> {
+		Some(self.jwks.as_str()).filter(|jwks| !jwks.is_empty())
+	}
+
+	pub fn jwks_uri(&self) -> Option<&str> {
+		Some(self.jwks_uri.as_str()).filter(|uri| !uri.is_empty())
+	}
+
+	/// Public clients can't keep a secret, so PKCE is the only thing that
+	/// protects their authorization codes.
+	pub fn requires_pkce(&self) -> bool {
+		self.pkce_required || self.public
 	}
 
+	pub fn scope_allowed(&self, scope: &str) -> bool {
+		self.allowed_scopes
+			.split(' ')
+			.any(|allowed| allowed == scope)
+	}
+
+	/// Whether this client may exchange the access tokens of its users for
+	/// tokens for the client named `audience`.
+	pub fn token_exchange_allowed(&self, audience: &str) -> bool {
+		self.token_exchange_audiences
+			.split(' ')
+			.any(|allowed| allowed == audience)
+	}
+
+	/// Any secret of the client that hasn't expired is accepted, so a new
+	/// secret can be rolled out before the old one is deleted.
 	pub async fn find_and_authenticate(
 		name: String,
 		secret: &str,
 		db: &DbConn,
 	) -> Result<Client> {
 		let client = Self::find_by_name(name, db).await?;
-		if client.secret == secret {
+		let secrets = ClientSecret::for_client(&client, db).await?;
+		if !client.public && secrets.iter().any(|s| s.matches(secret)) {
 			Ok(client)
 		} else {
 			Err(ZauthError::from(AuthenticationError::AuthFailed))
 		}
 	}
 
-	pub async fn generate_secret(mut self, db: &DbConn) -> Result<Self> {
-		self.secret = Self::generate_random_secret();
-		self.update(db).await
-	}
-
 	pub async fn roles(self, db: &DbConn) -> Result<Vec<Role>> {
 		db.run(move |conn| {
 			ClientRole::belonging_to(&self)
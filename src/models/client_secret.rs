@@ -0,0 +1,190 @@
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+use openssl::rand::rand_bytes;
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use validator::Validate;
+
+use crate::DbConn;
+use crate::config::Config;
+use crate::errors::{InternalError, Result, ZauthError};
+use crate::models::client::Client;
+use crate::models::schema::client_secrets;
+use crate::util::{hash_token, random_token, token_matches};
+
+const SECRET_LENGTH: usize = 64;
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+
+/// One of the secrets a client can authenticate with. A client can hold
+/// several secrets at once, so it can switch to a new one before the old one
+/// is deleted.
+///
+/// Only a hash of the secret is stored, the secret itself is shown once when
+/// it is created. Clients that use `client_secret_jwt` sign assertions with
+/// the secret, so for them the secret is also kept encrypted.
+#[derive(
+	Serialize, Queryable, Selectable, Identifiable, Associations, Debug, Clone,
+)]
+#[diesel(belongs_to(Client))]
+pub struct ClientSecret {
+	pub id: i32,
+	pub client_id: i32,
+	pub label: String,
+	#[serde(skip)]
+	pub hashed_secret: String,
+	pub created_at: NaiveDateTime,
+	pub expires_at: Option<NaiveDateTime>,
+	#[serde(skip)]
+	pub encrypted_secret: Option<String>,
+}
+
+#[derive(Validate, FromForm, Deserialize, Debug)]
+pub struct NewClientSecret {
+	#[validate(length(max = 100))]
+	pub label: String,
+	#[validate(range(min = 1, max = 3650))]
+	pub expires_in_days: Option<i64>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = client_secrets)]
+struct NewClientSecretWithHash {
+	client_id: i32,
+	label: String,
+	hashed_secret: String,
+	expires_at: Option<NaiveDateTime>,
+	encrypted_secret: Option<String>,
+}
+
+impl ClientSecret {
+	/// Generate a new secret for the client. Returns the stored secret
+	/// together with the secret itself, which can't be retrieved later on.
+	pub async fn create(
+		client: &Client,
+		secret: NewClientSecret,
+		config: &Config,
+		db: &DbConn,
+	) -> Result<(ClientSecret, String)> {
+		secret.validate()?;
+		let raw_secret = random_token(SECRET_LENGTH);
+		let encrypted_secret = if client.secret_jwt_allowed {
+			let key = config.client_secret_key()?.ok_or_else(|| {
+				ZauthError::Unprocessable(
+					"client_secret_jwt is not supported without a \
+					 client_secret_key"
+						.into(),
+				)
+			})?;
+			Some(encrypt(&key, client.id, &raw_secret)?)
+		} else {
+			None
+		};
+		let secret = NewClientSecretWithHash {
+			encrypted_secret,
+			client_id: client.id,
+			label: secret.label.trim().into(),
+			hashed_secret: hash_token(&raw_secret),
+			expires_at: secret
+				.expires_in_days
+				.map(|days| Utc::now().naive_utc() + Duration::days(days)),
+		};
+		let secret = db
+			.run(move |conn| {
+				diesel::insert_into(client_secrets::table)
+					.values(&secret)
+					.get_result(conn)
+			})
+			.await?;
+		Ok((secret, raw_secret))
+	}
+
+	pub async fn for_client(
+		client: &Client,
+		db: &DbConn,
+	) -> Result<Vec<ClientSecret>> {
+		let client_id = client.id;
+		db.run(move |conn| {
+			client_secrets::table
+				.filter(client_secrets::client_id.eq(client_id))
+				.order(client_secrets::id)
+				.load(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	pub async fn find(id: i32, db: &DbConn) -> Result<ClientSecret> {
+		db.run(move |conn| client_secrets::table.find(id).first(conn))
+			.await
+			.map_err(ZauthError::from)
+	}
+
+	pub async fn delete(self, db: &DbConn) -> Result<()> {
+		db.run(move |conn| {
+			diesel::delete(client_secrets::table.find(self.id)).execute(conn)
+		})
+		.await?;
+		Ok(())
+	}
+
+	/// The secret itself, for verifying `client_secret_jwt` assertions.
+	/// Returns `None` for secrets that are only stored hashed.
+	pub fn reveal(&self, config: &Config) -> Result<Option<String>> {
+		match (&self.encrypted_secret, config.client_secret_key()?) {
+			(Some(encrypted), Some(key)) if !self.expired() => {
+				Ok(decrypt(&key, self.client_id, encrypted))
+			},
+			_ => Ok(None),
+		}
+	}
+
+	pub fn expired(&self) -> bool {
+		self.expires_at
+			.is_some_and(|expires_at| expires_at <= Utc::now().naive_utc())
+	}
+
+	pub fn matches(&self, secret: &str) -> bool {
+		!self.expired() && token_matches(&self.hashed_secret, secret)
+	}
+}
+
+/// Encrypt the secret with AES-256-GCM. The id of the client is
+/// authenticated along with it, so a secret can't be moved to another client.
+fn encrypt(key: &[u8], client_id: i32, secret: &str) -> Result<String> {
+	let mut nonce = [0; NONCE_LENGTH];
+	rand_bytes(&mut nonce).map_err(InternalError::from)?;
+	let mut tag = [0; TAG_LENGTH];
+	let ciphertext = encrypt_aead(
+		Cipher::aes_256_gcm(),
+		key,
+		Some(&nonce),
+		&client_id.to_be_bytes(),
+		secret.as_bytes(),
+		&mut tag,
+	)
+	.map_err(InternalError::from)?;
+	Ok(BASE64_STANDARD.encode([&nonce[..], &tag, &ciphertext].concat()))
+}
+
+/// Returns `None` when the secret can't be decrypted, like when it was
+/// encrypted with another key.
+fn decrypt(key: &[u8], client_id: i32, encrypted: &str) -> Option<String> {
+	let data = BASE64_STANDARD.decode(encrypted).ok()?;
+	if data.len() < NONCE_LENGTH + TAG_LENGTH {
+		return None;
+	}
+	let (nonce, rest) = data.split_at(NONCE_LENGTH);
+	let (tag, ciphertext) = rest.split_at(TAG_LENGTH);
+	let secret = decrypt_aead(
+		Cipher::aes_256_gcm(),
+		key,
+		Some(nonce),
+		&client_id.to_be_bytes(),
+		ciphertext,
+		tag,
+	)
+	.ok()?;
+	String::from_utf8(secret).ok()
+}
@@ -0,0 +1,151 @@
+use chrono::NaiveDateTime;
+use diesel::{self, prelude::*};
+use std::collections::BTreeSet;
+
+use crate::DbConn;
+
+use super::schema::{clients, consents};
+use super::session::Session;
+use crate::errors::{Result, ZauthError};
+use crate::util::split_scopes;
+
+/// The scopes a user has agreed to share with a client. When a client asks
+/// for scopes that are all covered by the consent, the user isn't asked again.
+#[derive(Queryable, Selectable, Identifiable, Serialize, Debug, Clone)]
+#[diesel(table_name = consents)]
+pub struct Consent {
+	pub id: i32,
+	pub user_id: i32,
+	pub client_id: i32,
+	pub scope: String,
+	pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = consents)]
+struct NewConsent {
+	user_id: i32,
+	client_id: i32,
+	scope: String,
+}
+
+/// A consent together with a description of the client it was given to.
+#[derive(Queryable, Serialize, Debug, Clone)]
+pub struct ConnectedApplication {
+	pub id: i32,
+	pub client_name: String,
+	pub client_description: String,
+	pub scope: String,
+	pub created_at: NaiveDateTime,
+}
+
+/// Scopes are stored sorted and without duplicates, so equivalent scope sets
+/// are stored the same way.
+fn normalize_scopes<'a>(scopes: impl Iterator<Item = &'a str>) -> String {
+	scopes
+		.filter(|scope| !scope.is_empty())
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+impl Consent {
+	pub async fn find(
+		user_id: i32,
+		client_id: i32,
+		db: &DbConn,
+	) -> Result<Option<Consent>> {
+		db.run(move |conn| {
+			consents::table
+				.filter(consents::user_id.eq(user_id))
+				.filter(consents::client_id.eq(client_id))
+				.first(conn)
+				.optional()
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	/// Whether the user agreed to share every requested scope.
+	pub fn covers(&self, scope: &Option<String>) -> bool {
+		let granted: Vec<&str> = self.scope.split(' ').collect();
+		split_scopes(scope)
+			.iter()
+			.filter(|scope| !scope.is_empty())
+			.all(|scope| granted.contains(&scope.as_str()))
+	}
+
+	/// Record that the user agreed to share the given scopes with the client,
+	/// on top of what they agreed to before.
+	pub async fn grant(
+		user_id: i32,
+		client_id: i32,
+		scope: &Option<String>,
+		db: &DbConn,
+	) -> Result<Consent> {
+		let previous = Consent::find(user_id, client_id, db).await?;
+		let requested = split_scopes(scope);
+		let scope = normalize_scopes(
+			previous
+				.iter()
+				.flat_map(|consent| consent.scope.split(' '))
+				.chain(requested.iter().map(String::as_str)),
+		);
+		let consent = NewConsent {
+			user_id,
+			client_id,
+			scope,
+		};
+		db.run(move |conn| {
+			diesel::insert_into(consents::table)
+				.values(&consent)
+				.on_conflict((consents::user_id, consents::client_id))
+				.do_update()
+				.set(consents::scope.eq(&consent.scope))
+				.get_result(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	pub async fn find_by_id(id: i32, db: &DbConn) -> Result<Consent> {
+		db.run(move |conn| consents::table.find(id).first(conn))
+			.await
+			.map_err(ZauthError::from)
+	}
+
+	pub async fn connected_applications(
+		user_id: i32,
+		db: &DbConn,
+	) -> Result<Vec<ConnectedApplication>> {
+		db.run(move |conn| {
+			consents::table
+				.inner_join(clients::table)
+				.filter(consents::user_id.eq(user_id))
+				.select((
+					consents::id,
+					clients::name,
+					clients::description,
+					consents::scope,
+					consents::created_at,
+				))
+				.order(clients::name)
+				.load(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	/// Remove the consent and log the user out of the client, by invalidating
+	/// all its sessions and refresh tokens for this user.
+	pub async fn revoke(self, db: &DbConn) -> Result<()> {
+		Session::invalidate_for_client(self.user_id, self.client_id, db)
+			.await?;
+		db.run(move |conn| {
+			diesel::delete(consents::table.find(self.id)).execute(conn)
+		})
+		.await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,129 @@
+use chrono::NaiveDateTime;
+use diesel::{self, prelude::*};
+
+use crate::DbConn;
+
+use super::schema::device_authorizations;
+use crate::errors::{Result, ZauthError};
+
+/// A device authorization of a `DeviceStore` that keeps them in the database.
+/// `user_id`, `auth_time` and `sid` are only set once the user granted it.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Debug, Clone)]
+#[diesel(table_name = device_authorizations)]
+pub struct StoredDeviceAuthorization {
+	pub device_code: String,
+	pub user_code: String,
+	pub client_id: i32,
+	pub scope: Option<String>,
+	pub state: String,
+	pub user_id: Option<i32>,
+	pub auth_time: Option<NaiveDateTime>,
+	pub sid: Option<String>,
+	pub expires_at: NaiveDateTime,
+	pub last_poll: Option<NaiveDateTime>,
+}
+
+/// The code to find a device authorization with.
+#[derive(Debug, Clone)]
+pub enum DeviceAuthorizationCode {
+	Device(String),
+	User(String),
+}
+
+impl StoredDeviceAuthorization {
+	/// Store the authorization. Returns false when its device code or user
+	/// code is already taken.
+	pub async fn insert(self, db: &DbConn) -> Result<bool> {
+		let count = db
+			.run(move |conn| {
+				diesel::insert_into(device_authorizations::table)
+					.values(&self)
+					.on_conflict_do_nothing()
+					.execute(conn)
+			})
+			.await
+			.map_err(ZauthError::from)?;
+		Ok(count == 1)
+	}
+
+	pub async fn remove_expired_before(
+		before: NaiveDateTime,
+		db: &DbConn,
+	) -> Result<()> {
+		db.run(move |conn| {
+			diesel::delete(
+				device_authorizations::table
+					.filter(device_authorizations::expires_at.le(before)),
+			)
+			.execute(conn)
+		})
+		.await?;
+		Ok(())
+	}
+
+	pub async fn find_by_user_code(
+		user_code: String,
+		db: &DbConn,
+	) -> Result<Option<StoredDeviceAuthorization>> {
+		db.run(move |conn| {
+			device_authorizations::table
+				.filter(device_authorizations::user_code.eq(user_code))
+				.first(conn)
+				.optional()
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	/// Lock the authorization while `change` decides what happens to it, so
+	/// instances of zauth sharing the database can't both act on it. The
+	/// authorization `change` returns is stored, when it returns none the
+	/// authorization is removed. Returns `None` when there is no
+	/// authorization with the code.
+	pub async fn change<F, R>(
+		code: DeviceAuthorizationCode,
+		change: F,
+		db: &DbConn,
+	) -> Result<Option<R>>
+	where
+		F: FnOnce(
+				StoredDeviceAuthorization,
+			) -> (R, Option<StoredDeviceAuthorization>)
+			+ Send
+			+ 'static,
+		R: Send + 'static,
+	{
+		db.run(move |conn| {
+			conn.transaction::<_, diesel::result::Error, _>(|conn| {
+				let query = device_authorizations::table.for_update();
+				let found: Option<StoredDeviceAuthorization> = match code {
+					DeviceAuthorizationCode::Device(device_code) => query
+						.filter(
+							device_authorizations::device_code.eq(device_code),
+						)
+						.first(conn)
+						.optional()?,
+					DeviceAuthorizationCode::User(user_code) => query
+						.filter(device_authorizations::user_code.eq(user_code))
+						.first(conn)
+						.optional()?,
+				};
+				let Some(found) = found else {
+					return Ok(None);
+				};
+				let target = device_authorizations::table
+					.find(found.device_code.clone());
+				let (result, changed) = change(found);
+				match changed {
+					Some(changed) => {
+						diesel::update(target).set(changed).execute(conn)?
+					},
+					None => diesel::delete(target).execute(conn)?,
+				};
+				Ok(Some(result))
+			})
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+}
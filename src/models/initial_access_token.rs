@@ -0,0 +1,103 @@
+use chrono::NaiveDateTime;
+use diesel::{self, prelude::*};
+use validator::Validate;
+
+use crate::DbConn;
+use crate::config::Config;
+use crate::errors::{Result, ZauthError};
+use crate::models::schema::initial_access_tokens;
+use crate::util::{hash_token, random_token};
+
+/// A token admins hand out to let developers register clients themselves
+/// (RFC 7591, section 3). It stays valid until it is deleted.
+///
+/// Only a hash of the token is stored, the token itself is shown once when it
+/// is created.
+#[derive(Serialize, Queryable, Selectable, Debug, Clone)]
+pub struct InitialAccessToken {
+	pub id: i32,
+	#[serde(skip)]
+	pub hashed_token: String,
+	pub description: String,
+	pub created_at: NaiveDateTime,
+}
+
+#[derive(Validate, FromForm, Deserialize, Debug)]
+pub struct NewInitialAccessToken {
+	#[validate(length(min = 1, max = 100))]
+	pub description: String,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = initial_access_tokens)]
+struct NewInitialAccessTokenWithHash {
+	hashed_token: String,
+	description: String,
+}
+
+impl InitialAccessToken {
+	/// Generate a new token. Returns the stored token together with the token
+	/// itself, which can't be retrieved later on.
+	pub async fn create(
+		token: NewInitialAccessToken,
+		conf: &Config,
+		db: &DbConn,
+	) -> Result<(InitialAccessToken, String)> {
+		token.validate()?;
+		let raw_token = random_token(conf.secure_token_length);
+		let token = NewInitialAccessTokenWithHash {
+			hashed_token: hash_token(&raw_token),
+			description: token.description,
+		};
+		let token = db
+			.run(move |conn| {
+				diesel::insert_into(initial_access_tokens::table)
+					.values(&token)
+					.get_result(conn)
+			})
+			.await?;
+		Ok((token, raw_token))
+	}
+
+	pub async fn all(db: &DbConn) -> Result<Vec<InitialAccessToken>> {
+		db.run(move |conn| {
+			initial_access_tokens::table
+				.order(initial_access_tokens::id)
+				.load(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	pub async fn find(id: i32, db: &DbConn) -> Result<InitialAccessToken> {
+		db.run(move |conn| initial_access_tokens::table.find(id).first(conn))
+			.await
+			.map_err(ZauthError::from)
+	}
+
+	/// Tokens are looked up by their hash, so the lookup doesn't tell how
+	/// much of a token was right.
+	pub async fn find_by_token(
+		token: &str,
+		db: &DbConn,
+	) -> Result<Option<InitialAccessToken>> {
+		let hashed_token = hash_token(token);
+		db.run(move |conn| {
+			initial_access_tokens::table
+				.filter(initial_access_tokens::hashed_token.eq(hashed_token))
+				.first(conn)
+				.optional()
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	pub async fn delete(self, db: &DbConn) -> Result<()> {
+		db.run(move |conn| {
+			diesel::delete(initial_access_tokens::table.find(self.id))
+				.execute(conn)
+		})
+		.await?;
+		Ok(())
+	}
+}
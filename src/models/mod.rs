@@ -1,7 +1,15 @@
 pub mod client;
+pub mod client_secret;
+pub mod consent;
+pub mod device_authorization;
+pub mod initial_access_token;
 pub mod mail;
 pub mod passkey;
+pub mod refresh_token;
 pub mod role;
 pub mod schema;
+pub mod scope;
 pub mod session;
+pub mod stored_token;
+pub mod used_assertion;
 pub mod user;
@@ -0,0 +1,166 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+
+use crate::DbConn;
+
+use super::schema::{refresh_tokens, sessions};
+use crate::config::Config;
+use crate::errors::{Result, ZauthError};
+use crate::models::session::Session;
+use crate::util::{hash_token, random_token};
+
+/// A refresh token can be exchanged exactly once for a new access token and a
+/// new refresh token. All refresh tokens that descend from the same
+/// authorization share a `family`, so the whole chain can be revoked when a
+/// token is used twice.
+///
+/// Only a hash of the token is stored, the token itself is only handed to the
+/// client once.
+#[derive(
+	Queryable, Selectable, Identifiable, Associations, AsChangeset, Debug, Clone,
+)]
+#[diesel(belongs_to(Session))]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+	pub id: i32,
+	pub hashed_key: String,
+	pub session_id: i32,
+	pub family: String,
+	pub used: bool,
+	pub valid: bool,
+	pub created_at: NaiveDateTime,
+	pub expires_at: NaiveDateTime,
+	pub auth_time: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = refresh_tokens)]
+struct NewRefreshToken {
+	hashed_key: String,
+	session_id: i32,
+	family: String,
+	expires_at: NaiveDateTime,
+	auth_time: NaiveDateTime,
+}
+
+impl RefreshToken {
+	/// Create a refresh token for the given client session. A new token
+	/// family is started when no family is given. The `auth_time` is the
+	/// moment the user originally logged in, which is carried along to every
+	/// ID token issued with this family. Returns the stored token together with
+	/// the token itself, which can't be retrieved later on.
+	pub async fn create(
+		session: &Session,
+		family: Option<String>,
+		auth_time: NaiveDateTime,
+		conf: &Config,
+		db: &DbConn,
+	) -> Result<(RefreshToken, String)> {
+		let key = random_token(conf.secure_token_length);
+		let token = NewRefreshToken {
+			hashed_key: hash_token(&key),
+			session_id: session.id,
+			family: family
+				.unwrap_or_else(|| random_token(conf.secure_token_length)),
+			expires_at: Utc::now().naive_utc() + conf.refresh_token_duration(),
+			auth_time,
+		};
+		let token = db
+			.run(move |conn| {
+				diesel::insert_into(refresh_tokens::table)
+					.values(&token)
+					.get_result(conn)
+			})
+			.await?;
+		Ok((token, key))
+	}
+
+	/// Find a valid, unexpired refresh token together with the session it was
+	/// issued with. The token is no longer valid once that session is.
+	pub async fn find_by_key(
+		key: String,
+		db: &DbConn,
+	) -> Result<(RefreshToken, Session)> {
+		let now = Utc::now().naive_utc();
+		let hashed_key = hash_token(&key);
+		db.run(move |conn| {
+			refresh_tokens::table
+				.inner_join(sessions::table)
+				.filter(refresh_tokens::hashed_key.eq(hashed_key))
+				.filter(refresh_tokens::valid.eq(true))
+				.filter(sessions::valid.eq(true))
+				.filter(refresh_tokens::expires_at.gt(now))
+				.select((RefreshToken::as_select(), sessions::all_columns))
+				.first(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	/// Atomically mark this token as used. Returns false when the token had
+	/// already been used before.
+	pub async fn mark_used(&self, db: &DbConn) -> Result<bool> {
+		let id = self.id;
+		let count = db
+			.run(move |conn| {
+				diesel::update(
+					refresh_tokens::table
+						.filter(refresh_tokens::id.eq(id))
+						.filter(refresh_tokens::used.eq(false)),
+				)
+				.set(refresh_tokens::used.eq(true))
+				.execute(conn)
+			})
+			.await?;
+		Ok(count == 1)
+	}
+
+	/// Invalidate every refresh token in this token's family, together with
+	/// the access tokens that were issued alongside them.
+	pub async fn revoke_family(&self, db: &DbConn) -> Result<()> {
+		let family = self.family.clone();
+		db.run(move |conn| {
+			conn.transaction(|conn| {
+				let session_ids = refresh_tokens::table
+					.filter(refresh_tokens::family.eq(&family))
+					.select(refresh_tokens::session_id);
+				diesel::update(
+					sessions::table.filter(sessions::id.eq_any(session_ids)),
+				)
+				.set(sessions::valid.eq(false))
+				.execute(conn)?;
+				diesel::update(
+					refresh_tokens::table
+						.filter(refresh_tokens::family.eq(&family)),
+				)
+				.set(refresh_tokens::valid.eq(false))
+				.execute(conn)
+			})
+		})
+		.await?;
+		Ok(())
+	}
+
+	/// Invalidate the refresh tokens of every family that has a token issued
+	/// together with the given session.
+	pub async fn revoke_for_session(
+		session: &Session,
+		db: &DbConn,
+	) -> Result<()> {
+		let session_id = session.id;
+		db.run(move |conn| {
+			let families: Vec<String> = refresh_tokens::table
+				.filter(refresh_tokens::session_id.eq(session_id))
+				.select(refresh_tokens::family)
+				.load(conn)?;
+			diesel::update(
+				refresh_tokens::table
+					.filter(refresh_tokens::family.eq_any(families)),
+			)
+			.set(refresh_tokens::valid.eq(false))
+			.execute(conn)
+		})
+		.await?;
+		Ok(())
+	}
+}
@@ -10,17 +10,45 @@ pub mod sql_types {
 	pub struct UserState;
 }
 
+diesel::table! {
+	client_secrets (id) {
+		id -> Int4,
+		client_id -> Int4,
+		#[max_length = 255]
+		label -> Varchar,
+		#[max_length = 255]
+		hashed_secret -> Varchar,
+		created_at -> Timestamp,
+		expires_at -> Nullable<Timestamp>,
+		encrypted_secret -> Nullable<Text>,
+	}
+}
+
 diesel::table! {
 	clients (id) {
 		id -> Int4,
 		#[max_length = 255]
 		name -> Varchar,
 		description -> Text,
-		#[max_length = 255]
-		secret -> Varchar,
 		needs_grant -> Bool,
 		redirect_uri_list -> Text,
 		created_at -> Timestamp,
+		pkce_required -> Bool,
+		userinfo_signed -> Bool,
+		jwt_access_tokens -> Bool,
+		allowed_scopes -> Text,
+		post_logout_redirect_uri_list -> Text,
+		backchannel_logout_uri -> Text,
+		token_exchange_audiences -> Text,
+		jwks -> Text,
+		jwks_uri -> Text,
+		#[max_length = 255]
+		hashed_registration_access_token -> Nullable<Varchar>,
+		public -> Bool,
+		par_required -> Bool,
+		signed_request_required -> Bool,
+		request_uri_list -> Text,
+		secret_jwt_allowed -> Bool,
 	}
 }
 
@@ -31,6 +59,42 @@ diesel::table! {
 	}
 }
 
+diesel::table! {
+	consents (id) {
+		id -> Int4,
+		user_id -> Int4,
+		client_id -> Int4,
+		scope -> Text,
+		created_at -> Timestamp,
+	}
+}
+
+diesel::table! {
+	device_authorizations (device_code) {
+		device_code -> Text,
+		user_code -> Text,
+		client_id -> Int4,
+		scope -> Nullable<Text>,
+		state -> Text,
+		user_id -> Nullable<Int4>,
+		auth_time -> Nullable<Timestamp>,
+		sid -> Nullable<Text>,
+		expires_at -> Timestamp,
+		last_poll -> Nullable<Timestamp>,
+	}
+}
+
+diesel::table! {
+	initial_access_tokens (id) {
+		id -> Int4,
+		#[max_length = 255]
+		hashed_token -> Varchar,
+		#[max_length = 255]
+		description -> Varchar,
+		created_at -> Timestamp,
+	}
+}
+
 diesel::table! {
 	use diesel::sql_types::*;
 	use super::sql_types::ContentType;
@@ -59,6 +123,22 @@ diesel::table! {
 	}
 }
 
+diesel::table! {
+	refresh_tokens (id) {
+		id -> Int4,
+		#[max_length = 255]
+		hashed_key -> Varchar,
+		session_id -> Int4,
+		#[max_length = 255]
+		family -> Varchar,
+		used -> Bool,
+		valid -> Bool,
+		created_at -> Timestamp,
+		expires_at -> Timestamp,
+		auth_time -> Timestamp,
+	}
+}
+
 diesel::table! {
 	roles (id) {
 		id -> Int4,
@@ -70,6 +150,16 @@ diesel::table! {
 	}
 }
 
+diesel::table! {
+	scopes (id) {
+		id -> Int4,
+		#[max_length = 255]
+		name -> Varchar,
+		#[max_length = 255]
+		description -> Varchar,
+	}
+}
+
 diesel::table! {
 	sessions (id) {
 		id -> Int4,
@@ -81,6 +171,9 @@ diesel::table! {
 		expires_at -> Timestamp,
 		valid -> Bool,
 		scope -> Nullable<Text>,
+		#[max_length = 255]
+		sid -> Nullable<Varchar>,
+		authenticated_at -> Timestamp,
 	}
 }
 
@@ -117,6 +210,24 @@ diesel::table! {
 	}
 }
 
+diesel::table! {
+	stored_tokens (token) {
+		token -> Text,
+		kind -> Text,
+		item -> Text,
+		expires_at -> Timestamp,
+	}
+}
+
+diesel::table! {
+	used_assertions (id) {
+		id -> Int4,
+		client_id -> Int4,
+		jti -> Text,
+		expires_at -> Timestamp,
+	}
+}
+
 diesel::table! {
 	users_roles (user_id, role_id) {
 		user_id -> Int4,
@@ -124,22 +235,37 @@ diesel::table! {
 	}
 }
 
+diesel::joinable!(client_secrets -> clients (client_id));
 diesel::joinable!(clients_roles -> clients (client_id));
 diesel::joinable!(clients_roles -> roles (role_id));
+diesel::joinable!(consents -> clients (client_id));
+diesel::joinable!(consents -> users (user_id));
+diesel::joinable!(device_authorizations -> clients (client_id));
+diesel::joinable!(device_authorizations -> users (user_id));
 diesel::joinable!(passkeys -> users (user_id));
+diesel::joinable!(refresh_tokens -> sessions (session_id));
 diesel::joinable!(roles -> clients (client_id));
 diesel::joinable!(sessions -> clients (client_id));
 diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(used_assertions -> clients (client_id));
 diesel::joinable!(users_roles -> roles (role_id));
 diesel::joinable!(users_roles -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+	client_secrets,
 	clients,
 	clients_roles,
+	consents,
+	device_authorizations,
+	initial_access_tokens,
 	mails,
 	passkeys,
+	refresh_tokens,
 	roles,
+	scopes,
 	sessions,
+	stored_tokens,
+	used_assertions,
 	users,
 	users_roles,
 );
@@ -0,0 +1,149 @@
+use diesel::{self, prelude::*};
+use regex::Regex;
+use std::sync::LazyLock;
+use validator::Validate;
+
+use crate::DbConn;
+use crate::errors::{OAuthError, Result, ZauthError};
+use crate::models::client::Client;
+use crate::models::schema::scopes;
+use crate::util::split_scopes;
+
+/// Characters allowed in a scope token (RFC 6749, section 3.3).
+static SCOPE_NAME_REGEX: LazyLock<Regex> =
+	LazyLock::new(|| Regex::new(r"^[\x21\x23-\x5B\x5D-\x7E]+$").unwrap());
+
+/// A scope clients can request, with a description we show to users on the
+/// grant page.
+#[derive(
+	Deserialize, Serialize, Queryable, Debug, Clone, PartialEq, Selectable,
+)]
+pub struct Scope {
+	pub id: i32,
+	pub name: String,
+	pub description: String,
+}
+
+#[derive(Validate, FromForm, Debug, Insertable, Deserialize)]
+#[diesel(table_name = scopes)]
+pub struct NewScope {
+	#[validate(
+		length(min = 1, max = 50),
+		regex(
+			path = *SCOPE_NAME_REGEX,
+			message = "Scope names can't contain spaces, quotes or backslashes."
+		)
+	)]
+	pub name: String,
+	#[validate(length(min = 1, max = 100))]
+	pub description: String,
+}
+
+impl Scope {
+	pub async fn create(scope: NewScope, db: &DbConn) -> Result<Scope> {
+		scope.validate()?;
+
+		db.run(move |conn| {
+			diesel::insert_into(scopes::table)
+				.values(&scope)
+				.get_result::<Scope>(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	pub async fn all(db: &DbConn) -> Result<Vec<Scope>> {
+		let all_scopes = db
+			.run(move |conn| {
+				scopes::table.order(scopes::id).load::<Scope>(conn)
+			})
+			.await?;
+		Ok(all_scopes)
+	}
+
+	pub async fn find(id: i32, db: &DbConn) -> Result<Self> {
+		db.run(move |conn| scopes::table.find(id).first(conn))
+			.await
+			.map_err(ZauthError::from)
+	}
+
+	/// Find the registered scopes with the given names, in registry order.
+	/// Unknown names are left out.
+	pub async fn find_by_names(
+		names: Vec<String>,
+		db: &DbConn,
+	) -> Result<Vec<Scope>> {
+		db.run(move |conn| {
+			scopes::table
+				.filter(scopes::name.eq_any(names))
+				.order(scopes::id)
+				.load::<Scope>(conn)
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+
+	/// Restrict the requested scope to the scopes the client is allowed to
+	/// request. Asking for a scope that isn't registered at all is an error.
+	pub async fn permitted(
+		client: &Client,
+		scope: &Option<String>,
+		db: &DbConn,
+	) -> Result<Option<String>> {
+		let requested: Vec<String> = split_scopes(scope)
+			.into_iter()
+			.filter(|scope| !scope.is_empty())
+			.collect();
+		let registered = Scope::find_by_names(requested.clone(), db).await?;
+		if let Some(unknown) = requested
+			.iter()
+			.find(|name| !registered.iter().any(|scope| &scope.name == *name))
+		{
+			return Err(ZauthError::from(OAuthError::UnknownScope(
+				unknown.clone(),
+			)));
+		}
+		Ok(Self::allowed_for(client, requested))
+	}
+
+	/// Restrict a scope that was granted before to the scopes that are still
+	/// registered and that the client is still allowed to request. Scopes
+	/// removed from the registry in the meantime are left out.
+	pub async fn still_permitted(
+		client: &Client,
+		scope: &Option<String>,
+		db: &DbConn,
+	) -> Result<Option<String>> {
+		let granted: Vec<String> = split_scopes(scope)
+			.into_iter()
+			.filter(|scope| !scope.is_empty())
+			.collect();
+		let registered = Scope::find_by_names(granted.clone(), db).await?;
+		let granted = granted
+			.into_iter()
+			.filter(|name| registered.iter().any(|scope| &scope.name == name))
+			.collect();
+		Ok(Self::allowed_for(client, granted))
+	}
+
+	fn allowed_for(client: &Client, names: Vec<String>) -> Option<String> {
+		let permitted = names
+			.into_iter()
+			.filter(|name| client.scope_allowed(name))
+			.collect::<Vec<String>>();
+		if permitted.is_empty() {
+			None
+		} else {
+			Some(permitted.join(" "))
+		}
+	}
+
+	pub async fn delete(self, db: &DbConn) -> Result<()> {
+		db.run(move |conn| {
+			diesel::delete(scopes::table.find(self.id)).execute(conn)
+		})
+		.await
+		.map_err(ZauthError::from)?;
+		Ok(())
+	}
+}
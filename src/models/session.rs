@@ -3,9 +3,10 @@ use diesel::{self, prelude::*};
 
 use crate::DbConn;
 
-use super::schema::sessions;
+use super::schema::{refresh_tokens, sessions};
 use crate::config::Config;
 use crate::errors::{Result, ZauthError};
+use crate::jwt::JWTBuilder;
 use crate::models::client::Client;
 use crate::models::user::User;
 use crate::util::random_token;
@@ -23,6 +24,8 @@ pub struct Session {
 	pub expires_at: NaiveDateTime,
 	pub valid: bool,
 	pub scope: Option<String>,
+	pub sid: Option<String>,
+	pub authenticated_at: NaiveDateTime,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -34,8 +37,13 @@ pub struct NewSession {
 	pub created_at: NaiveDateTime,
 	pub expires_at: NaiveDateTime,
 	pub scope: Option<String>,
+	pub sid: Option<String>,
+	pub authenticated_at: NaiveDateTime,
 }
 
+/// Length of the session id we share with clients in ID tokens.
+const SID_LENGTH: usize = 32;
+
 impl Session {
 	pub async fn create(
 		user: &User,
@@ -51,6 +59,8 @@ impl Session {
 			created_at,
 			expires_at,
 			scope: None,
+			sid: Some(random_token(SID_LENGTH)),
+			authenticated_at: created_at,
 		};
 		db.run(move |conn| {
 			conn.transaction(|conn| {
@@ -66,10 +76,14 @@ impl Session {
 		.await
 	}
 
+	/// Create a session for a client to act on behalf of the user, or on its
+	/// own behalf without a user. The `sid` is that of the user session the
+	/// client was authorized in.
 	pub async fn create_client_session(
 		user: Option<&User>,
 		client: &Client,
 		scope: Option<String>,
+		sid: Option<String>,
 		conf: &Config,
 		db: &DbConn,
 	) -> Result<Session> {
@@ -83,6 +97,8 @@ impl Session {
 			created_at,
 			expires_at,
 			scope,
+			sid,
+			authenticated_at: created_at,
 		};
 		db.run(move |conn| {
 			conn.transaction(|conn| {
@@ -131,6 +147,19 @@ impl Session {
 		Ok(session)
 	}
 
+	/// Find the session behind an access token, which is either the session
+	/// key or a JWT access token that carries the session id as its `jti`.
+	pub async fn find_by_access_token(
+		token: String,
+		jwt_builder: &JWTBuilder,
+		db: &DbConn,
+	) -> Result<Session> {
+		match jwt_builder.decode_access_token(&token) {
+			Some(id) => Self::find_by_id(id, db).await,
+			None => Self::find_by_key(token, db).await,
+		}
+	}
+
 	pub async fn find_by_id(id: i32, db: &DbConn) -> Result<Session> {
 		let now = Utc::now().naive_utc();
 		let session = db
@@ -145,11 +174,83 @@ impl Session {
 		Ok(session)
 	}
 
+	/// Record that the user logged in again, without ending the session.
+	pub async fn reauthenticate(mut self, db: &DbConn) -> Result<Session> {
+		self.authenticated_at = Utc::now().naive_utc();
+		self.update(db).await
+	}
+
 	pub async fn invalidate(mut self, db: &DbConn) -> Result<Session> {
 		self.valid = false;
 		self.update(db).await
 	}
 
+	/// Invalidate all sessions a client has for a user, together with their
+	/// refresh tokens.
+	pub async fn invalidate_for_client(
+		user_id: i32,
+		client_id: i32,
+		db: &DbConn,
+	) -> Result<()> {
+		db.run(move |conn| {
+			conn.transaction(|conn| {
+				let client_sessions = sessions::table
+					.filter(sessions::user_id.eq(user_id))
+					.filter(sessions::client_id.eq(client_id));
+				diesel::update(
+					refresh_tokens::table.filter(
+						refresh_tokens::session_id
+							.eq_any(client_sessions.select(sessions::id)),
+					),
+				)
+				.set(refresh_tokens::valid.eq(false))
+				.execute(conn)?;
+				diesel::update(client_sessions)
+					.set(sessions::valid.eq(false))
+					.execute(conn)
+			})
+		})
+		.await?;
+		Ok(())
+	}
+
+	/// The clients that got a session during the user session with this
+	/// session id.
+	pub async fn client_ids_for_sid(
+		sid: String,
+		db: &DbConn,
+	) -> Result<Vec<i32>> {
+		let client_ids = db
+			.run(move |conn| {
+				sessions::table
+					.filter(sessions::sid.eq(sid))
+					.filter(sessions::client_id.is_not_null())
+					.select(sessions::client_id)
+					.distinct()
+					.load::<Option<i32>>(conn)
+			})
+			.await?;
+		Ok(client_ids.into_iter().flatten().collect())
+	}
+
+	/// The clients that ever got a session for this user.
+	pub async fn client_ids_for_user(
+		user_id: i32,
+		db: &DbConn,
+	) -> Result<Vec<i32>> {
+		let client_ids = db
+			.run(move |conn| {
+				sessions::table
+					.filter(sessions::user_id.eq(user_id))
+					.filter(sessions::client_id.is_not_null())
+					.select(sessions::client_id)
+					.distinct()
+					.load::<Option<i32>>(conn)
+			})
+			.await?;
+		Ok(client_ids.into_iter().flatten().collect())
+	}
+
 	pub async fn user(&self, db: &DbConn) -> Result<User> {
 		User::find(
 			self.user_id.ok_or(ZauthError::Unprocessable(
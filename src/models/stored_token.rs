@@ -0,0 +1,66 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+
+use crate::DbConn;
+
+use super::schema::stored_tokens;
+use crate::errors::{Result, ZauthError};
+
+/// A token of a `DatabaseTokenStore`. The `kind` tells which store it belongs
+/// to, the item it stands for is kept as JSON.
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = stored_tokens)]
+pub struct StoredToken {
+	pub token: String,
+	pub kind: String,
+	pub item: String,
+	pub expires_at: NaiveDateTime,
+}
+
+impl StoredToken {
+	/// Store the token and remove the ones that have expired. Returns false
+	/// when the token already exists.
+	pub async fn insert(self, db: &DbConn) -> Result<bool> {
+		let now = Utc::now().naive_utc();
+		let count = db
+			.run(move |conn| {
+				conn.transaction(|conn| {
+					diesel::delete(
+						stored_tokens::table
+							.filter(stored_tokens::expires_at.le(now)),
+					)
+					.execute(conn)?;
+					diesel::insert_into(stored_tokens::table)
+						.values(&self)
+						.on_conflict_do_nothing()
+						.execute(conn)
+				})
+			})
+			.await
+			.map_err(ZauthError::from)?;
+		Ok(count == 1)
+	}
+
+	/// Remove the token and return it, unless it has expired. This happens in
+	/// a single statement, so a token can only be taken once, even when
+	/// several instances of zauth share the database.
+	pub async fn take(
+		token: String,
+		kind: &'static str,
+		db: &DbConn,
+	) -> Result<Option<StoredToken>> {
+		let now = Utc::now().naive_utc();
+		db.run(move |conn| {
+			diesel::delete(
+				stored_tokens::table
+					.filter(stored_tokens::token.eq(token))
+					.filter(stored_tokens::kind.eq(kind))
+					.filter(stored_tokens::expires_at.gt(now)),
+			)
+			.get_result(conn)
+			.optional()
+		})
+		.await
+		.map_err(ZauthError::from)
+	}
+}
@@ -0,0 +1,62 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::{self, prelude::*};
+
+use crate::DbConn;
+
+use super::schema::used_assertions;
+use crate::errors::{Result, ZauthError};
+
+/// The `jti` of a client assertion that has already been used to
+/// authenticate. It is remembered until the assertion expires, so the same
+/// assertion can't be used twice.
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone)]
+#[diesel(table_name = used_assertions)]
+pub struct UsedAssertion {
+	pub id: i32,
+	pub client_id: i32,
+	pub jti: String,
+	pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = used_assertions)]
+struct NewUsedAssertion {
+	client_id: i32,
+	jti: String,
+	expires_at: NaiveDateTime,
+}
+
+impl UsedAssertion {
+	/// Atomically remember that the client used the assertion with this
+	/// `jti`. Returns false when the assertion had already been used before.
+	pub async fn record(
+		client_id: i32,
+		jti: String,
+		expires_at: NaiveDateTime,
+		db: &DbConn,
+	) -> Result<bool> {
+		let now = Utc::now().naive_utc();
+		let assertion = NewUsedAssertion {
+			client_id,
+			jti,
+			expires_at,
+		};
+		let count = db
+			.run(move |conn| {
+				conn.transaction(|conn| {
+					diesel::delete(
+						used_assertions::table
+							.filter(used_assertions::expires_at.le(now)),
+					)
+					.execute(conn)?;
+					diesel::insert_into(used_assertions::table)
+						.values(&assertion)
+						.on_conflict_do_nothing()
+						.execute(conn)
+				})
+			})
+			.await
+			.map_err(ZauthError::from)?;
+		Ok(count == 1)
+	}
+}
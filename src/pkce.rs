@@ -0,0 +1,94 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use openssl::memcmp;
+use openssl::sha::sha256;
+
+use crate::errors::{OAuthError, Result, ZauthError};
+
+/// Transformation applied by the client to the code verifier to obtain the
+/// code challenge (RFC 7636, section 4.2).
+#[derive(
+	Serialize,
+	Deserialize,
+	FromFormField,
+	UriDisplayQuery,
+	Debug,
+	Clone,
+	Copy,
+	PartialEq,
+)]
+pub enum CodeChallengeMethod {
+	Plain,
+	S256,
+}
+
+impl CodeChallengeMethod {
+	pub fn parse(method: Option<&str>) -> Result<Self> {
+		match method {
+			None | Some("plain") => Ok(CodeChallengeMethod::Plain),
+			Some("S256") => Ok(CodeChallengeMethod::S256),
+			Some(other) => Err(ZauthError::from(
+				OAuthError::UnsupportedChallengeMethod(other.to_string()),
+			)),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, FromForm, UriDisplayQuery, Debug, Clone)]
+pub struct CodeChallenge {
+	pub challenge: String,
+	pub method: CodeChallengeMethod,
+}
+
+impl CodeChallenge {
+	/// Build a code challenge from the parameters of an authorization request.
+	///
+	/// Returns `None` when the client did not send a challenge at all, and an
+	/// error when the challenge or its method is malformed.
+	pub fn from_request(
+		challenge: Option<String>,
+		method: Option<String>,
+	) -> Result<Option<Self>> {
+		match challenge {
+			Some(challenge) => {
+				if !is_valid_code(&challenge) {
+					return Err(ZauthError::from(
+						OAuthError::InvalidCodeChallenge,
+					));
+				}
+				Ok(Some(CodeChallenge {
+					challenge,
+					method: CodeChallengeMethod::parse(method.as_deref())?,
+				}))
+			},
+			None if method.is_some() => {
+				Err(ZauthError::from(OAuthError::InvalidCodeChallenge))
+			},
+			None => Ok(None),
+		}
+	}
+
+	/// Check whether the given code verifier matches this challenge.
+	pub fn verify(&self, verifier: &str) -> bool {
+		if !is_valid_code(verifier) {
+			return false;
+		}
+		let transformed = match self.method {
+			CodeChallengeMethod::Plain => verifier.to_string(),
+			CodeChallengeMethod::S256 => {
+				URL_SAFE_NO_PAD.encode(sha256(verifier.as_bytes()))
+			},
+		};
+		transformed.len() == self.challenge.len()
+			&& memcmp::eq(transformed.as_bytes(), self.challenge.as_bytes())
+	}
+}
+
+/// Both the code verifier and the code challenge consist of 43 to 128
+/// unreserved URI characters.
+fn is_valid_code(code: &str) -> bool {
+	(43..=128).contains(&code.len())
+		&& code.chars().all(|c| {
+			c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~')
+		})
+}
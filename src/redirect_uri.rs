@@ -0,0 +1,117 @@
+use rocket::http::uri::{Absolute, Authority};
+
+/// Marks an entry in the redirect URI list of a client as a wildcard pattern
+/// instead of a URI that has to match exactly.
+pub const PATTERN_PREFIX: &str = "pattern:";
+
+/// Loopback addresses native apps can receive redirects on. The app picks a
+/// free port when it starts, so the port is ignored when matching these
+/// (RFC 8252, section 7.3).
+const LOOPBACK_HOSTS: [&str; 2] = ["127.0.0.1", "[::1]"];
+
+/// Whether an entry of a client's redirect URI list allows `redirect_uri`.
+pub fn entry_matches(entry: &str, redirect_uri: &str) -> bool {
+	match entry.strip_prefix(PATTERN_PREFIX) {
+		Some(pattern) => pattern_matches(pattern, redirect_uri),
+		None => entry == redirect_uri || loopback_matches(entry, redirect_uri),
+	}
+}
+
+/// Check that a wildcard pattern follows the rules, which keep it from
+/// matching hosts the client does not control:
+///
+/// - it uses https;
+/// - it contains exactly one `*`, in the first label of the host;
+/// - at least two fixed labels follow the wildcard label;
+/// - it contains no user info.
+///
+/// The `*` stands for one or more letters, digits or dashes. Everything else
+/// in the URI has to match exactly.
+pub fn validate_pattern(pattern: &str) -> Result<(), &'static str> {
+	let uri = Absolute::parse(pattern)
+		.map_err(|_| "Redirect URI patterns have to be absolute URIs.")?;
+	if uri.scheme() != "https" {
+		return Err("Redirect URI patterns have to use https.");
+	}
+	let authority = uri
+		.authority()
+		.ok_or("Redirect URI patterns need a host.")?;
+	if authority.user_info().is_some() {
+		return Err("Redirect URI patterns can't contain user info.");
+	}
+	if pattern.matches('*').count() != 1 {
+		return Err("Redirect URI patterns need exactly one wildcard.");
+	}
+	let mut labels = authority.host().split('.');
+	if !labels.next().is_some_and(|label| label.contains('*')) {
+		return Err("The wildcard has to be in the first label of the host.");
+	}
+	if labels.filter(|label| !label.is_empty()).count() < 2 {
+		return Err("The wildcard has to be followed by at least two labels.");
+	}
+	Ok(())
+}
+
+fn loopback_matches(registered: &str, redirect_uri: &str) -> bool {
+	let (Ok(registered), Ok(requested)) =
+		(Absolute::parse(registered), Absolute::parse(redirect_uri))
+	else {
+		return false;
+	};
+	let (Some(registered_authority), Some(requested_authority)) =
+		(registered.authority(), requested.authority())
+	else {
+		return false;
+	};
+	registered.scheme() == "http"
+		&& requested.scheme() == "http"
+		&& LOOPBACK_HOSTS.contains(&registered_authority.host())
+		&& registered_authority.host() == requested_authority.host()
+		&& requested_authority.user_info().is_none()
+		&& same_path_and_query(&registered, &requested)
+}
+
+fn pattern_matches(pattern: &str, redirect_uri: &str) -> bool {
+	if validate_pattern(pattern).is_err() {
+		return false;
+	}
+	let (Ok(pattern), Ok(requested)) =
+		(Absolute::parse(pattern), Absolute::parse(redirect_uri))
+	else {
+		return false;
+	};
+	let (Some(pattern_authority), Some(requested_authority)) =
+		(pattern.authority(), requested.authority())
+	else {
+		return false;
+	};
+	requested.scheme() == pattern.scheme()
+		&& host_matches(pattern_authority, requested_authority)
+		&& requested_authority.user_info().is_none()
+		&& requested_authority.port() == pattern_authority.port()
+		&& same_path_and_query(&pattern, &requested)
+}
+
+/// Match the host against a pattern host that passed `validate_pattern`.
+fn host_matches(pattern: &Authority, requested: &Authority) -> bool {
+	let (Some((pattern_label, pattern_rest)), Some((label, rest))) = (
+		pattern.host().split_once('.'),
+		requested.host().split_once('.'),
+	) else {
+		return false;
+	};
+	let (prefix, suffix) = pattern_label.split_once('*').unwrap_or_default();
+	rest.eq_ignore_ascii_case(pattern_rest)
+		&& label.len() > prefix.len() + suffix.len()
+		&& label.starts_with(prefix)
+		&& label.ends_with(suffix)
+		&& label[prefix.len()..label.len() - suffix.len()]
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn same_path_and_query(registered: &Absolute, requested: &Absolute) -> bool {
+	registered.path().as_str() == requested.path().as_str()
+		&& registered.query().map(|query| query.as_str())
+			== requested.query().map(|query| query.as_str())
+}
@@ -0,0 +1,127 @@
+use jsonwebtoken::{DecodingKey, Validation, decode, decode_header};
+use log::warn;
+
+use crate::client_assertion::{KEY_ALGORITHMS, client_jwks};
+use crate::config::Config;
+use crate::controllers::oauth_controller::AuthorizationRequest;
+use crate::errors::{OAuthError, Result, ZauthError};
+use crate::http_client::HttpClient;
+use crate::models::client::Client;
+
+/// The parameters of an authorization request, signed by the client (RFC
+/// 9101). Parameters sent outside of it are not signed, so they are ignored.
+#[derive(Deserialize, Debug)]
+pub struct RequestObject {
+	client_id: Option<String>,
+	response_type: Option<String>,
+	redirect_uri: Option<String>,
+	scope: Option<String>,
+	state: Option<String>,
+	code_challenge: Option<String>,
+	code_challenge_method: Option<String>,
+	nonce: Option<String>,
+	prompt: Option<String>,
+	max_age: Option<i64>,
+	login_hint: Option<String>,
+	response_mode: Option<String>,
+}
+
+impl RequestObject {
+	/// Check that the request object is signed with one of the keys of the
+	/// client and that it was meant for us (RFC 9101, section 6).
+	pub async fn verify(
+		jwt: &str,
+		client: &Client,
+		http_client: &HttpClient,
+		config: &Config,
+	) -> Result<RequestObject> {
+		let invalid = |reason: &str| {
+			ZauthError::from(OAuthError::InvalidRequestObject(reason.into()))
+		};
+		let header = decode_header(jwt).map_err(|_| invalid("not a JWT"))?;
+		if !KEY_ALGORITHMS.contains(&header.alg) {
+			return Err(invalid("unsupported signing algorithm"));
+		}
+		let jwks = client_jwks(client, http_client)
+			.await
+			.ok_or_else(|| invalid("the client has no keys"))?;
+
+		let mut validation = Validation::new(header.alg);
+		validation.set_issuer(&[&client.name]);
+		validation.set_audience(&[config.base_url().to_string()]);
+		validation.set_required_spec_claims(&["iss", "aud", "exp"]);
+		let object = jwks
+			.keys
+			.iter()
+			.filter(|jwk| {
+				header.kid.is_none() || jwk.common.key_id == header.kid
+			})
+			.filter_map(|jwk| DecodingKey::from_jwk(jwk).ok())
+			.find_map(|key| {
+				decode::<RequestObject>(jwt, &key, &validation).ok()
+			})
+			.ok_or_else(|| invalid("invalid signature or claims"))?
+			.claims;
+		if object
+			.client_id
+			.as_ref()
+			.is_some_and(|client_id| *client_id != client.name)
+		{
+			return Err(invalid("client_id does not match"));
+		}
+		Ok(object)
+	}
+
+	/// Fetch the request object a client passed by reference (RFC 9101,
+	/// section 5.2). Only clients that can sign request objects may do so,
+	/// and only from a URI they registered.
+	pub async fn fetch(
+		request_uri: &str,
+		client: &Client,
+		http_client: &HttpClient,
+	) -> Result<String> {
+		let invalid = |reason: &str| {
+			ZauthError::from(OAuthError::InvalidRequestUri(format!(
+				"'{}' {}",
+				request_uri, reason
+			)))
+		};
+		if client.jwks().is_none() && client.jwks_uri().is_none() {
+			return Err(invalid("can't be used by a client without keys"));
+		}
+		if !client.request_uri_acceptable(request_uri) {
+			return Err(invalid("is not registered for the client"));
+		}
+		match http_client.get_text(request_uri).await {
+			Ok(text) => Ok(text.trim().to_string()),
+			Err(err) => {
+				warn!(
+					"Error fetching request object of client {} from {}: {}",
+					client.name, request_uri, err
+				);
+				Err(invalid("could not be fetched"))
+			},
+		}
+	}
+
+	/// The authorization request made up of only the parameters in the
+	/// request object (RFC 9101, section 6.3).
+	pub fn into_request(self, client_id: String) -> AuthorizationRequest {
+		AuthorizationRequest {
+			response_type: self.response_type,
+			client_id,
+			redirect_uri: self.redirect_uri,
+			scope: self.scope,
+			state: self.state,
+			code_challenge: self.code_challenge,
+			code_challenge_method: self.code_challenge_method,
+			nonce: self.nonce,
+			prompt: self.prompt,
+			max_age: self.max_age,
+			login_hint: self.login_hint,
+			response_mode: self.response_mode,
+			request: None,
+			request_uri: None,
+		}
+	}
+}
@@ -1,8 +1,14 @@
-use crate::config::Config;
+use crate::DbConn;
+use crate::config::{Config, TokenStoreBackend};
+use crate::errors::{InternalError, Result};
+use crate::models::stored_token::StoredToken;
 use crate::util;
-use chrono::{DateTime, Duration, Local};
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
 use rocket::tokio::sync::Mutex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::marker::PhantomData;
 
 #[derive(Debug)]
 pub struct Token<T> {
@@ -11,18 +17,59 @@ pub struct Token<T> {
 	pub expiry: DateTime<Local>,
 }
 
+/// Hands out short-lived random tokens that stand for an item, like an
+/// authorization code stands for the authorization the user gave. A token
+/// can be exchanged for its item only once, before it expires.
+#[rocket::async_trait]
+pub trait TokenStore<T: Send + 'static>: Send + Sync {
+	fn token_validity(&self) -> Duration;
+
+	async fn create_token(&self, item: T, db: &DbConn) -> Result<String>;
+
+	async fn fetch_token(
+		&self,
+		token_str: String,
+		db: &DbConn,
+	) -> Result<Option<Token<T>>>;
+}
+
+/// Create the token store configured with `token_store`. The `kind` keeps
+/// tokens of different stores apart in the database.
+pub fn from_config<T>(
+	kind: &'static str,
+	token_validity: Duration,
+	config: &Config,
+) -> Box<dyn TokenStore<T>>
+where
+	T: Serialize + DeserializeOwned + Send + 'static,
+{
+	match config.token_store {
+		TokenStoreBackend::Memory => {
+			Box::new(MemoryTokenStore::with_validity(token_validity, config))
+		},
+		TokenStoreBackend::Database => {
+			Box::new(DatabaseTokenStore::new(kind, token_validity, config))
+		},
+	}
+}
+
+/// Keeps the tokens in memory. They are lost when zauth restarts and can't
+/// be shared with other instances.
 #[derive(Debug)]
-pub struct TokenStore<T> {
+pub struct MemoryTokenStore<T> {
 	tokens: Mutex<HashMap<String, Token<T>>>,
 	token_validity: Duration,
 	token_length: usize,
 }
 
-impl<T> TokenStore<T> {
-	pub fn new(config: &Config) -> TokenStore<T> {
-		TokenStore {
+impl<T> MemoryTokenStore<T> {
+	pub fn with_validity(
+		token_validity: Duration,
+		config: &Config,
+	) -> MemoryTokenStore<T> {
+		MemoryTokenStore {
 			tokens: Mutex::new(HashMap::new()),
-			token_validity: config.authorization_token_duration(),
+			token_validity,
 			token_length: config.secure_token_length,
 		}
 	}
@@ -35,8 +82,15 @@ impl<T> TokenStore<T> {
 		let now = Local::now();
 		tokens.retain(|_key, token| now < token.expiry);
 	}
+}
+
+#[rocket::async_trait]
+impl<T: Send + 'static> TokenStore<T> for MemoryTokenStore<T> {
+	fn token_validity(&self) -> Duration {
+		self.token_validity
+	}
 
-	pub async fn create_token(&self, item: T) -> String {
+	async fn create_token(&self, item: T, _db: &DbConn) -> Result<String> {
 		let mut tokens = self.tokens.lock().await;
 
 		Self::remove_expired_tokens(&mut tokens);
@@ -52,12 +106,85 @@ impl<T> TokenStore<T> {
 			token.token_str = token_str.clone();
 		}
 		tokens.insert(token_str.clone(), token);
-		token_str
+		Ok(token_str)
 	}
 
-	pub async fn fetch_token(&self, token_str: String) -> Option<Token<T>> {
+	async fn fetch_token(
+		&self,
+		token_str: String,
+		_db: &DbConn,
+	) -> Result<Option<Token<T>>> {
 		let tokens = &mut self.tokens.lock().await;
 		Self::remove_expired_tokens(tokens);
-		tokens.remove(&token_str)
+		Ok(tokens.remove(&token_str))
+	}
+}
+
+/// Keeps the tokens in the database, so they survive restarts and every
+/// instance of zauth sharing the database can redeem them.
+#[derive(Debug)]
+pub struct DatabaseTokenStore<T> {
+	kind: &'static str,
+	token_validity: Duration,
+	token_length: usize,
+	item: PhantomData<fn() -> T>,
+}
+
+impl<T> DatabaseTokenStore<T> {
+	pub fn new(
+		kind: &'static str,
+		token_validity: Duration,
+		config: &Config,
+	) -> DatabaseTokenStore<T> {
+		DatabaseTokenStore {
+			kind,
+			token_validity,
+			token_length: config.secure_token_length,
+			item: PhantomData,
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl<T> TokenStore<T> for DatabaseTokenStore<T>
+where
+	T: Serialize + DeserializeOwned + Send + 'static,
+{
+	fn token_validity(&self) -> Duration {
+		self.token_validity
+	}
+
+	async fn create_token(&self, item: T, db: &DbConn) -> Result<String> {
+		let item = serde_json::to_string(&item).map_err(InternalError::from)?;
+		let expires_at = Utc::now().naive_utc() + self.token_validity;
+		loop {
+			let token = StoredToken {
+				token: util::random_token(self.token_length),
+				kind: self.kind.to_string(),
+				item: item.clone(),
+				expires_at,
+			};
+			let token_str = token.token.clone();
+			if token.insert(db).await? {
+				return Ok(token_str);
+			}
+		}
+	}
+
+	async fn fetch_token(
+		&self,
+		token_str: String,
+		db: &DbConn,
+	) -> Result<Option<Token<T>>> {
+		let Some(token) = StoredToken::take(token_str, self.kind, db).await?
+		else {
+			return Ok(None);
+		};
+		Ok(Some(Token {
+			item: serde_json::from_str(&token.item)
+				.map_err(InternalError::from)?,
+			token_str: token.token,
+			expiry: Local.from_utc_datetime(&token.expires_at),
+		}))
 	}
 }
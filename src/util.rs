@@ -1,4 +1,6 @@
 use core::iter;
+use openssl::memcmp;
+use openssl::sha::sha256;
 use rand::{Rng, distr::Alphanumeric, rng};
 
 pub fn random_token(token_length: usize) -> String {
@@ -10,6 +12,23 @@ pub fn random_token(token_length: usize) -> String {
 		.collect()
 }
 
+/// Tokens are long and random, so a fast hash is enough to keep them safe
+/// when the database leaks.
+pub fn hash_token(token: &str) -> String {
+	sha256(token.as_bytes())
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
+
+/// Compare in constant time, so the response time doesn't tell how much of
+/// the token was right.
+pub fn token_matches(hashed_token: &str, token: &str) -> bool {
+	let hashed = hash_token(token);
+	hashed.len() == hashed_token.len()
+		&& memcmp::eq(hashed.as_bytes(), hashed_token.as_bytes())
+}
+
 pub fn split_scopes(scope: &Option<String>) -> Vec<String> {
 	scope
 		.as_ref()
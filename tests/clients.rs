@@ -4,13 +4,17 @@ extern crate rocket;
 use common::HttpClient;
 use rocket::http::Accept;
 use rocket::http::ContentType;
+use rocket::http::Header;
 use rocket::http::Status;
+use serde_json::{Value, json};
 use zauth::models::user::User;
 
 mod common;
 
 use crate::common::{config, url};
 use zauth::models::client::{Client, NewClient};
+use zauth::models::client_secret::ClientSecret;
+use zauth::models::initial_access_token::InitialAccessToken;
 use zauth::models::session::Session;
 
 #[rocket::async_test]
@@ -74,7 +78,7 @@ async fn create_and_update_client() {
 }
 
 #[rocket::async_test]
-async fn change_client_secret() {
+async fn rotate_client_secrets() {
 	common::as_admin(async move |http_client: HttpClient, db, _user: User| {
 		let client = Client::create(
 			NewClient {
@@ -85,19 +89,63 @@ async fn change_client_secret() {
 		.await
 		.expect("create client");
 
-		let secret_pre = client.secret.clone();
-		assert!(secret_pre.len() > 5);
+		let create_secret = async |body: Value| {
+			let response = http_client
+				.post(format!("/clients/{}/secrets", &client.id))
+				.header(ContentType::JSON)
+				.header(Accept::JSON)
+				.body(body.to_string())
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::Created);
+			response.into_json::<Value>().await.unwrap()
+		};
+		let authenticates = async |secret: &Value| {
+			Client::find_and_authenticate(
+				"test".into(),
+				secret.as_str().unwrap(),
+				&db,
+			)
+			.await
+			.is_ok()
+		};
+
+		let old = create_secret(json!({"label": "old"})).await;
+		assert!(old["expires_at"].is_null());
+		let new =
+			create_secret(json!({"label": "new", "expires_in_days": 30})).await;
+		assert!(new["expires_at"].is_string());
+		assert_ne!(old["secret"], new["secret"]);
+
+		// Both secrets work until the old one is deleted
+		assert!(authenticates(&old["secret"]).await);
+		assert!(authenticates(&new["secret"]).await);
 
+		// Only hashes are stored, and the secret isn't shown again
+		let secrets = ClientSecret::for_client(&client, &db).await.unwrap();
+		assert_eq!(secrets.len(), 2);
+		assert!(secrets.iter().all(|s| s.hashed_secret != old["secret"]));
 		let response = http_client
-			.post(format!("/clients/{}/generate_secret", &client.id))
+			.get(format!("/clients/{}/secrets", &client.id))
 			.header(Accept::JSON)
 			.dispatch()
 			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let listed: Value = response.into_json().await.unwrap();
+		assert_eq!(listed.as_array().unwrap().len(), 2);
+		assert!(listed[0]["secret"].is_null());
+		assert!(listed[0]["hashed_secret"].is_null());
+		assert_eq!(listed[0]["label"], "old");
 
+		let response = http_client
+			.delete(format!("/clients/{}/secrets/{}", &client.id, old["id"]))
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
 		assert_eq!(response.status(), Status::NoContent);
-
-		let client = client.reload(&db).await.expect("reload client");
-		assert_ne!(secret_pre, client.secret);
+		assert!(!authenticates(&old["secret"]).await);
+		assert!(authenticates(&new["secret"]).await);
+		assert!(!authenticates(&json!("wrong")).await);
 	})
 	.await;
 }
@@ -126,6 +174,7 @@ async fn delete_client_with_session() {
 			Some(&user),
 			&client,
 			None,
+			None,
 			&config(),
 			&db,
 		)
@@ -145,3 +194,212 @@ async fn delete_client_with_session() {
 	})
 	.await;
 }
+
+#[rocket::async_test]
+async fn redirect_uri_patterns_are_validated() {
+	common::as_admin(async move |http_client: HttpClient, db, _user| {
+		let client = Client::create(
+			NewClient {
+				name: String::from("test"),
+			},
+			&db,
+		)
+		.await
+		.unwrap();
+
+		let client_id = client.id;
+		let update = async |redirect_uri_list: &str| {
+			http_client
+				.put(format!("/clients/{}", client_id))
+				.body(format!("redirect_uri_list={}", url(redirect_uri_list)))
+				.header(ContentType::Form)
+				.header(Accept::JSON)
+				.dispatch()
+				.await
+				.status()
+		};
+
+		for pattern in [
+			"pattern:http://*.preview.example.com/callback",
+			"pattern:https://*.com/callback",
+			"pattern:https://preview.*.example.com/callback",
+			"pattern:https://*.example.com/*",
+			"pattern:https://user@*.example.com/callback",
+		] {
+			assert_ne!(update(pattern).await, Status::NoContent, "{}", pattern);
+		}
+		let client = client.reload(&db).await.unwrap();
+		assert_eq!(client.redirect_uri_list, "");
+
+		let redirect_uri_list = "https://example.com/callback\n\
+		                         pattern:https://pr-*.preview.example.com/cb";
+		assert_eq!(update(redirect_uri_list).await, Status::NoContent);
+		let client = client.reload(&db).await.unwrap();
+		assert_eq!(client.redirect_uri_list, redirect_uri_list);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn dynamic_client_registration() {
+	common::as_admin(async move |http_client: HttpClient, db, _user| {
+		let response = http_client
+			.post("/initial_access_tokens")
+			.body("description=test")
+			.header(ContentType::Form)
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Created);
+		let initial_access_token: Value = response.into_json().await.unwrap();
+		let initial_access_token =
+			initial_access_token["token"].as_str().unwrap().to_string();
+
+		let register = async |token: &str, metadata: Value| {
+			let response = http_client
+				.post("/oauth/register")
+				.header(ContentType::JSON)
+				.header(Header::new(
+					"Authorization",
+					format!("Bearer {}", token),
+				))
+				.body(metadata.to_string())
+				.dispatch()
+				.await;
+			let status = response.status();
+			(status, response.into_json::<Value>().await)
+		};
+		let metadata = json!({
+			"client_name": "registered",
+			"description": "Registered by itself",
+			"redirect_uris": ["https://example.com/callback"],
+			"scope": "openid profile",
+		});
+
+		let (status, _) = register("unknown", metadata.clone()).await;
+		assert_eq!(status, Status::Unauthorized);
+
+		let (status, body) =
+			register(&initial_access_token, metadata.clone()).await;
+		assert_eq!(status, Status::Created);
+		let body = body.unwrap();
+		assert_eq!(body["client_id"], "registered");
+		assert_eq!(
+			body["redirect_uris"],
+			json!(["https://example.com/callback"])
+		);
+		let registration_access_token = body["registration_access_token"]
+			.as_str()
+			.unwrap()
+			.to_string();
+		let registration_client_uri = body["registration_client_uri"]
+			.as_str()
+			.unwrap()
+			.trim_start_matches("http://localhost:8000")
+			.to_string();
+
+		let client = Client::find_and_authenticate(
+			"registered".into(),
+			body["client_secret"].as_str().unwrap(),
+			&db,
+		)
+		.await
+		.unwrap();
+		assert_eq!(client.description, "Registered by itself");
+		assert_eq!(client.allowed_scopes, "openid profile");
+		assert!(client.needs_grant);
+		// Only hashes of the tokens are stored
+		assert_ne!(
+			client.hashed_registration_access_token.as_deref(),
+			Some(registration_access_token.as_str())
+		);
+		let tokens = InitialAccessToken::all(&db).await.unwrap();
+		assert_eq!(tokens.len(), 1);
+		assert_ne!(tokens[0].hashed_token, initial_access_token);
+
+		let (status, body) =
+			register(&initial_access_token, metadata.clone()).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body.unwrap()["error"], "invalid_client_metadata");
+
+		let (status, body) = register(
+			&initial_access_token,
+			json!({"client_name": "other", "redirect_uris": ["not a uri"]}),
+		)
+		.await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body.unwrap()["error"], "invalid_redirect_uri");
+
+		let (status, body) = register(
+			&initial_access_token,
+			json!({
+				"client_name": "other",
+				"redirect_uris": ["pattern:https://*.example.com/callback"],
+			}),
+		)
+		.await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body.unwrap()["error"], "invalid_redirect_uri");
+
+		let (status, body) = register(
+			&initial_access_token,
+			json!({
+				"client_name": "other",
+				"redirect_uris": ["https://example.com/callback"],
+				"backchannel_logout_uri": "http://example.com/logout",
+			}),
+		)
+		.await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body.unwrap()["error"], "invalid_client_metadata");
+
+		// The client manages its registration with its own token
+		let bearer = |token: &str| {
+			Header::new("Authorization", format!("Bearer {}", token))
+		};
+		let response = http_client
+			.get(registration_client_uri.clone())
+			.header(bearer(&initial_access_token))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+
+		let response = http_client
+			.get(registration_client_uri.clone())
+			.header(bearer(&registration_access_token))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let body: Value = response.into_json().await.unwrap();
+		assert_eq!(body["client_name"], "registered");
+		// Only hashes of secrets are stored, so it can't be shown again
+		assert!(body["client_secret"].is_null());
+		assert!(body["registration_access_token"].is_null());
+
+		let response = http_client
+			.put(registration_client_uri.clone())
+			.header(ContentType::JSON)
+			.header(bearer(&registration_access_token))
+			.body(
+				json!({
+					"client_id": "registered",
+					"redirect_uris": ["https://example.com/other"],
+				})
+				.to_string(),
+			)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let client = client.reload(&db).await.unwrap();
+		assert_eq!(client.redirect_uri_list, "https://example.com/other");
+
+		let response = http_client
+			.delete(registration_client_uri.clone())
+			.header(bearer(&registration_access_token))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::NoContent);
+		assert!(client.reload(&db).await.is_err());
+	})
+	.await;
+}
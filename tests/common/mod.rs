@@ -13,13 +13,17 @@ use parking_lot::Mutex;
 use std::str::FromStr;
 
 use crate::common::zauth::DbConn;
-use crate::common::zauth::config::Config;
+use crate::common::zauth::config::{Config, TokenStoreBackend};
 use crate::common::zauth::models::client::*;
 use crate::common::zauth::models::user::*;
 use lettre::Address;
 use rocket::http::{ContentType, Status};
+use rocket::tokio::sync::mpsc::{self, UnboundedReceiver};
 use rocket::tokio::time::sleep;
 use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
 use std::time::Duration;
 use zauth::mailer::STUB_MAILER_OUTBOX;
 
@@ -41,11 +45,20 @@ pub fn config() -> Config {
 		user_session_seconds: 300,
 		client_session_seconds: 300,
 		authorization_token_seconds: 300,
+		pushed_request_seconds: 60,
+		refresh_token_seconds: 300,
+		device_code_seconds: 300,
+		device_poll_interval_seconds: 5,
 		email_confirmation_token_seconds: 300,
 		secure_token_length: 64,
+		token_store: TokenStoreBackend::Memory,
 		bcrypt_cost: BCRYPT_COST,
 		ec_private_key: "keys/jwt_key.pem".to_string(),
+		ec_retired_keys: vec![],
+		client_secret_key: None,
 		base_url: "example.com".to_string(),
+		backchannel_logout_retries: 2,
+		backchannel_logout_retry_seconds: 0,
 		mail_queue_size: 10,
 		mail_queue_wait_seconds: 0,
 		mail_from: "zauth@example.com".to_string(),
@@ -54,6 +67,7 @@ pub fn config() -> Config {
 		mailing_list_email: "leden@zeus.ugent.be".to_string(),
 		maximum_pending_users: 5,
 		mailer_role: "mailer".to_string(),
+		client_registration_role: "developer".to_string(),
 		picture_url_prefix: "https://zpi.zeus.gent/image/".to_string(),
 		mail_use_tls: false,
 		mail_username: None,
@@ -63,11 +77,66 @@ pub fn config() -> Config {
 	}
 }
 
+/// Start an HTTP server on localhost that answers the requests it receives
+/// with the given statuses, in order, and passes on their bodies. Returns the
+/// URL it listens on.
+pub fn stub_receiver(
+	statuses: Vec<u16>,
+) -> (String, UnboundedReceiver<String>) {
+	let listener = TcpListener::bind("127.0.0.1:0").expect("stub receiver");
+	let address = listener.local_addr().unwrap();
+	let (sender, receiver) = mpsc::unbounded_channel();
+	thread::spawn(move || {
+		for status in statuses {
+			let (mut stream, _) = listener.accept().expect("connection");
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut content_length = 0;
+			loop {
+				let mut line = String::new();
+				reader.read_line(&mut line).unwrap();
+				if line.trim().is_empty() {
+					break;
+				}
+				if let Some((name, value)) = line.split_once(':')
+					&& name.eq_ignore_ascii_case("content-length")
+				{
+					content_length = value.trim().parse().unwrap();
+				}
+			}
+			let mut body = vec![0; content_length];
+			reader.read_exact(&mut body).unwrap();
+			write!(
+				stream,
+				"HTTP/1.1 {} Stub\r\nContent-Length: 0\r\n\
+				 Connection: close\r\n\r\n",
+				status
+			)
+			.unwrap();
+			let _ = sender.send(String::from_utf8(body).unwrap());
+		}
+	});
+	(format!("http://{}/logout", address), receiver)
+}
+
+/// The migration that creates the scopes table and registers the default
+/// scopes.
+const SCOPES_MIGRATION: &str =
+	include_str!("../../migrations/2026-10-17-150000_create_scopes/up.sql");
+
 async fn reset_db(db: &DbConn) {
 	db.run(|conn| {
-		sql_query("TRUNCATE TABLE mails, sessions, users, clients, passkeys, users_roles, clients_roles, roles")
+		sql_query("TRUNCATE TABLE mails, client_secrets, consents, device_authorizations, initial_access_tokens, refresh_tokens, sessions, stored_tokens, used_assertions, users, clients, passkeys, users_roles, clients_roles, roles, scopes")
 			.execute(conn)
 			.expect("drop all tables");
+		// Start again from the scopes the migration registers
+		let seed = SCOPES_MIGRATION
+			.split(';')
+			.map(str::trim)
+			.find(|statement| statement.starts_with("INSERT INTO scopes"))
+			.expect("scopes seed");
+		sql_query(seed)
+			.execute(conn)
+			.expect("seed scopes");
 	})
 	.await
 }
@@ -87,6 +156,7 @@ where
 	let config = rocket::Config::figment()
 		.merge(("mail_queue_wait_seconds", 0))
 		.merge(("maximum_pending_users", 5))
+		.merge(("device_poll_interval_seconds", 0))
 		.merge(("databases.postgresql_database.url", db_url));
 
 	let _lock = DB_LOCK.lock();
@@ -6,6 +6,7 @@ use rocket::http::Header;
 use rocket::http::{Accept, ContentType, Status};
 use serde_json::Value;
 use zauth::models::client::{Client, NewClient};
+use zauth::models::client_secret::{ClientSecret, NewClientSecret};
 use zauth::models::role::NewRole;
 use zauth::models::role::Role;
 
@@ -295,10 +296,21 @@ async fn authorized_client_can_use_mailinglist() {
 		)
 		.await
 		.expect("client created");
+		let (_, secret) = ClientSecret::create(
+			&client,
+			NewClientSecret {
+				label: "test".into(),
+				expires_in_days: None,
+			},
+			&common::config(),
+			&db,
+		)
+		.await
+		.expect("client secret created");
 		let token_url = "/oauth/token";
 		let form_body = format!(
 			"grant_type=client_credentials&&client_id={}&client_secret={}",
-			client.name, client.secret
+			client.name, secret
 		);
 
 		let req = http_client
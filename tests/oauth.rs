@@ -8,22 +8,38 @@ extern crate zauth;
 use self::serde_json::{Number, Value};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
+use chrono::Utc;
 use common::HttpClient;
 use jsonwebtoken::DecodingKey;
 use jsonwebtoken::Validation;
 use jsonwebtoken::jwk::JwkSet;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
 use regex::Regex;
 use rocket::http::Header;
 use rocket::http::Status;
 use rocket::http::{Accept, ContentType};
+use rocket::local::asynchronous::LocalResponse;
+use std::io::Write;
 
 use zauth::DbConn;
+use zauth::config::{Config, RetiredKey, TokenStoreBackend};
 use zauth::controllers::oauth_controller::UserToken;
+use zauth::device_store::{
+	DeviceAuthorizationState, DevicePoll, DeviceStore, format_user_code,
+};
+use zauth::jwt::JWTBuilder;
 use zauth::models::client::{Client, NewClient};
+use zauth::models::client_secret::{ClientSecret, NewClientSecret};
+use zauth::models::refresh_token::RefreshToken;
 use zauth::models::role::NewRole;
 use zauth::models::role::Role;
+use zauth::models::scope::{NewScope, Scope};
+use zauth::models::session::Session;
 use zauth::models::user::{NewUser, User};
-use zauth::token_store::TokenStore;
+use zauth::pkce::{CodeChallenge, CodeChallengeMethod};
+use zauth::token_store::{DatabaseTokenStore, TokenStore};
 
 mod common;
 use crate::common::url;
@@ -34,6 +50,9 @@ const CLIENT_STATE: &str = "anarchy (╯°□°)╯ ┻━┻";
 const USER_USERNAME: &str = "batman";
 const USER_PASSWORD: &str = "wolololo";
 const USER_EMAIL: &str = "test@test.com";
+// Example values from RFC 7636, appendix B
+const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+const CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
 
 fn get_param(param_name: &str, query: &String) -> Option<String> {
 	Regex::new(&format!("{}=([^&]+)", param_name))
@@ -59,14 +78,27 @@ async fn create_user(db: &DbConn) -> User {
 	.expect("user")
 }
 
-async fn create_client(db: &DbConn, name: &str) -> Client {
+// Returns the client together with its secret
+async fn create_client(db: &DbConn, name: &str) -> (Client, String) {
 	let mut client = Client::create(NewClient { name: name.into() }, db)
 		.await
 		.expect("client created");
 
 	client.needs_grant = true;
 	client.redirect_uri_list = String::from(REDIRECT_URI);
-	client.update(db).await.expect("client updated")
+	let client = client.update(db).await.expect("client updated");
+	let (_, secret) = ClientSecret::create(
+		&client,
+		NewClientSecret {
+			label: String::from("test"),
+			expires_in_days: None,
+		},
+		&common::config(),
+		db,
+	)
+	.await
+	.expect("client secret created");
+	(client, secret)
 }
 
 async fn create_role(db: &DbConn, name: &str, client_id: Option<i32>) -> Role {
@@ -82,11 +114,72 @@ async fn create_role(db: &DbConn, name: &str, client_id: Option<i32>) -> Role {
 	.expect("role created")
 }
 
+// An authorization the user granted to the client, as it is kept behind an
+// authorization code.
+fn user_token(user: &User, client: &Client, redirect_uri: &str) -> UserToken {
+	UserToken {
+		scope: None,
+		user_id: user.id,
+		username: user.username.clone(),
+		client_id: client.id,
+		client_name: client.name.clone(),
+		redirect_uri: String::from(redirect_uri),
+		code_challenge: None,
+		nonce: None,
+		auth_time: Utc::now().naive_utc(),
+		sid: None,
+	}
+}
+
+// Skip the authorization steps and exchange a fresh authorization code for
+// tokens with the given scope.
+async fn exchange_code(
+	http_client: &HttpClient,
+	client: &Client,
+	secret: &str,
+	user: &User,
+	scope: &str,
+) -> Value {
+	let db = DbConn::get_one(http_client.rocket())
+		.await
+		.expect("database connection");
+	let token_store = http_client
+		.rocket()
+		.state::<Box<dyn TokenStore<UserToken>>>()
+		.expect("should have token store");
+
+	let authorization_code = token_store
+		.create_token(
+			UserToken {
+				scope: Some(String::from(scope)),
+				..user_token(user, client, REDIRECT_URI)
+			},
+			&db,
+		)
+		.await
+		.expect("authorization code");
+
+	let response = http_client
+		.post("/oauth/token")
+		.header(ContentType::Form)
+		.body(format!(
+			"grant_type=authorization_code&code={}&redirect_uri={}&\
+			 client_id={}&client_secret={}",
+			authorization_code, REDIRECT_URI, client.name, secret
+		))
+		.dispatch()
+		.await;
+	assert_eq!(response.status(), Status::Ok);
+
+	let response_body = response.into_string().await.expect("response body");
+	serde_json::from_str(&response_body).expect("response json values")
+}
+
 // Test all the usual oauth requests until `access_token/id_token` is retrieved.
 async fn get_token(
 	authorize_url: String,
 	http_client: &HttpClient,
-	client: &Client,
+	secret: &str,
 	user: &User,
 ) -> Value {
 	let response = http_client.get(authorize_url).dispatch().await;
@@ -192,7 +285,7 @@ async fn get_token(
 	);
 
 	let credentials =
-		BASE64_STANDARD.encode(format!("{}:{}", CLIENT_ID, client.secret));
+		BASE64_STANDARD.encode(format!("{}:{}", CLIENT_ID, secret));
 
 	let req = http_client
 		.post(token_url)
@@ -219,7 +312,7 @@ async fn get_token(
 async fn normal_flow() {
 	common::as_visitor(async move |http_client, db| {
 		let user = create_user(&db).await;
-		let client = create_client(&db, CLIENT_ID).await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
 
 		// 1. User is redirected to OAuth server with request params given by
 		// the client
@@ -233,7 +326,7 @@ async fn normal_flow() {
 		);
 
 		// Do all the requests until access_token is retrieved.
-		let data = get_token(authorize_url, &http_client, &client, &user).await;
+		let data = get_token(authorize_url, &http_client, &secret, &user).await;
 
 		dbg!(&data);
 		assert!(data["access_token"].is_string());
@@ -247,25 +340,19 @@ async fn normal_flow() {
 		// First, re-create a token
 		let token_store = http_client
 			.rocket()
-			.state::<TokenStore<UserToken>>()
+			.state::<Box<dyn TokenStore<UserToken>>>()
 			.expect("should have token store");
 
 		let authorization_code = token_store
-			.create_token(UserToken {
-				scope: None,
-				user_id: user.id,
-				username: user.username.clone(),
-				client_id: client.id,
-				client_name: client.name,
-				redirect_uri: String::from(REDIRECT_URI),
-			})
-			.await;
+			.create_token(user_token(&user, &client, REDIRECT_URI), &db)
+			.await
+			.expect("authorization code");
 
 		let token_url = "/oauth/token";
 		let form_body = format!(
 			"grant_type=authorization_code&code={}&redirect_uri={}&\
 			 client_id={}&client_secret={}",
-			authorization_code, REDIRECT_URI, CLIENT_ID, client.secret
+			authorization_code, REDIRECT_URI, CLIENT_ID, secret
 		);
 
 		let req = http_client
@@ -319,17 +406,17 @@ async fn normal_flow() {
 async fn openid_flow() {
 	common::as_visitor(async move |http_client, db| {
 		let user = create_user(&db).await;
-		let client = create_client(&db, CLIENT_ID).await;
+		let (_, secret) = create_client(&db, CLIENT_ID).await;
 
 		let authorize_url = format!(
 			"/oauth/authorize?response_type=code&redirect_uri={}&client_id={}&\
-			 state={}&scope=openid",
+			 state={}&scope=openid&nonce=n-0S6_WzA2Mj",
 			url(REDIRECT_URI),
 			url(CLIENT_ID),
 			url(CLIENT_STATE)
 		);
 
-		let data = get_token(authorize_url, &http_client, &client, &user).await;
+		let data = get_token(authorize_url, &http_client, &secret, &user).await;
 
 		assert!(data["access_token"].is_string());
 		assert!(data["token_type"].is_string());
@@ -359,6 +446,12 @@ async fn openid_flow() {
 		assert_eq!(id_token["preferred_username"], USER_USERNAME);
 		assert_eq!(id_token["email"], USER_EMAIL);
 		assert_eq!(id_token["roles"], Value::Null);
+		assert_eq!(id_token["nonce"], "n-0S6_WzA2Mj");
+		assert_eq!(id_token["azp"], CLIENT_ID);
+		assert!(
+			id_token["auth_time"].as_i64().expect("auth time")
+				<= id_token["iat"].as_i64().expect("issued at")
+		);
 	})
 	.await;
 }
@@ -367,8 +460,8 @@ async fn openid_flow() {
 async fn roles_flow() {
 	common::as_visitor(async move |http_client, db| {
 		let user = create_user(&db).await;
-		let client = create_client(&db, CLIENT_ID).await;
-		let client_not_used = create_client(&db, "not_used").await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
+		let (client_not_used, _) = create_client(&db, "not_used").await;
 		let role_global = create_role(&db, "global", None).await;
 		let role_client = create_role(&db, "client", Some(client.id)).await;
 		let role_client_not_used =
@@ -400,7 +493,7 @@ async fn roles_flow() {
 			url("openid roles")
 		);
 
-		let data = get_token(authorize_url, &http_client, &client, &user).await;
+		let data = get_token(authorize_url, &http_client, &secret, &user).await;
 
 		let url = "/oauth/jwks";
 		let req = http_client.get(url);
@@ -461,7 +554,7 @@ async fn roles_flow() {
 #[rocket::async_test]
 async fn client_credentials_flow() {
 	common::as_visitor(async move |http_client, db| {
-		let client = create_client(&db, CLIENT_ID).await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
 		let role_global = create_role(&db, "global", None).await;
 		role_global
 			.add_client(client.id, &db)
@@ -471,7 +564,7 @@ async fn client_credentials_flow() {
 		let token_url = "/oauth/token";
 		let form_body = format!(
 			"grant_type=client_credentials&&client_id={}&client_secret={}",
-			CLIENT_ID, client.secret
+			CLIENT_ID, secret
 		);
 
 		let req = http_client
@@ -521,3 +614,2484 @@ async fn client_credentials_flow() {
 	})
 	.await;
 }
+
+#[rocket::async_test]
+async fn pkce_flow() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
+
+		let token_store = http_client
+			.rocket()
+			.state::<Box<dyn TokenStore<UserToken>>>()
+			.expect("should have token store");
+
+		let create_code = async || {
+			token_store
+				.create_token(
+					UserToken {
+						code_challenge: Some(CodeChallenge {
+							challenge: String::from(CODE_CHALLENGE),
+							method: CodeChallengeMethod::S256,
+						}),
+						..user_token(&user, &client, REDIRECT_URI)
+					},
+					&db,
+				)
+				.await
+				.expect("authorization code")
+		};
+
+		let request_token = async |code: String, verifier: Option<&str>| {
+			let mut form_body = format!(
+				"grant_type=authorization_code&code={}&redirect_uri={}&\
+				 client_id={}&client_secret={}",
+				code, REDIRECT_URI, CLIENT_ID, secret
+			);
+			if let Some(verifier) = verifier {
+				form_body += &format!("&code_verifier={}", verifier);
+			}
+			http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(form_body)
+				.dispatch()
+				.await
+		};
+
+		// Missing code verifier
+		let response = request_token(create_code().await, None).await;
+		assert_eq!(response.status(), Status::BadRequest);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["error"], "invalid_grant");
+
+		// Wrong code verifier
+		let wrong_verifier = CODE_VERIFIER.replace('d', "e");
+		let response =
+			request_token(create_code().await, Some(&wrong_verifier)).await;
+		assert_eq!(response.status(), Status::BadRequest);
+
+		// Correct code verifier
+		let response =
+			request_token(create_code().await, Some(CODE_VERIFIER)).await;
+		assert_eq!(response.status(), Status::Ok);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert!(data["access_token"].is_string());
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn pkce_required() {
+	common::as_visitor(async move |http_client, db| {
+		let (mut client, _) = create_client(&db, CLIENT_ID).await;
+		client.pkce_required = true;
+		client.update(&db).await.expect("client updated");
+
+		let authorize_url = format!(
+			"/oauth/authorize?response_type=code&redirect_uri={}&client_id={}",
+			url(REDIRECT_URI),
+			url(CLIENT_ID),
+		);
+
+		let authorize_error = async |url: String| {
+			let response = http_client.get(url).dispatch().await;
+			assert_eq!(response.status(), Status::SeeOther);
+			let location = response
+				.headers()
+				.get_one("Location")
+				.expect("Location header")
+				.to_string();
+			assert!(location.starts_with(REDIRECT_URI));
+			get_param("error", &location)
+		};
+
+		assert_eq!(
+			authorize_error(authorize_url.clone()).await.as_deref(),
+			Some("invalid_request")
+		);
+		assert_eq!(
+			authorize_error(format!(
+				"{}&code_challenge={}&code_challenge_method=S512",
+				authorize_url, CODE_CHALLENGE
+			))
+			.await
+			.as_deref(),
+			Some("invalid_request")
+		);
+
+		let response = http_client
+			.get(format!(
+				"{}&code_challenge={}&code_challenge_method=S256",
+				authorize_url, CODE_CHALLENGE
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn public_clients() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.public = true;
+		let client = client.update(&db).await.expect("client updated");
+
+		// Public clients always have to use PKCE
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response.headers().get_one("Location").unwrap();
+		assert_eq!(
+			get_param("error", &location.to_string()).as_deref(),
+			Some("invalid_request")
+		);
+
+		let token_store = http_client
+			.rocket()
+			.state::<Box<dyn TokenStore<UserToken>>>()
+			.expect("should have token store");
+		let code = token_store
+			.create_token(
+				UserToken {
+					code_challenge: Some(CodeChallenge {
+						challenge: String::from(CODE_CHALLENGE),
+						method: CodeChallengeMethod::S256,
+					}),
+					..user_token(&user, &client, REDIRECT_URI)
+				},
+				&db,
+			)
+			.await
+			.expect("authorization code");
+
+		let token_request = async |body: String| {
+			let response = http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(body)
+				.dispatch()
+				.await;
+			let status = response.status();
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.unwrap_or_default();
+			(status, data)
+		};
+
+		// The secret no longer works, only the client id is needed
+		let (status, _) = token_request(format!(
+			"grant_type=authorization_code&code={}&redirect_uri={}&\
+			 client_id={}&client_secret={}&code_verifier={}",
+			code, REDIRECT_URI, CLIENT_ID, secret, CODE_VERIFIER
+		))
+		.await;
+		assert_eq!(status, Status::Unauthorized);
+
+		let (status, data) = token_request(format!(
+			"grant_type=authorization_code&code={}&redirect_uri={}&\
+			 client_id={}&code_verifier={}",
+			code, REDIRECT_URI, CLIENT_ID, CODE_VERIFIER
+		))
+		.await;
+		assert_eq!(status, Status::Ok);
+		assert!(data["access_token"].is_string());
+
+		let (status, data) = token_request(format!(
+			"grant_type=client_credentials&client_id={}",
+			CLIENT_ID
+		))
+		.await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(data["error"], "unauthorized_client");
+
+		// Confidential clients still need their secret
+		let (other, _) = create_client(&db, "confidential").await;
+		let (status, _) = token_request(format!(
+			"grant_type=client_credentials&client_id={}",
+			other.name
+		))
+		.await;
+		assert_eq!(status, Status::Unauthorized);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn pushed_authorization_requests() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.par_required = true;
+		client.update(&db).await.expect("client updated");
+
+		let push = async |params: String| {
+			let response = http_client
+				.post("/oauth/par")
+				.header(ContentType::Form)
+				.body(params)
+				.dispatch()
+				.await;
+			let status = response.status();
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.unwrap_or_default();
+			(status, data)
+		};
+		let params = |secret: &str, redirect_uri: &str| {
+			format!(
+				"client_id={}&client_secret={}&response_type=code&\
+				 redirect_uri={}&state={}",
+				CLIENT_ID,
+				secret,
+				url(redirect_uri),
+				url(CLIENT_STATE)
+			)
+		};
+
+		let (status, _) = push(params("wrong", REDIRECT_URI)).await;
+		assert_eq!(status, Status::Unauthorized);
+		let (status, data) =
+			push(params(&secret, "https://example.com/other")).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(data["error"], "invalid_request");
+
+		// This client has to push its requests
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response.headers().get_one("Location").unwrap();
+		assert_eq!(
+			get_param("error", &location.to_string()).as_deref(),
+			Some("invalid_request")
+		);
+
+		let (status, data) = push(params(&secret, REDIRECT_URI)).await;
+		assert_eq!(status, Status::Created);
+		assert_eq!(data["expires_in"], 60);
+		let request_uri = data["request_uri"].as_str().unwrap().to_string();
+		assert!(request_uri.starts_with("urn:ietf:params:oauth:request_uri:"));
+
+		// The pushed parameters are used, including the state
+		let authorize_url = format!(
+			"/oauth/authorize?client_id={}&request_uri={}",
+			url(CLIENT_ID),
+			url(&request_uri)
+		);
+		let data =
+			get_token(authorize_url.clone(), &http_client, &secret, &user)
+				.await;
+		assert!(data["access_token"].is_string());
+
+		// A pushed request can only be used once
+		let response = http_client.get(authorize_url).dispatch().await;
+		assert_eq!(response.status(), Status::BadRequest);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.unwrap();
+		assert_eq!(data["error"], "invalid_request_uri");
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn signed_request_objects() {
+	common::as_visitor(async move |http_client, db| {
+		// Sign the request objects of the client with a key of its own
+		let key_file = generate_key_file();
+		let mut config = common::config();
+		config.ec_private_key = key_file.path().to_str().unwrap().to_string();
+		let client_keys = JWTBuilder::new(&config).expect("jwt builder");
+
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.jwks = serde_json::to_string(&client_keys.jwks()).unwrap();
+		client.signed_request_required = true;
+		client.update(&db).await.expect("client updated");
+
+		let claims = serde_json::json!({
+			"iss": CLIENT_ID,
+			"aud": "http://localhost:8000",
+			"exp": Utc::now().timestamp() + 60,
+			"response_type": "code",
+			"redirect_uri": REDIRECT_URI,
+			"state": CLIENT_STATE,
+		});
+
+		// This client has to sign its requests
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response.headers().get_one("Location").unwrap();
+		assert_eq!(
+			get_param("error", &location.to_string()).as_deref(),
+			Some("invalid_request")
+		);
+
+		// Signed with a key that is not registered for the client
+		let request = JWTBuilder::new(&common::config())
+			.unwrap()
+			.encode(&claims)
+			.unwrap();
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?client_id={}&request={}",
+				url(CLIENT_ID),
+				url(&request)
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::BadRequest);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.unwrap();
+		assert_eq!(data["error"], "invalid_request_object");
+
+		// Request objects have to expire
+		let mut unexpiring = claims.clone();
+		unexpiring.as_object_mut().unwrap().remove("exp");
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?client_id={}&request={}",
+				url(CLIENT_ID),
+				url(&client_keys.encode(&unexpiring).unwrap())
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::BadRequest);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.unwrap();
+		assert_eq!(data["error"], "invalid_request_object");
+
+		// Unsigned parameters next to the request object are ignored
+		let mut without_redirect = claims.clone();
+		without_redirect
+			.as_object_mut()
+			.unwrap()
+			.remove("redirect_uri");
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?client_id={}&redirect_uri={}&request={}",
+				url(CLIENT_ID),
+				url(REDIRECT_URI),
+				url(&client_keys.encode(&without_redirect).unwrap())
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+
+		// Request objects are only fetched from registered URIs
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?client_id={}&request_uri={}",
+				url(CLIENT_ID),
+				url("https://example.com/request.jwt")
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::BadRequest);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.unwrap();
+		assert_eq!(data["error"], "invalid_request_uri");
+
+		// All parameters are taken from the request object
+		let request = client_keys.encode(&claims).unwrap();
+		let authorize_url = format!(
+			"/oauth/authorize?client_id={}&request={}",
+			url(CLIENT_ID),
+			url(&request)
+		);
+		let data = get_token(authorize_url, &http_client, &secret, &user).await;
+		assert!(data["access_token"].is_string());
+
+		// A request object can also be pushed
+		let response = http_client
+			.post("/oauth/par")
+			.header(ContentType::Form)
+			.body(format!(
+				"client_id={}&client_secret={}&request={}",
+				CLIENT_ID,
+				secret,
+				url(&request)
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Created);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.unwrap();
+		let authorize_url = format!(
+			"/oauth/authorize?client_id={}&request_uri={}",
+			url(CLIENT_ID),
+			url(data["request_uri"].as_str().unwrap())
+		);
+		let response = http_client.get(authorize_url).dispatch().await;
+		assert_eq!(response.status(), Status::Ok);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn database_token_store() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (client, _) = create_client(&db, CLIENT_ID).await;
+		let config = common::config();
+		let store = |kind: &'static str, seconds: i64| {
+			DatabaseTokenStore::<UserToken>::new(
+				kind,
+				chrono::Duration::seconds(seconds),
+				&config,
+			)
+		};
+
+		// Another instance sharing the database can redeem the code, but
+		// only once
+		let code = store("authorization_code", 60)
+			.create_token(user_token(&user, &client, REDIRECT_URI), &db)
+			.await
+			.expect("authorization code");
+		let other_instance = store("authorization_code", 60);
+		let token = other_instance
+			.fetch_token(code.clone(), &db)
+			.await
+			.unwrap()
+			.expect("stored token");
+		assert_eq!(token.item.user_id, user.id);
+		assert_eq!(token.item.redirect_uri, REDIRECT_URI);
+		assert!(
+			other_instance
+				.fetch_token(code, &db)
+				.await
+				.unwrap()
+				.is_none()
+		);
+
+		// Tokens of another kind of store can't be redeemed
+		let code = store("authorization_code", 60)
+			.create_token(user_token(&user, &client, REDIRECT_URI), &db)
+			.await
+			.expect("authorization code");
+		let pushed_requests = store("pushed_request", 60);
+		assert!(
+			pushed_requests
+				.fetch_token(code.clone(), &db)
+				.await
+				.unwrap()
+				.is_none()
+		);
+
+		// When two instances redeem the same code at once, only one of them
+		// gets it
+		let other_db = DbConn::get_one(http_client.rocket())
+			.await
+			.expect("database connection");
+		let (first, second) = rocket::tokio::join!(
+			other_instance.fetch_token(code.clone(), &db),
+			other_instance.fetch_token(code, &other_db),
+		);
+		assert!(first.unwrap().is_some() ^ second.unwrap().is_some());
+
+		let code = store("authorization_code", -1)
+			.create_token(user_token(&user, &client, REDIRECT_URI), &db)
+			.await
+			.expect("authorization code");
+		assert!(
+			other_instance
+				.fetch_token(code, &db)
+				.await
+				.unwrap()
+				.is_none()
+		);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn database_device_store() {
+	common::as_visitor(async move |_http_client, db| {
+		let user = create_user(&db).await;
+		let (client, _) = create_client(&db, CLIENT_ID).await;
+		let config = Config {
+			token_store: TokenStoreBackend::Database,
+			device_poll_interval_seconds: 0,
+			..common::config()
+		};
+		let store = DeviceStore::new(&config);
+		let other_instance = DeviceStore::new(&config);
+
+		// Another instance sharing the database sees the authorization
+		let (device_code, user_code) = store
+			.create(&client, Some("openid".into()), &db)
+			.await
+			.expect("device authorization");
+		let pending = other_instance
+			.find_pending(&format_user_code(&user_code).to_lowercase(), &db)
+			.await
+			.unwrap()
+			.expect("pending authorization");
+		assert_eq!(pending.client_id, client.id);
+		assert!(matches!(
+			store.poll(&device_code, client.id, &db).await.unwrap(),
+			DevicePoll::Pending
+		));
+		assert!(matches!(
+			store.poll(&device_code, client.id + 1, &db).await.unwrap(),
+			DevicePoll::Invalid
+		));
+
+		let granted = DeviceAuthorizationState::Granted {
+			user_id: user.id,
+			auth_time: Utc::now().naive_utc(),
+			sid: None,
+		};
+		assert!(
+			other_instance
+				.resolve(&user_code, granted.clone(), &db)
+				.await
+				.unwrap()
+		);
+		assert!(!store.resolve(&user_code, granted, &db).await.unwrap());
+		assert!(store.find_pending(&user_code, &db).await.unwrap().is_none());
+
+		// The device code can only be exchanged once
+		match store.poll(&device_code, client.id, &db).await.unwrap() {
+			DevicePoll::Granted { user_id, scope, .. } => {
+				assert_eq!(user_id, user.id);
+				assert_eq!(scope.as_deref(), Some("openid"));
+			},
+			poll => panic!("expected a granted poll, got {:?}", poll),
+		}
+		assert!(matches!(
+			other_instance
+				.poll(&device_code, client.id, &db)
+				.await
+				.unwrap(),
+			DevicePoll::Invalid
+		));
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn refresh_token_flow() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
+
+		let token_store = http_client
+			.rocket()
+			.state::<Box<dyn TokenStore<UserToken>>>()
+			.expect("should have token store");
+
+		let authorization_code = token_store
+			.create_token(
+				UserToken {
+					scope: Some(String::from("offline_access")),
+					..user_token(&user, &client, REDIRECT_URI)
+				},
+				&db,
+			)
+			.await
+			.expect("authorization code");
+
+		let request_token = async |form_body: String| {
+			let response = http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(form_body)
+				.dispatch()
+				.await;
+			let status = response.status();
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+			(status, data)
+		};
+
+		let refresh = async |refresh_token: &Value| {
+			request_token(format!(
+				"grant_type=refresh_token&refresh_token={}&client_id={}&\
+				 client_secret={}",
+				url(refresh_token.as_str().expect("refresh token")),
+				CLIENT_ID,
+				secret
+			))
+			.await
+		};
+
+		let (status, first) = request_token(format!(
+			"grant_type=authorization_code&code={}&redirect_uri={}&\
+			 client_id={}&client_secret={}",
+			authorization_code, REDIRECT_URI, CLIENT_ID, secret
+		))
+		.await;
+		assert_eq!(status, Status::Ok);
+		assert!(first["refresh_token"].is_string());
+
+		// A rejected request does not use up the refresh token
+		let (status, data) = request_token(format!(
+			"grant_type=refresh_token&refresh_token={}&client_id={}&\
+			 client_secret={}&scope=unknown",
+			url(first["refresh_token"].as_str().expect("refresh token")),
+			CLIENT_ID,
+			secret
+		))
+		.await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(data["error"], "invalid_scope");
+
+		// Refreshing rotates both the access and the refresh token
+		let (status, second) = refresh(&first["refresh_token"]).await;
+		assert_eq!(status, Status::Ok);
+		assert!(second["access_token"].is_string());
+		assert_ne!(first["access_token"], second["access_token"]);
+		assert_ne!(first["refresh_token"], second["refresh_token"]);
+
+		// Only a hash of the refresh token is stored
+		let key = second["refresh_token"].as_str().expect("refresh token");
+		let (stored, _) = RefreshToken::find_by_key(key.to_string(), &db)
+			.await
+			.expect("stored refresh token");
+		assert_ne!(stored.hashed_key, key);
+
+		// Reusing a refresh token revokes the whole family
+		let (status, data) = refresh(&first["refresh_token"]).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(data["error"], "invalid_grant");
+
+		let (status, _) = refresh(&second["refresh_token"]).await;
+		assert_eq!(status, Status::BadRequest);
+
+		let access_token =
+			second["access_token"].as_str().expect("access token");
+		let response = http_client
+			.get("/current_user")
+			.header(Accept::JSON)
+			.header(Header::new(
+				"Authorization",
+				format!("Bearer {}", access_token),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+
+		// Refresh tokens can't be used once their session has ended
+		let data = exchange_code(
+			&http_client,
+			&client,
+			&secret,
+			&user,
+			"offline_access",
+		)
+		.await;
+		let key = data["refresh_token"].as_str().expect("refresh token");
+		let (_, session) = RefreshToken::find_by_key(key.to_string(), &db)
+			.await
+			.expect("stored refresh token");
+		session.invalidate(&db).await.expect("invalidated session");
+		let (status, data) = refresh(&data["refresh_token"]).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(data["error"], "invalid_grant");
+
+		// Scopes removed from the registry are dropped when refreshing
+		let calendar = Scope::create(
+			NewScope {
+				name: "calendar".into(),
+				description: "See your calendar".into(),
+			},
+			&db,
+		)
+		.await
+		.expect("scope");
+		let mut client = client;
+		client.allowed_scopes = String::from("offline_access calendar");
+		let client = client.update(&db).await.expect("client updated");
+		let data = exchange_code(
+			&http_client,
+			&client,
+			&secret,
+			&user,
+			"offline_access calendar",
+		)
+		.await;
+		calendar.delete(&db).await.expect("scope deleted");
+		let (status, data) = refresh(&data["refresh_token"]).await;
+		assert_eq!(status, Status::Ok);
+		assert_eq!(data["scope"], "offline_access");
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn discovery_documents() {
+	common::as_visitor(async move |http_client, db| {
+		Scope::create(
+			NewScope {
+				name: "calendar".into(),
+				description: "See your calendar".into(),
+			},
+			&db,
+		)
+		.await
+		.expect("scope");
+
+		for path in [
+			"/.well-known/openid-configuration",
+			"/.well-known/oauth-authorization-server",
+		] {
+			let response = http_client.get(path).dispatch().await;
+			assert_eq!(response.status(), Status::Ok);
+
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+
+			let issuer = data["issuer"].as_str().expect("issuer");
+			assert_eq!(
+				data["token_endpoint"],
+				format!("{}/oauth/token", issuer)
+			);
+			assert_eq!(data["jwks_uri"], format!("{}/oauth/jwks", issuer));
+			assert_eq!(
+				data["end_session_endpoint"],
+				format!("{}/oauth/logout", issuer)
+			);
+			assert_eq!(data["backchannel_logout_supported"], true);
+			assert_eq!(
+				data["authorization_response_iss_parameter_supported"],
+				true
+			);
+			assert_eq!(
+				data["response_modes_supported"],
+				serde_json::json!(["query", "fragment", "form_post"])
+			);
+			assert_eq!(
+				data["scopes_supported"],
+				serde_json::json!([
+					"openid",
+					"profile",
+					"email",
+					"roles",
+					"offline_access",
+					"calendar"
+				])
+			);
+			assert!(
+				data["id_token_signing_alg_values_supported"]
+					.as_array()
+					.expect("signing algorithms")
+					.contains(&Value::from("ES384"))
+			);
+		}
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn revoke_tokens() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
+
+		let token_store = http_client
+			.rocket()
+			.state::<Box<dyn TokenStore<UserToken>>>()
+			.expect("should have token store");
+
+		let request_tokens = async || {
+			let authorization_code = token_store
+				.create_token(
+					UserToken {
+						scope: Some(String::from("offline_access")),
+						..user_token(&user, &client, REDIRECT_URI)
+					},
+					&db,
+				)
+				.await
+				.expect("authorization code");
+			let response = http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(format!(
+					"grant_type=authorization_code&code={}&redirect_uri={}&\
+					 client_id={}&client_secret={}",
+					authorization_code, REDIRECT_URI, CLIENT_ID, secret
+				))
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::Ok);
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+			(
+				String::from(data["access_token"].as_str().unwrap()),
+				String::from(data["refresh_token"].as_str().unwrap()),
+			)
+		};
+
+		let revoke = async |token: &str| {
+			let credentials =
+				BASE64_STANDARD.encode(format!("{}:{}", CLIENT_ID, secret));
+			http_client
+				.post("/oauth/revoke")
+				.header(ContentType::Form)
+				.header(Header::new(
+					"Authorization",
+					format!("Basic {}", credentials),
+				))
+				.body(format!("token={}", url(token)))
+				.dispatch()
+				.await
+				.status()
+		};
+
+		let access_token_status = async |token: &str| {
+			http_client
+				.get("/current_user")
+				.header(Accept::JSON)
+				.header(Header::new(
+					"Authorization",
+					format!("Bearer {}", token),
+				))
+				.dispatch()
+				.await
+				.status()
+		};
+
+		let refresh_status = async |token: &str| {
+			http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(format!(
+					"grant_type=refresh_token&refresh_token={}&client_id={}&\
+					 client_secret={}",
+					url(token),
+					CLIENT_ID,
+					secret
+				))
+				.dispatch()
+				.await
+				.status()
+		};
+
+		// Revoking an access token also revokes its refresh token
+		let (access_token, refresh_token) = request_tokens().await;
+		assert_eq!(access_token_status(&access_token).await, Status::Ok);
+		assert_eq!(revoke(&access_token).await, Status::Ok);
+		assert_eq!(
+			access_token_status(&access_token).await,
+			Status::Unauthorized
+		);
+		assert_eq!(refresh_status(&refresh_token).await, Status::BadRequest);
+
+		// Revoking a refresh token
+		let (_, refresh_token) = request_tokens().await;
+		assert_eq!(revoke(&refresh_token).await, Status::Ok);
+		assert_eq!(refresh_status(&refresh_token).await, Status::BadRequest);
+
+		// Unknown tokens are ignored
+		assert_eq!(revoke("unknown").await, Status::Ok);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn introspect_tokens() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (client, secret) = create_client(&db, CLIENT_ID).await;
+		let role = create_role(&db, "role", Some(client.id)).await;
+		role.add_user(user.id, &db).await.expect("add user to role");
+
+		let token_store = http_client
+			.rocket()
+			.state::<Box<dyn TokenStore<UserToken>>>()
+			.expect("should have token store");
+
+		let authorization_code = token_store
+			.create_token(
+				UserToken {
+					scope: Some(String::from("roles offline_access")),
+					..user_token(&user, &client, REDIRECT_URI)
+				},
+				&db,
+			)
+			.await
+			.expect("authorization code");
+
+		let response = http_client
+			.post("/oauth/token")
+			.header(ContentType::Form)
+			.body(format!(
+				"grant_type=authorization_code&code={}&redirect_uri={}&\
+				 client_id={}&client_secret={}",
+				authorization_code, REDIRECT_URI, CLIENT_ID, secret
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let tokens: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+
+		let introspect = async |token: &str| {
+			let response = http_client
+				.post("/oauth/introspect")
+				.header(ContentType::Form)
+				.body(format!(
+					"token={}&client_id={}&client_secret={}",
+					url(token),
+					CLIENT_ID,
+					secret
+				))
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::Ok);
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+			data
+		};
+
+		let data = introspect(tokens["access_token"].as_str().unwrap()).await;
+		assert_eq!(data["active"], true);
+		assert_eq!(data["scope"], "roles offline_access");
+		assert_eq!(data["client_id"], CLIENT_ID);
+		assert_eq!(data["username"], USER_USERNAME);
+		assert_eq!(data["sub"], user.id.to_string());
+		assert!(data["exp"].is_number());
+		assert!(data["iat"].is_number());
+		assert_eq!(data["roles"], Value::Array(vec!["role".into()]));
+
+		let data = introspect(tokens["refresh_token"].as_str().unwrap()).await;
+		assert_eq!(data["active"], true);
+		assert_eq!(data["token_type"], "refresh_token");
+
+		let data = introspect("unknown").await;
+		assert_eq!(data["active"], false);
+		assert_eq!(data.get("scope"), None);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn userinfo() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		let role = create_role(&db, "role", Some(client.id)).await;
+		role.add_user(user.id, &db).await.expect("add user to role");
+
+		let userinfo = async |token: &Value, post: bool| {
+			let authorization = Header::new(
+				"Authorization",
+				format!("Bearer {}", token.as_str().expect("access token")),
+			);
+			let request = if post {
+				http_client.post("/oauth/userinfo")
+			} else {
+				http_client.get("/oauth/userinfo")
+			};
+			request.header(authorization).dispatch().await
+		};
+
+		// Only the subject without any scopes
+		let tokens =
+			exchange_code(&http_client, &client, &secret, &user, "openid")
+				.await;
+		let response = userinfo(&tokens["access_token"], false).await;
+		assert_eq!(response.status(), Status::Ok);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["sub"], user.id.to_string());
+		assert_eq!(data.get("preferred_username"), None);
+		assert_eq!(data.get("email"), None);
+		assert_eq!(data.get("roles"), None);
+
+		let tokens = exchange_code(
+			&http_client,
+			&client,
+			&secret,
+			&user,
+			"openid profile email roles",
+		)
+		.await;
+		let response = userinfo(&tokens["access_token"], true).await;
+		assert_eq!(response.status(), Status::Ok);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["preferred_username"], USER_USERNAME);
+		assert_eq!(data["name"], user.full_name);
+		assert_eq!(data["email"], format!("{}@zeus.ugent.be", USER_USERNAME));
+		assert_eq!(data["email_verified"], true);
+		assert_eq!(data["roles"], Value::Array(vec!["role".into()]));
+
+		// Signed responses
+		client.userinfo_signed = true;
+		let client = client.update(&db).await.expect("client updated");
+		let response = userinfo(&tokens["access_token"], false).await;
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(
+			response.content_type(),
+			Some(ContentType::new("application", "jwt"))
+		);
+		let jwt = response.into_string().await.expect("response body");
+
+		let response = http_client.get("/oauth/jwks").dispatch().await;
+		let jwk_set: JwkSet = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		let mut validation = Validation::new(jsonwebtoken::Algorithm::ES384);
+		validation.set_audience(&[&client.name]);
+		validation.set_required_spec_claims(&["aud", "iss"]);
+		let claims = jsonwebtoken::decode::<Value>(
+			&jwt,
+			&DecodingKey::from_jwk(jwk_set.keys.first().unwrap()).unwrap(),
+			&validation,
+		)
+		.expect("signed userinfo")
+		.claims;
+		assert_eq!(claims["preferred_username"], USER_USERNAME);
+
+		// Without a valid access token
+		let response = userinfo(&Value::from("invalid"), false).await;
+		assert_eq!(response.status(), Status::Unauthorized);
+
+		// Without the openid scope
+		let tokens =
+			exchange_code(&http_client, &client, &secret, &user, "profile")
+				.await;
+		let response = userinfo(&tokens["access_token"], false).await;
+		assert_eq!(response.status(), Status::Forbidden);
+		assert_eq!(
+			response.headers().get_one("WWW-Authenticate"),
+			Some("Bearer error=\"insufficient_scope\", scope=\"openid\"")
+		);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["error"], "insufficient_scope");
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn jwt_access_tokens() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.jwt_access_tokens = true;
+		let client = client.update(&db).await.expect("client updated");
+		let role = create_role(&db, "role", Some(client.id)).await;
+		role.add_user(user.id, &db).await.expect("add user to role");
+
+		let tokens = exchange_code(
+			&http_client,
+			&client,
+			&secret,
+			&user,
+			"openid roles",
+		)
+		.await;
+		let access_token = tokens["access_token"].as_str().unwrap();
+
+		let response = http_client.get("/oauth/jwks").dispatch().await;
+		let jwk_set: JwkSet = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+
+		let header =
+			jsonwebtoken::decode_header(access_token).expect("jwt header");
+		assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+
+		let mut validation = Validation::new(jsonwebtoken::Algorithm::ES384);
+		validation.set_audience(&["http://localhost:8000"]);
+		let claims = jsonwebtoken::decode::<Value>(
+			access_token,
+			&DecodingKey::from_jwk(jwk_set.keys.first().unwrap()).unwrap(),
+			&validation,
+		)
+		.expect("access token")
+		.claims;
+		assert_eq!(claims["sub"], user.id.to_string());
+		assert_eq!(claims["client_id"], CLIENT_ID);
+		assert_eq!(claims["scope"], "openid roles");
+		assert_eq!(claims["roles"], Value::Array(vec!["role".into()]));
+		assert!(claims["exp"].is_number());
+
+		// The token doesn't reveal the key of the session behind it
+		let session = Session::find_by_id(
+			claims["jti"].as_str().unwrap().parse().expect("session id"),
+			&db,
+		)
+		.await
+		.expect("session");
+		assert_ne!(session.key.as_deref(), claims["jti"].as_str());
+		assert_ne!(session.key.as_deref(), Some(access_token));
+
+		// The token is also accepted by zauth itself, until it is revoked
+		let userinfo_status = async || {
+			http_client
+				.get("/oauth/userinfo")
+				.header(Header::new(
+					"Authorization",
+					format!("Bearer {}", access_token),
+				))
+				.dispatch()
+				.await
+				.status()
+		};
+		assert_eq!(userinfo_status().await, Status::Ok);
+
+		let response = http_client
+			.post("/oauth/revoke")
+			.header(ContentType::Form)
+			.body(format!(
+				"token={}&client_id={}&client_secret={}",
+				url(access_token),
+				CLIENT_ID,
+				secret
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(userinfo_status().await, Status::Unauthorized);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn device_flow() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (_, secret) = create_client(&db, CLIENT_ID).await;
+
+		let start = async || {
+			let response = http_client
+				.post("/oauth/device_authorization")
+				.header(ContentType::Form)
+				.body(format!(
+					"client_id={}&client_secret={}&scope=openid",
+					CLIENT_ID, secret
+				))
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::Ok);
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+			data
+		};
+
+		let poll = async |device_code: &Value| {
+			let response = http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(format!(
+					"grant_type={}&device_code={}&client_id={}&\
+					 client_secret={}",
+					url("urn:ietf:params:oauth:grant-type:device_code"),
+					url(device_code.as_str().expect("device code")),
+					CLIENT_ID,
+					secret
+				))
+				.dispatch()
+				.await;
+			let status = response.status();
+			let data: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+			(status, data)
+		};
+
+		let data = start().await;
+		let user_code = data["user_code"].as_str().expect("user code");
+		assert!(data["verification_uri"].is_string());
+		assert!(data["expires_in"].is_number());
+
+		let (status, error) = poll(&data["device_code"]).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(error["error"], "authorization_pending");
+
+		// The user has to log in before entering the code
+		let response = http_client
+			.get(format!("/oauth/device?user_code={}", url(user_code)))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let response = http_client
+			.post("/login")
+			.body(format!(
+				"username={}&password={}",
+				url(&user.username),
+				url(USER_PASSWORD)
+			))
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response
+			.headers()
+			.get_one("Location")
+			.expect("Location header");
+		assert!(location.starts_with("/oauth/device"));
+
+		let response = http_client
+			.post("/oauth/device")
+			.header(ContentType::Form)
+			.body("user_code=BCDF-GHJK")
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let body = response.into_string().await.expect("response body");
+		assert!(body.contains("invalid or has expired"));
+
+		// Codes are accepted in lowercase and without dash
+		let typed_code = user_code.replace('-', "").to_lowercase();
+		let response = http_client
+			.post("/oauth/device")
+			.header(ContentType::Form)
+			.body(format!("user_code={}", typed_code))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+
+		let response = http_client
+			.post("/oauth/device/grant")
+			.header(ContentType::Form)
+			.body("grant=true")
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+
+		let (status, tokens) = poll(&data["device_code"]).await;
+		assert_eq!(status, Status::Ok);
+		assert!(tokens["access_token"].is_string());
+		assert!(tokens["id_token"].is_string());
+
+		// A device code can only be used once
+		let (status, error) = poll(&data["device_code"]).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(error["error"], "invalid_grant");
+
+		// The user can also deny access
+		let data = start().await;
+		let response = http_client
+			.post("/oauth/device")
+			.header(ContentType::Form)
+			.body(format!(
+				"user_code={}",
+				url(data["user_code"].as_str().unwrap())
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let response = http_client
+			.post("/oauth/device/grant")
+			.header(ContentType::Form)
+			.body("grant=false")
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+
+		let (status, error) = poll(&data["device_code"]).await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(error["error"], "access_denied");
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn remembered_consent() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (_, secret) = create_client(&db, CLIENT_ID).await;
+
+		let authorize_url = |scope: &str| {
+			format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}&state={}&scope={}",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+				url(CLIENT_STATE),
+				url(scope)
+			)
+		};
+
+		let login = async || {
+			let response = http_client
+				.post("/login")
+				.body(format!(
+					"username={}&password={}",
+					url(&user.username),
+					url(USER_PASSWORD),
+				))
+				.header(ContentType::Form)
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::SeeOther);
+		};
+
+		// The first time, the user has to grant access
+		let data = get_token(
+			authorize_url("openid offline_access"),
+			&http_client,
+			&secret,
+			&user,
+		)
+		.await;
+		let access_token = String::from(data["access_token"].as_str().unwrap());
+		let refresh_token =
+			String::from(data["refresh_token"].as_str().unwrap());
+
+		login().await;
+
+		let grant_response = async |scope: &str| {
+			let response =
+				http_client.get(authorize_url(scope)).dispatch().await;
+			assert_eq!(response.status(), Status::Ok);
+			let response = http_client
+				.post("/oauth/authorize")
+				.body("authorized=true")
+				.header(ContentType::Form)
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::SeeOther);
+			http_client.get("/oauth/grant").dispatch().await
+		};
+
+		// Asking for the same or fewer scopes skips the grant page
+		for scope in ["offline_access openid", "openid"] {
+			let response = grant_response(scope).await;
+			assert_eq!(response.status(), Status::SeeOther);
+			let location = response
+				.headers()
+				.get_one("Location")
+				.expect("Location header");
+			assert!(location.starts_with(REDIRECT_URI));
+			assert!(get_param("code", &location.to_string()).is_some());
+		}
+
+		// Asking for more scopes shows the grant page again
+		let response = grant_response("openid email").await;
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::HTML));
+
+		let response = http_client
+			.get("/consents")
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let applications: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		let applications = applications.as_array().expect("array");
+		assert_eq!(applications.len(), 1);
+		assert_eq!(applications[0]["client_name"], CLIENT_ID);
+		assert_eq!(applications[0]["scope"], "offline_access openid");
+
+		// Revoking the consent logs the user out of the client
+		let response = http_client
+			.delete(format!("/consents/{}", applications[0]["id"]))
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::NoContent);
+
+		let response = http_client.post("/logout").dispatch().await;
+		assert_eq!(response.status(), Status::SeeOther);
+
+		let response = http_client
+			.get("/current_user")
+			.header(Accept::JSON)
+			.header(Header::new(
+				"Authorization",
+				format!("Bearer {}", access_token),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+
+		let response = http_client
+			.post("/oauth/token")
+			.header(ContentType::Form)
+			.body(format!(
+				"grant_type=refresh_token&refresh_token={}&client_id={}&\
+				 client_secret={}",
+				url(&refresh_token),
+				CLIENT_ID,
+				secret
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::BadRequest);
+
+		// And the user has to grant access again
+		login().await;
+		let response = grant_response("openid").await;
+		assert_eq!(response.status(), Status::Ok);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn allowed_scopes() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.allowed_scopes = String::from("openid profile");
+		client.update(&db).await.expect("client updated");
+
+		let authorize_url = |scope: &str| {
+			format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}&state={}&scope={}",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+				url(CLIENT_STATE),
+				url(scope)
+			)
+		};
+
+		// Scopes that aren't registered are rejected
+		let response = http_client
+			.get(authorize_url("openid nonsense"))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response
+			.headers()
+			.get_one("Location")
+			.expect("Location header")
+			.to_string();
+		assert!(location.starts_with(REDIRECT_URI));
+		assert_eq!(
+			get_param("error", &location).as_deref(),
+			Some("invalid_scope")
+		);
+
+		// Scopes the client isn't allowed to have are left out
+		let response = http_client
+			.post("/login")
+			.body(format!(
+				"username={}&password={}",
+				url(&user.username),
+				url(USER_PASSWORD),
+			))
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+
+		let response = http_client
+			.get(authorize_url("openid email"))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let response = http_client.get("/oauth/grant").dispatch().await;
+		assert_eq!(response.status(), Status::Ok);
+		let grant_page = response.into_string().await.expect("response body");
+		assert!(grant_page.contains("Know who you are"));
+		assert!(!grant_page.contains("See your email address"));
+
+		let response = http_client
+			.post("/oauth/grant")
+			.body("grant=true")
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response
+			.headers()
+			.get_one("Location")
+			.expect("Location header")
+			.to_string();
+		let code = get_param("code", &location).expect("authorization code");
+
+		let response = http_client
+			.post("/oauth/token")
+			.header(ContentType::Form)
+			.body(format!(
+				"grant_type=authorization_code&code={}&redirect_uri={}&\
+				 client_id={}&client_secret={}",
+				code, REDIRECT_URI, CLIENT_ID, secret
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["scope"], "openid");
+
+		// The same goes for the client credentials grant
+		let response = http_client
+			.post("/oauth/token")
+			.header(ContentType::Form)
+			.body(format!(
+				"grant_type=client_credentials&client_id={}&client_secret={}&\
+				 scope={}",
+				CLIENT_ID,
+				secret,
+				url("openid roles")
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["scope"], "openid");
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn prompt_parameters() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		create_client(&db, CLIENT_ID).await;
+
+		let authorize_url = |params: &str| {
+			format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}&state={}&scope=openid&{}",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+				url(CLIENT_STATE),
+				params
+			)
+		};
+		let silent_error = async |params: &str| {
+			let response =
+				http_client.get(authorize_url(params)).dispatch().await;
+			assert_eq!(response.status(), Status::SeeOther);
+			let location = response
+				.headers()
+				.get_one("Location")
+				.expect("Location header")
+				.to_string();
+			assert!(location.starts_with(REDIRECT_URI));
+			get_param("error", &location)
+		};
+		let login = async || {
+			let response = http_client
+				.post("/login")
+				.body(format!(
+					"username={}&password={}",
+					url(&user.username),
+					url(USER_PASSWORD),
+				))
+				.header(ContentType::Form)
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::SeeOther);
+		};
+		let grant_page_status = async |params: &str| {
+			let response =
+				http_client.get(authorize_url(params)).dispatch().await;
+			assert_eq!(response.status(), Status::Ok);
+			let response = http_client
+				.post("/oauth/authorize")
+				.body("authorized=true")
+				.header(ContentType::Form)
+				.dispatch()
+				.await;
+			assert_eq!(response.status(), Status::SeeOther);
+			http_client.get("/oauth/grant").dispatch().await.status()
+		};
+
+		// prompt=none can't be combined with other values
+		assert_eq!(
+			silent_error("prompt=none+login").await.as_deref(),
+			Some("invalid_request")
+		);
+
+		assert_eq!(
+			silent_error("prompt=none").await.as_deref(),
+			Some("login_required")
+		);
+		login().await;
+		assert_eq!(
+			silent_error("prompt=none").await.as_deref(),
+			Some("consent_required")
+		);
+
+		// Once the user granted access, no interaction is needed
+		assert_eq!(grant_page_status("").await, Status::Ok);
+		let response = http_client
+			.post("/oauth/grant")
+			.body("grant=true")
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let response = http_client
+			.get(authorize_url("prompt=none"))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response
+			.headers()
+			.get_one("Location")
+			.expect("Location header")
+			.to_string();
+		assert!(get_param("code", &location).is_some());
+
+		// Unless the login is older than max_age
+		assert_eq!(
+			silent_error("prompt=none&max_age=0").await.as_deref(),
+			Some("login_required")
+		);
+
+		// prompt=consent always shows the grant page
+		assert_eq!(grant_page_status("").await, Status::SeeOther);
+		assert_eq!(grant_page_status("prompt=consent").await, Status::Ok);
+
+		// prompt=login makes the user log in again
+		let response = http_client
+			.get(authorize_url("prompt=login&login_hint=batman"))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let response = http_client
+			.post("/oauth/authorize")
+			.body("authorized=true")
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		let login_url = "/login?login_hint=batman&reauthenticate=true";
+		assert_eq!(response.headers().get_one("Location"), Some(login_url));
+		let response = http_client.get(login_url).dispatch().await;
+		assert_eq!(response.status(), Status::Ok);
+		let login_page = response.into_string().await.expect("response body");
+		assert!(login_page.contains("value=\"batman\""));
+
+		// The user stays logged in, but can't skip logging in again
+		let response = http_client.get("/oauth/grant").dispatch().await;
+		assert_eq!(response.headers().get_one("Location"), Some(login_url));
+		let response = http_client
+			.post("/oauth/grant")
+			.body("grant=true")
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.headers().get_one("Location"), Some(login_url));
+
+		login().await;
+		let response = http_client.get("/oauth/grant").dispatch().await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response
+			.headers()
+			.get_one("Location")
+			.expect("Location header")
+			.to_string();
+		assert!(get_param("code", &location).is_some());
+
+		// Going straight to the grant page doesn't skip logging in again
+		for params in ["prompt=login&login_hint=batman", "max_age=0"] {
+			let response =
+				http_client.get(authorize_url(params)).dispatch().await;
+			assert_eq!(response.status(), Status::Ok);
+			let response = http_client.get("/oauth/grant").dispatch().await;
+			assert_eq!(response.status(), Status::SeeOther);
+			let location = response
+				.headers()
+				.get_one("Location")
+				.expect("Location header")
+				.to_string();
+			assert!(location.starts_with("/login?"));
+			assert!(location.contains("reauthenticate=true"));
+		}
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn rp_initiated_logout() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.post_logout_redirect_uri_list =
+			String::from("https://example.com/logged_out");
+		let client = client.update(&db).await.expect("client updated");
+		let (other_client, other_client_secret) =
+			create_client(&db, "other").await;
+
+		let data =
+			exchange_code(&http_client, &client, &secret, &user, "openid")
+				.await;
+		let id_token = data["id_token"].as_str().expect("id token");
+		let access_token = data["access_token"].as_str().expect("access token");
+		let other_id_token = exchange_code(
+			&http_client,
+			&other_client,
+			&other_client_secret,
+			&user,
+			"openid",
+		)
+		.await["id_token"]
+			.as_str()
+			.expect("id token")
+			.to_string();
+
+		let response = http_client
+			.post("/login")
+			.body(format!(
+				"username={}&password={}",
+				url(&user.username),
+				url(USER_PASSWORD),
+			))
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+
+		// The post logout redirect URI has to be registered for the client
+		let response = http_client
+			.get(format!(
+				"/oauth/logout?id_token_hint={}&post_logout_redirect_uri={}",
+				id_token,
+				url("https://example.com/elsewhere")
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+		let response = http_client
+			.get(format!(
+				"/oauth/logout?id_token_hint={}&post_logout_redirect_uri={}",
+				other_id_token,
+				url("https://example.com/logged_out")
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+
+		// The hint and the client id should agree
+		let response = http_client
+			.get(format!(
+				"/oauth/logout?id_token_hint={}&client_id=other",
+				id_token
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::BadRequest);
+
+		let logout_params = format!(
+			"id_token_hint={}&post_logout_redirect_uri={}&state={}",
+			id_token,
+			url("https://example.com/logged_out"),
+			url(CLIENT_STATE)
+		);
+
+		// The user is asked to confirm before they are logged out
+		let response = http_client
+			.get(format!("/oauth/logout?{}", logout_params))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::HTML));
+		let response = http_client
+			.post("/oauth/logout")
+			.body(logout_params.clone())
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::HTML));
+
+		let response = http_client
+			.post("/oauth/logout")
+			.body(format!(
+				"{}&confirmed=true&logout_client=true",
+				logout_params
+			))
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		assert_eq!(
+			response.headers().get_one("Location"),
+			Some(
+				format!(
+					"https://example.com/logged_out?state={}",
+					url(CLIENT_STATE)
+				)
+				.as_str()
+			)
+		);
+
+		let response = http_client
+			.get("/current_user")
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+		let response = http_client
+			.get("/current_user")
+			.header(Accept::JSON)
+			.header(Header::new(
+				"Authorization",
+				format!("Bearer {}", access_token),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Unauthorized);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn authorization_response_modes() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let redirect_uri = "https://example.com/callback?app=zauth";
+		let (mut client, _) = create_client(&db, CLIENT_ID).await;
+		client.needs_grant = false;
+		client.redirect_uri_list = String::from(redirect_uri);
+		client.update(&db).await.expect("client updated");
+
+		let authorize = async |params: &str| {
+			http_client
+				.get(format!(
+					"/oauth/authorize?redirect_uri={}&client_id={}&state={}&{}",
+					url(redirect_uri),
+					url(CLIENT_ID),
+					url(CLIENT_STATE),
+					params
+				))
+				.dispatch()
+				.await
+		};
+		let location_of = |response: &LocalResponse| {
+			response
+				.headers()
+				.get_one("Location")
+				.expect("Location header")
+				.to_string()
+		};
+
+		// Errors are sent back to the client, keeping the query of its
+		// redirect URI
+		let response = authorize("response_type=token").await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = location_of(&response);
+		assert!(location.starts_with(&format!("{}&", redirect_uri)));
+		assert_eq!(
+			get_param("error", &location).as_deref(),
+			Some("unsupported_response_type")
+		);
+		assert!(get_param("error_description", &location).is_some());
+		assert_eq!(get_param("state", &location), Some(url(CLIENT_STATE)));
+		assert_eq!(
+			get_param("iss", &location),
+			Some(url("http://localhost:8000"))
+		);
+
+		let response =
+			authorize("response_type=code&response_mode=telepathy").await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = location_of(&response);
+		assert!(location.starts_with(&format!("{}&", redirect_uri)));
+		assert_eq!(
+			get_param("error", &location).as_deref(),
+			Some("invalid_request")
+		);
+
+		let response = http_client
+			.post("/login")
+			.body(format!(
+				"username={}&password={}",
+				url(&user.username),
+				url(USER_PASSWORD),
+			))
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+
+		let response =
+			authorize("response_type=code&prompt=none&response_mode=fragment")
+				.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = location_of(&response);
+		assert!(location.starts_with(&format!("{}#code=", redirect_uri)));
+		assert!(location.contains("&state="));
+
+		let response =
+			authorize("response_type=code&prompt=none&response_mode=form_post")
+				.await;
+		assert_eq!(response.status(), Status::Ok);
+		assert_eq!(response.content_type(), Some(ContentType::HTML));
+		let body = response.into_string().await.expect("response body");
+		assert!(
+			body.contains(r#"action="https://example.com/callback?app=zauth""#)
+		);
+		assert!(body.contains(r#"name="code""#));
+		assert!(body.contains(r#"name="state""#));
+		assert!(body.contains(r#"name="iss""#));
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn loopback_and_pattern_redirect_uris() {
+	common::as_visitor(async move |http_client, db| {
+		let (mut client, _) = create_client(&db, CLIENT_ID).await;
+		client.redirect_uri_list = String::from(
+			"http://127.0.0.1/callback\n\
+			 http://[::1]:8080/callback\n\
+			 pattern:https://pr-*.preview.example.com/auth/callback",
+		);
+		client.update(&db).await.expect("client updated");
+
+		let authorize_status = async |redirect_uri: &str| {
+			http_client
+				.get(format!(
+					"/oauth/authorize?response_type=code&redirect_uri={}&\
+					 client_id={}&state={}",
+					url(redirect_uri),
+					url(CLIENT_ID),
+					url(CLIENT_STATE)
+				))
+				.dispatch()
+				.await
+				.status()
+		};
+
+		for accepted in [
+			"http://127.0.0.1/callback",
+			"http://127.0.0.1:51004/callback",
+			"http://[::1]/callback",
+			"http://[::1]:1234/callback",
+			"https://pr-42.preview.example.com/auth/callback",
+			"https://pr-fix-login.preview.example.com/auth/callback",
+		] {
+			assert_eq!(
+				authorize_status(accepted).await,
+				Status::Ok,
+				"{}",
+				accepted
+			);
+		}
+		for rejected in [
+			"http://127.0.0.1:51004/other",
+			"https://127.0.0.1:51004/callback",
+			"http://localhost:51004/callback",
+			"http://127.0.0.1:51004/callback?next=evil",
+			"https://pr-.preview.example.com/auth/callback",
+			"https://pr-1.evil.preview.example.com/auth/callback",
+			"https://pr-1.preview.example.com.evil.com/auth/callback",
+			"https://pr-1.preview.example.com/auth/callback/../other",
+			"https://pr-1.preview.example.com:8443/auth/callback",
+			"http://pr-1.preview.example.com/auth/callback",
+			"https://attacker@pr-1.preview.example.com/auth/callback",
+		] {
+			assert_eq!(
+				authorize_status(rejected).await,
+				Status::Unauthorized,
+				"{}",
+				rejected
+			);
+		}
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn backchannel_logout() {
+	// The first delivery fails, so the logout token has to be sent again
+	let (logout_uri, mut logout_requests) =
+		common::stub_receiver(vec![503, 200, 200]);
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.needs_grant = false;
+		client.backchannel_logout_uri = logout_uri;
+		let client = client.update(&db).await.expect("client updated");
+
+		let response = http_client.get("/oauth/jwks").dispatch().await;
+		let jwk_set: JwkSet = response.into_json().await.expect("jwks");
+		let decoding_key =
+			DecodingKey::from_jwk(jwk_set.keys.first().unwrap()).unwrap();
+		let mut validation = Validation::new(jsonwebtoken::Algorithm::ES384);
+		validation.set_audience(&[CLIENT_ID]);
+		validation.set_issuer(&["http://localhost:8000"]);
+		let decode = |token: &str| {
+			jsonwebtoken::decode::<Value>(token, &decoding_key, &validation)
+				.expect("valid token")
+				.claims
+		};
+		let mut next_logout_token = async || {
+			let body = rocket::tokio::time::timeout(
+				std::time::Duration::from_secs(10),
+				logout_requests.recv(),
+			)
+			.await
+			.expect("logout request")
+			.expect("logout request body");
+			body.strip_prefix("logout_token=")
+				.expect("logout token")
+				.to_string()
+		};
+
+		let response = http_client
+			.post("/login")
+			.body(format!(
+				"username={}&password={}",
+				url(&user.username),
+				url(USER_PASSWORD),
+			))
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+
+		let response = http_client
+			.get(format!(
+				"/oauth/authorize?response_type=code&redirect_uri={}&\
+				 client_id={}&scope=openid&prompt=none",
+				url(REDIRECT_URI),
+				url(CLIENT_ID),
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let location = response
+			.headers()
+			.get_one("Location")
+			.expect("Location header")
+			.to_string();
+		let code = get_param("code", &location).expect("authorization code");
+		let response = http_client
+			.post("/oauth/token")
+			.header(ContentType::Form)
+			.body(format!(
+				"grant_type=authorization_code&code={}&redirect_uri={}&\
+				 client_id={}&client_secret={}",
+				code,
+				url(REDIRECT_URI),
+				client.name,
+				secret
+			))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::Ok);
+		let data: Value = response.into_json().await.expect("token response");
+		let id_token = decode(data["id_token"].as_str().expect("id token"));
+		let sid = id_token["sid"].as_str().expect("session id").to_string();
+
+		let response = http_client.post("/logout").dispatch().await;
+		assert_eq!(response.status(), Status::SeeOther);
+
+		let logout_token = next_logout_token().await;
+		assert_eq!(next_logout_token().await, logout_token);
+		let header = jsonwebtoken::decode_header(&logout_token).unwrap();
+		assert_eq!(header.typ.as_deref(), Some("logout+jwt"));
+		let claims = decode(&logout_token);
+		assert_eq!(claims["sid"], sid);
+		assert_eq!(claims["sub"], user.id.to_string());
+		assert_eq!(claims["nonce"], Value::Null);
+		assert!(
+			claims["events"]
+				.get("http://schemas.openid.net/event/backchannel-logout")
+				.is_some()
+		);
+
+		// A logout token is no ID token
+		let response = http_client
+			.get(format!("/oauth/logout?id_token_hint={}", logout_token))
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::BadRequest);
+
+		// Disabling the user ends all of their sessions
+		let mut admin = User::create(
+			NewUser {
+				username: String::from("admin"),
+				password: String::from("password"),
+				full_name: String::from("admin name"),
+				email: String::from("admin@domain.tld"),
+				ssh_key: None,
+				not_a_robot: true,
+			},
+			common::BCRYPT_COST,
+			&db,
+		)
+		.await
+		.expect("admin");
+		admin.admin = true;
+		admin.update(&db).await.expect("admin updated");
+		let response = http_client
+			.post("/login")
+			.body("username=admin&password=password")
+			.header(ContentType::Form)
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::SeeOther);
+		let response = http_client
+			.post(format!("/users/{}/change_state", user.username))
+			.header(ContentType::Form)
+			.header(Accept::JSON)
+			.body("state=Disabled")
+			.dispatch()
+			.await;
+		assert_eq!(response.status(), Status::NoContent);
+
+		let claims = decode(&next_logout_token().await);
+		assert_eq!(claims["sub"], user.id.to_string());
+		assert_eq!(claims["sid"], Value::Null);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn token_exchange() {
+	common::as_visitor(async move |http_client, db| {
+		let user = create_user(&db).await;
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.token_exchange_audiences = String::from("api");
+		let client = client.update(&db).await.expect("client updated");
+		let (mut api, api_secret) = create_client(&db, "api").await;
+		api.allowed_scopes = String::from("openid profile");
+		let api = api.update(&db).await.expect("client updated");
+		let (other, other_secret) = create_client(&db, "other").await;
+
+		let tokens = exchange_code(
+			&http_client,
+			&client,
+			&secret,
+			&user,
+			"openid profile roles",
+		)
+		.await;
+		let subject_token = tokens["access_token"].as_str().unwrap();
+
+		let exchange = async |client: &Client, secret: &str, params: &str| {
+			let response = http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(format!(
+					"grant_type={}&client_id={}&client_secret={}&\
+					 subject_token={}&subject_token_type={}&{}",
+					url("urn:ietf:params:oauth:grant-type:token-exchange"),
+					client.name,
+					secret,
+					url(subject_token),
+					url("urn:ietf:params:oauth:token-type:access_token"),
+					params
+				))
+				.dispatch()
+				.await;
+			let status = response.status();
+			let body: Value = serde_json::from_str(
+				&response.into_string().await.expect("response body"),
+			)
+			.expect("response json values");
+			(status, body)
+		};
+
+		let (status, body) = exchange(&client, &secret, "audience=other").await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body["error"], "invalid_target");
+
+		let (status, body) =
+			exchange(&client, &secret, "audience=api&scope=openid+email").await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body["error"], "invalid_scope");
+
+		// Only the client the subject token was issued to can exchange it
+		let (status, body) =
+			exchange(&other, &other_secret, "audience=api").await;
+		assert_eq!(status, Status::BadRequest);
+		assert_eq!(body["error"], "invalid_grant");
+
+		let (status, body) =
+			exchange(&client, &secret, "resource=api&scope=profile+roles")
+				.await;
+		assert_eq!(status, Status::Ok);
+		assert_eq!(
+			body["issued_token_type"],
+			"urn:ietf:params:oauth:token-type:access_token"
+		);
+		assert_eq!(body["token_type"], "bearer");
+		assert_eq!(body["scope"], "profile");
+		assert_eq!(body.get("refresh_token"), None);
+
+		let response = http_client
+			.post("/oauth/introspect")
+			.header(ContentType::Form)
+			.body(format!(
+				"token={}&client_id={}&client_secret={}",
+				url(body["access_token"].as_str().unwrap()),
+				api.name,
+				api_secret
+			))
+			.dispatch()
+			.await;
+		let data: Value = serde_json::from_str(
+			&response.into_string().await.expect("response body"),
+		)
+		.expect("response json values");
+		assert_eq!(data["active"], true);
+		assert_eq!(data["client_id"], "api");
+		assert_eq!(data["sub"], user.id.to_string());
+
+		// The exchanged token expires no later than the subject token
+		let mut subject_session =
+			Session::find_by_key(subject_token.into(), &db)
+				.await
+				.expect("subject session");
+		subject_session.expires_at =
+			Utc::now().naive_utc() + chrono::Duration::seconds(60);
+		let subject_session =
+			subject_session.update(&db).await.expect("session updated");
+
+		let (status, body) = exchange(&client, &secret, "audience=api").await;
+		assert_eq!(status, Status::Ok);
+		assert!(body["expires_in"].as_i64().unwrap() <= 60);
+		let exchanged = Session::find_by_key(
+			body["access_token"].as_str().unwrap().into(),
+			&db,
+		)
+		.await
+		.expect("exchanged session");
+		assert_eq!(exchanged.expires_at, subject_session.expires_at);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn client_assertions() {
+	common::as_visitor(async move |http_client, db| {
+		// Sign the assertions of the client with a key of its own
+		let key_file = generate_key_file();
+		let mut config = common::config();
+		config.ec_private_key = key_file.path().to_str().unwrap().to_string();
+		let client_keys = JWTBuilder::new(&config).expect("jwt builder");
+
+		let (mut client, secret) = create_client(&db, CLIENT_ID).await;
+		client.jwks = serde_json::to_string(&client_keys.jwks()).unwrap();
+		client.update(&db).await.expect("client updated");
+
+		let claims = |jti: &str, aud: &str| {
+			serde_json::json!({
+				"iss": CLIENT_ID,
+				"sub": CLIENT_ID,
+				"aud": aud,
+				"jti": jti,
+				"exp": Utc::now().timestamp() + 60,
+			})
+		};
+		let token_endpoint = "http://localhost:8000/oauth/token";
+		let client_credentials = async |assertion: &str| {
+			http_client
+				.post("/oauth/token")
+				.header(ContentType::Form)
+				.body(format!(
+					"grant_type=client_credentials&client_assertion_type={}&\
+					 client_assertion={}",
+					url(
+						"urn:ietf:params:oauth:client-assertion-type:jwt-bearer"
+					),
+					url(assertion)
+				))
+				.dispatch()
+				.await
+				.status()
+		};
+
+		let assertion = client_keys
+			.encode(&claims("first", token_endpoint))
+			.unwrap();
+		assert_eq!(client_credentials(&assertion).await, Status::Ok);
+		// Assertions can only be used once
+		assert_eq!(client_credentials(&assertion).await, Status::Unauthorized);
+
+		let assertion = client_keys
+			.encode(&claims("other", "https://example.com/oauth/token"))
+			.unwrap();
+		assert_eq!(client_credentials(&assertion).await, Status::Unauthorized);
+
+		// Signed with a key that is not registered for the client
+		let assertion = JWTBuilder::new(&common::config())
+			.unwrap()
+			.encode(&claims("unknown", token_endpoint))
+			.unwrap();
+		assert_eq!(client_credentials(&assertion).await, Status::Unauthorized);
+
+		let introspect = async |assertion: &str| {
+			http_client
+				.post("/oauth/introspect")
+				.header(ContentType::Form)
+				.body(format!(
+					"token=unknown&client_id={}&client_assertion_type={}&\
+					 client_assertion={}",
+					CLIENT_ID,
+					url(
+						"urn:ietf:params:oauth:client-assertion-type:jwt-bearer"
+					),
+					url(assertion)
+				))
+				.dispatch()
+				.await
+				.status()
+		};
+		let introspect_endpoint = "http://localhost:8000/oauth/introspect";
+		let assertion = client_keys
+			.encode(&claims("introspect", introspect_endpoint))
+			.unwrap();
+		assert_eq!(introspect(&assertion).await, Status::Ok);
+
+		// Secrets are only stored hashed, so client_secret_jwt assertions
+		// can't be verified unless the client opted in
+		let secret_assertion = |jti: &str, secret: &str| {
+			jsonwebtoken::encode(
+				&jsonwebtoken::Header::default(),
+				&claims(jti, introspect_endpoint),
+				&jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+			)
+			.unwrap()
+		};
+		let assertion = secret_assertion("hashed", &secret);
+		assert_eq!(introspect(&assertion).await, Status::Unauthorized);
+
+		// Secrets created after opting in are kept encrypted
+		let mut client = Client::find_by_name(CLIENT_ID.into(), &db)
+			.await
+			.expect("client");
+		client.secret_jwt_allowed = true;
+		let client = client.update(&db).await.expect("client updated");
+		let assertion = secret_assertion("old", &secret);
+		assert_eq!(introspect(&assertion).await, Status::Unauthorized);
+
+		let config = http_client.rocket().state::<Config>().unwrap();
+		let (_, secret) = ClientSecret::create(
+			&client,
+			NewClientSecret {
+				label: String::from("jwt"),
+				expires_in_days: None,
+			},
+			config,
+			&db,
+		)
+		.await
+		.expect("client secret created");
+		let assertion = secret_assertion("encrypted", &secret);
+		assert_eq!(introspect(&assertion).await, Status::Ok);
+
+		// Without a client_secret_key, there is no way to keep them
+		let result = ClientSecret::create(
+			&client,
+			NewClientSecret {
+				label: String::from("jwt"),
+				expires_in_days: None,
+			},
+			&common::config(),
+			&db,
+		)
+		.await;
+		assert!(result.is_err());
+	})
+	.await;
+}
+
+fn generate_key_file() -> tempfile::NamedTempFile {
+	let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+	let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+	let mut file = tempfile::NamedTempFile::new().unwrap();
+	file.write_all(&key.private_key_to_pem_pkcs8().unwrap())
+		.unwrap();
+	file
+}
+
+#[test]
+fn signing_key_rotation() {
+	let retired_key = generate_key_file();
+	let expired_key = generate_key_file();
+
+	let mut config = common::config();
+	config.ec_retired_keys = vec![
+		RetiredKey {
+			path: retired_key.path().to_str().unwrap().to_string(),
+			expires_at: Utc::now() + chrono::Duration::days(1),
+		},
+		RetiredKey {
+			path: expired_key.path().to_str().unwrap().to_string(),
+			expires_at: Utc::now() - chrono::Duration::days(1),
+		},
+	];
+	let jwt_builder = JWTBuilder::new(&config).expect("jwt builder");
+
+	// The active and the retired key are published, the expired one is not
+	let jwks = jwt_builder.jwks();
+	assert_eq!(jwks.keys.len(), 2);
+	let active_kid = jwks.keys[0].common.key_id.clone().expect("key id");
+	let retired_kid = jwks.keys[1].common.key_id.clone().expect("key id");
+	assert_ne!(active_kid, retired_kid);
+
+	// Tokens are signed with the active key
+	let token = jwt_builder
+		.encode(&serde_json::json!({"sub": "test"}))
+		.expect("token");
+	let header = jsonwebtoken::decode_header(&token).expect("header");
+	assert_eq!(header.kid, Some(active_kid));
+}
@@ -0,0 +1,110 @@
+use common::HttpClient;
+use rocket::http::{Accept, ContentType, Status};
+use zauth::models::{scope::Scope, user::User};
+
+mod common;
+
+#[rocket::async_test]
+async fn list_scopes_as_user() {
+	common::as_user(async move |http_client: HttpClient, _db, _user: User| {
+		let response = http_client.get("/scopes").dispatch().await;
+
+		assert_eq!(response.status(), Status::Forbidden);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn list_scopes_as_admin() {
+	common::as_admin(async move |http_client: HttpClient, _db, _user: User| {
+		let response = http_client
+			.get("/scopes")
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+
+		assert_eq!(response.status(), Status::Ok);
+
+		let scopes: Vec<Scope> = response.into_json().await.unwrap();
+		let names: Vec<&str> =
+			scopes.iter().map(|scope| scope.name.as_str()).collect();
+		assert_eq!(
+			names,
+			vec!["openid", "profile", "email", "roles", "offline_access"]
+		);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn create_scope_as_user() {
+	common::as_user(async move |http_client: HttpClient, _db, _user: User| {
+		let response = http_client
+			.post("/scopes")
+			.body("name=test&description=test_description")
+			.header(ContentType::Form)
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+
+		assert_eq!(response.status(), Status::Forbidden);
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn create_and_delete_scope() {
+	common::as_admin(async move |http_client: HttpClient, db, _user| {
+		let response = http_client
+			.post("/scopes")
+			.body("name=test&description=test_description")
+			.header(ContentType::Form)
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+
+		assert_eq!(response.status(), Status::Created);
+
+		let json: Scope = response.into_json().await.unwrap();
+		let created = Scope::find(json.id, &db).await.unwrap();
+		assert_eq!(created.name, "test");
+		assert_eq!(created.description, "test_description");
+
+		let response = http_client
+			.post("/scopes")
+			.body("name=test&description=again")
+			.header(ContentType::Form)
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+
+		assert_eq!(response.status(), Status::Conflict);
+
+		let response = http_client
+			.delete(format!("/scopes/{}", created.id))
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+
+		assert_eq!(response.status(), Status::NoContent);
+		assert!(Scope::find(created.id, &db).await.is_err());
+	})
+	.await;
+}
+
+#[rocket::async_test]
+async fn create_scope_with_space() {
+	common::as_admin(async move |http_client: HttpClient, db, _user| {
+		let response = http_client
+			.post("/scopes")
+			.body("name=two+words&description=test_description")
+			.header(ContentType::Form)
+			.header(Accept::JSON)
+			.dispatch()
+			.await;
+
+		assert_ne!(response.status(), Status::Created);
+		assert_eq!(Scope::all(&db).await.unwrap().len(), 5);
+	})
+	.await;
+}